@@ -0,0 +1,72 @@
+//! Mounts the shortener under `/links` inside another axum application,
+//! backed by the in-memory store.
+//!
+//! ```bash
+//! cargo run --example embed -- 127.0.0.1:3000
+//! curl -X POST localhost:3000/links/api/shorten -H 'Content-Type: application/json' \
+//!   -d '{"url": "https://example.com", "custom_code": "docs"}'
+//! curl -i localhost:3000/links/docs
+//! ```
+
+use std::{env, net::SocketAddr, sync::Arc};
+
+use axum::{Router, routing::get};
+use url_shortener::{
+    Shortener,
+    config::{Config, ConfigArgs},
+    db::MemoryStore,
+    models::CreateUrlRequest,
+    services::{qr_code, shorten},
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt().init();
+
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:3000".to_string());
+
+    // short links are built from base_url, so it carries the mount prefix
+    let mut config = Config::default();
+    config.server.base_url = format!("http://{}/links", address);
+
+    let shortener = Shortener::start(
+        Arc::new(config),
+        ConfigArgs::default(),
+        Arc::new(MemoryStore::new()),
+    )?;
+
+    // the services work on the store directly, without going through HTTP
+    let db = shortener.state().db().clone();
+    let request = CreateUrlRequest {
+        url: "https://github.com".to_string(),
+        custom_code: Some("gh".to_string()),
+        expires_at: None,
+        tags: vec!["seed".to_string()],
+        campaign: None,
+        owner: None,
+    };
+    let new_url = shorten::prepare_link(db.as_ref(), request, 6).await?;
+    let url = shorten::create_link(db.as_ref(), &new_url).await?;
+    let qr = qr_code::generate_qr_code(&format!("http://{}/links/{}", address, url.short_code))?;
+    println!(
+        "seeded /links/{}, QR code of {} bytes",
+        url.short_code,
+        qr.len()
+    );
+
+    let app = Router::new()
+        .route("/", get(|| async { "host application" }))
+        .nest("/links", shortener.router());
+
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    println!("listening on http://{}/links", address);
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
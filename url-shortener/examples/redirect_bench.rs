@@ -0,0 +1,97 @@
+//! Measures redirect throughput against a running server.
+//!
+//! Compare the cache on and off by starting the server twice, e.g.
+//!
+//! ```bash
+//! URL_CACHE_CAPACITY=0 RATE_LIMIT_REDIRECT_PER_MINUTE=100000000 cargo run --release
+//! cargo run --release --example redirect_bench -- http://127.0.0.1:8081 mylink 20000 32
+//!
+//! RATE_LIMIT_REDIRECT_PER_MINUTE=100000000 cargo run --release
+//! cargo run --release --example redirect_bench -- http://127.0.0.1:8081 mylink 20000 32
+//! ```
+
+use std::{
+    env,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Instant,
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let base_url = args
+        .next()
+        .unwrap_or_else(|| "http://127.0.0.1:8081".to_string());
+    let short_code = args.next().unwrap_or_else(|| "bench".to_string());
+    let requests: u64 = args
+        .next()
+        .map(|n| n.parse())
+        .transpose()?
+        .unwrap_or(10_000);
+    let concurrency: u64 = args.next().map(|n| n.parse()).transpose()?.unwrap_or(32);
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    // make sure the link exists, a conflict means it already does
+    client
+        .post(format!("{}/api/shorten", base_url))
+        .json(&serde_json::json!({ "url": "https://example.com", "custom_code": short_code }))
+        .send()
+        .await?;
+
+    let target = format!("{}/{}", base_url, short_code);
+    let remaining = Arc::new(AtomicU64::new(requests));
+    let failures = Arc::new(AtomicU64::new(0));
+    let started = Instant::now();
+
+    let workers: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let client = client.clone();
+            let target = target.clone();
+            let remaining = remaining.clone();
+            let failures = failures.clone();
+
+            tokio::spawn(async move {
+                while remaining
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                    .is_ok()
+                {
+                    match client.get(&target).send().await {
+                        Ok(response) if response.status().is_redirection() => {}
+                        _ => {
+                            failures.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.await?;
+    }
+
+    let elapsed = started.elapsed();
+    println!(
+        "{} redirects in {:.2?} ({:.0} req/s), {} failed",
+        requests,
+        elapsed,
+        requests as f64 / elapsed.as_secs_f64(),
+        failures.load(Ordering::Relaxed)
+    );
+
+    let cache_stats = client
+        .get(format!("{}/api/admin/url-cache", base_url))
+        .send()
+        .await?
+        .text()
+        .await?;
+    println!("cache: {}", cache_stats);
+
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Stand-in webhook endpoint that checks signatures and prints events.
+//!
+//! Every `fail_every`-th request is answered with a 500 to exercise retries.
+//!
+//! ```bash
+//! cargo run --example webhook_receiver -- 127.0.0.1:9000 <secret> 3
+//! curl -X POST localhost:8080/api/webhooks -H 'Content-Type: application/json' \
+//!   -d '{"url": "http://127.0.0.1:9000/hook", "events": ["link.created", "link.clicked"], "secret": "<secret>"}'
+//! ```
+
+use std::{
+    env,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use axum::{Router, body::Bytes, extract::State, http::HeaderMap, http::StatusCode, routing::post};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+struct Receiver {
+    secret: String,
+    fail_every: u64,
+    received: AtomicU64,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:9000".to_string());
+    let secret = args.next().ok_or_else(|| {
+        anyhow::anyhow!("usage: webhook_receiver <address> <secret> [fail_every]")
+    })?;
+    let fail_every: u64 = args.next().map(|n| n.parse()).transpose()?.unwrap_or(0);
+
+    let receiver = Arc::new(Receiver {
+        secret,
+        fail_every,
+        received: AtomicU64::new(0),
+    });
+
+    let app = Router::new()
+        .route("/hook", post(receive))
+        .with_state(receiver);
+
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    println!("listening on http://{}/hook", address);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+async fn receive(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> StatusCode {
+    let n = receiver.received.fetch_add(1, Ordering::Relaxed) + 1;
+
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    };
+    let event = header("x-webhook-event");
+    let timestamp = header("x-webhook-timestamp");
+    let signature = header("x-webhook-signature");
+
+    let Some(signature) = signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| hex::decode(hex).ok())
+    else {
+        println!("#{} {}: missing signature", n, event);
+        return StatusCode::UNAUTHORIZED;
+    };
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(receiver.secret.as_bytes())
+        .expect("HMAC accepts any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(&body);
+
+    if mac.verify_slice(&signature).is_err() {
+        println!("#{} {}: bad signature", n, event);
+        return StatusCode::UNAUTHORIZED;
+    }
+
+    if receiver.fail_every > 0 && n % receiver.fail_every == 0 {
+        println!("#{} {}: failing on purpose", n, event);
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    println!("#{} {}: {}", n, event, String::from_utf8_lossy(&body));
+    StatusCode::NO_CONTENT
+}
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS urls (
+    id TEXT PRIMARY KEY DEFAULT (replace(gen_random_uuid()::text, '-', '')),
+    original_url TEXT NOT NULL,
+    short_code TEXT UNIQUE NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT date_trunc('second', now()),
+    expires_at TIMESTAMPTZ,
+    click_count INTEGER NOT NULL DEFAULT 0
+);
+
+-- index for descending sorting
+CREATE INDEX IF NOT EXISTS idx_urls_created_at ON urls(created_at DESC);
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS clicks (
+    id TEXT PRIMARY KEY DEFAULT (replace(gen_random_uuid()::text, '-', '')),
+    url_id TEXT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    clicked_at TIMESTAMPTZ NOT NULL DEFAULT date_trunc('second', now()),
+    ip_address TEXT,
+    user_agent TEXT,
+    referer TEXT,
+    country TEXT,
+    city TEXT
+);
+
+-- Create indexes for analytics queries
+CREATE INDEX IF NOT EXISTS idx_clicks_url_id ON clicks(url_id);
+CREATE INDEX IF NOT EXISTS idx_clicks_clicked_at ON clicks(clicked_at DESC);
+CREATE INDEX IF NOT EXISTS idx_clicks_ip_address ON clicks(ip_address);
+CREATE INDEX IF NOT EXISTS idx_clicks_country ON clicks(country);
@@ -0,0 +1,18 @@
+-- per url and day totals for clicks that were purged from the clicks table
+CREATE TABLE IF NOT EXISTS click_daily_totals (
+    url_id TEXT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    clicks BIGINT NOT NULL DEFAULT 0,
+    unique_ips BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (url_id, day)
+);
+
+-- per url and day click counts broken down by a dimension (country, referer)
+CREATE TABLE IF NOT EXISTS click_daily_rollups (
+    url_id TEXT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    dimension TEXT NOT NULL,
+    value TEXT NOT NULL,
+    clicks BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (url_id, day, dimension, value)
+);
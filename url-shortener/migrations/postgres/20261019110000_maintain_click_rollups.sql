@@ -0,0 +1,5 @@
+-- device class derived from the user agent, see services::user_agent::classify_device
+ALTER TABLE clicks ADD COLUMN device TEXT;
+
+-- days whose raw clicks were purged keep only their per-day unique count
+ALTER TABLE click_daily_totals ADD COLUMN ips_purged BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- referer host without www./m. prefixes and the traffic channel it belongs to,
+-- see services::referer
+ALTER TABLE clicks ADD COLUMN referer_domain TEXT;
+ALTER TABLE clicks ADD COLUMN channel TEXT;
@@ -0,0 +1,12 @@
+-- grouping for aggregate stats, see GET /api/stats
+ALTER TABLE urls ADD COLUMN campaign TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_urls_campaign ON urls(campaign);
+
+CREATE TABLE IF NOT EXISTS url_tags (
+    url_id TEXT NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (url_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_url_tags_tag ON url_tags(tag);
@@ -0,0 +1,39 @@
+-- links and webhooks belong to an owner, webhooks without one receive every link's events
+ALTER TABLE urls ADD COLUMN owner TEXT;
+ALTER TABLE urls ADD COLUMN expiry_notified BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS idx_urls_owner ON urls(owner);
+
+-- links that expired before webhooks existed are not announced
+UPDATE urls SET expiry_notified = TRUE WHERE expires_at IS NOT NULL AND expires_at <= now();
+
+CREATE TABLE IF NOT EXISTS webhooks (
+    id TEXT PRIMARY KEY DEFAULT (replace(gen_random_uuid()::text, '-', '')),
+    owner TEXT,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- comma separated event names
+    events TEXT NOT NULL,
+    click_threshold BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT date_trunc('second', now())
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhooks_owner ON webhooks(owner);
+
+-- outbox, one row per event and webhook, retried until delivered or out of attempts
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id TEXT PRIMARY KEY DEFAULT (replace(gen_random_uuid()::text, '-', '')),
+    webhook_id TEXT NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_status_code BIGINT,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT date_trunc('second', now()),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at DESC);
@@ -0,0 +1,5 @@
+-- one random salt per day for hashed visitor IPs, older salts are deleted on rotation
+CREATE TABLE IF NOT EXISTS ip_salts (
+    day TEXT PRIMARY KEY,
+    salt TEXT NOT NULL
+);
@@ -0,0 +1,52 @@
+-- device class derived from the user agent, see services::user_agent::classify_device
+ALTER TABLE clicks ADD COLUMN device TEXT;
+
+UPDATE clicks SET device = CASE
+    WHEN user_agent IS NULL OR user_agent = '' THEN 'unknown'
+    WHEN lower(user_agent) LIKE '%bot%'
+        OR lower(user_agent) LIKE '%crawl%'
+        OR lower(user_agent) LIKE '%spider%' THEN 'bot'
+    WHEN lower(user_agent) LIKE '%ipad%' OR lower(user_agent) LIKE '%tablet%' THEN 'tablet'
+    WHEN lower(user_agent) LIKE '%mobi%'
+        OR lower(user_agent) LIKE '%iphone%'
+        OR lower(user_agent) LIKE '%android%' THEN 'mobile'
+    ELSE 'desktop'
+END;
+
+-- days whose raw clicks were purged keep only their per-day unique count
+ALTER TABLE click_daily_totals ADD COLUMN ips_purged INTEGER NOT NULL DEFAULT 0;
+UPDATE click_daily_totals SET ips_purged = 1;
+
+-- rollups are now maintained on ingestion, backfill them for the clicks we still have
+INSERT INTO click_daily_totals (url_id, day, clicks, unique_ips)
+SELECT url_id, DATE(clicked_at), COUNT(*), COUNT(DISTINCT ip_address)
+FROM clicks
+WHERE true
+GROUP BY url_id, DATE(clicked_at)
+ON CONFLICT (url_id, day) DO UPDATE SET
+    clicks = clicks + excluded.clicks,
+    unique_ips = unique_ips + excluded.unique_ips;
+
+INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+SELECT url_id, DATE(clicked_at), 'country', country, COUNT(*)
+FROM clicks WHERE country IS NOT NULL
+GROUP BY url_id, DATE(clicked_at), country
+ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET clicks = clicks + excluded.clicks;
+
+INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+SELECT url_id, DATE(clicked_at), 'referer', referer, COUNT(*)
+FROM clicks WHERE referer IS NOT NULL
+GROUP BY url_id, DATE(clicked_at), referer
+ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET clicks = clicks + excluded.clicks;
+
+INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+SELECT url_id, DATE(clicked_at), 'device', device, COUNT(*)
+FROM clicks WHERE device IS NOT NULL
+GROUP BY url_id, DATE(clicked_at), device
+ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET clicks = clicks + excluded.clicks;
+
+INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+SELECT url_id, DATE(clicked_at), 'ip', ip_address, COUNT(*)
+FROM clicks WHERE ip_address IS NOT NULL
+GROUP BY url_id, DATE(clicked_at), ip_address
+ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET clicks = clicks + excluded.clicks;
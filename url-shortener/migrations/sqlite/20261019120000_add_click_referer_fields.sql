@@ -0,0 +1,6 @@
+-- referer host without www./m. prefixes and the traffic channel it belongs to,
+-- see services::referer. Rows with a referer are backfilled at startup.
+ALTER TABLE clicks ADD COLUMN referer_domain TEXT;
+ALTER TABLE clicks ADD COLUMN channel TEXT;
+
+UPDATE clicks SET channel = 'direct' WHERE referer IS NULL OR referer = '';
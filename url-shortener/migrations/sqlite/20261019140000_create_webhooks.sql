@@ -0,0 +1,41 @@
+-- links and webhooks belong to an owner, webhooks without one receive every link's events
+ALTER TABLE urls ADD COLUMN owner TEXT;
+ALTER TABLE urls ADD COLUMN expiry_notified INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_urls_owner ON urls(owner);
+
+-- links that expired before webhooks existed are not announced. expires_at
+-- is RFC 3339, compared through julianday rather than as text
+UPDATE urls SET expiry_notified = 1
+WHERE expires_at IS NOT NULL AND julianday(expires_at) <= julianday('now');
+
+CREATE TABLE IF NOT EXISTS webhooks (
+    id TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    owner TEXT,
+    url TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    -- comma separated event names
+    events TEXT NOT NULL,
+    click_threshold INTEGER,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhooks_owner ON webhooks(owner);
+
+-- outbox, one row per event and webhook, retried until delivered or out of attempts
+CREATE TABLE IF NOT EXISTS webhook_deliveries (
+    id TEXT PRIMARY KEY DEFAULT (lower(hex(randomblob(16)))),
+    webhook_id TEXT NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL DEFAULT (datetime('now')),
+    last_status_code INTEGER,
+    last_error TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now')),
+    delivered_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_due ON webhook_deliveries(status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries(webhook_id, created_at DESC);
@@ -0,0 +1,3 @@
+-- deliveries are claimed as 'sending' until locked_until, so concurrent
+-- dispatchers don't send the same row; an expired claim is picked up again
+ALTER TABLE webhook_deliveries ADD COLUMN locked_until TEXT;
@@ -0,0 +1,305 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Parser, Subcommand};
+
+use url_shortener::{
+    config::{Config, ConfigArgs},
+    db::{self, Backend, Storage},
+    models::{CreateUrlRequest, DateRange},
+    services::{
+        backup, shorten,
+        transfer::{self, LinkFormat},
+    },
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "URL shortener server and link management")]
+pub struct Cli {
+    /// Runs the server when omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run migrations and start the HTTP server.
+    Serve,
+    /// Apply pending migrations and exit.
+    Migrate,
+    /// Shorten a URL.
+    Create {
+        url: String,
+        /// Custom short code instead of a generated one.
+        #[arg(long)]
+        code: Option<String>,
+        /// RFC 3339 timestamp after which the link stops redirecting.
+        #[arg(long)]
+        expires_at: Option<DateTime<Utc>>,
+        /// Tag the link, may be repeated.
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long)]
+        campaign: Option<String>,
+        #[arg(long)]
+        owner: Option<String>,
+    },
+    /// List all links, newest first.
+    List,
+    /// Delete a link together with its clicks.
+    Delete { code: String },
+    /// Print click statistics of a link as JSON.
+    Stats {
+        code: String,
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        #[arg(long)]
+        to: Option<NaiveDate>,
+    },
+    /// Write all links as JSON or CSV.
+    Export {
+        /// File to write to, stdout when omitted.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Taken from the extension of the output file when omitted, JSON otherwise.
+        #[arg(long, value_enum)]
+        format: Option<LinkFormat>,
+    },
+    /// Create links from a JSON or CSV export, keeping their codes. Invalid
+    /// rows and codes that already exist are skipped and reported.
+    Import {
+        /// File to read from, `-` for stdin.
+        input: PathBuf,
+        /// Taken from the extension of the input file when omitted, JSON otherwise.
+        #[arg(long, value_enum)]
+        format: Option<LinkFormat>,
+        /// Check every row and report what would be imported, without creating anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write a consistent snapshot of the SQLite database, also while the
+    /// server is running.
+    Backup {
+        /// File to write, a timestamped file in `database.backup_dir` when omitted.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Replace the SQLite database with a backup. Stop the server first.
+    Restore {
+        input: PathBuf,
+        /// Only validate the backup, leave the database as it is.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and print the effective settings as TOML,
+    /// with the admin token and database password masked.
+    Check,
+}
+
+/// Runs a management command against the configured database.
+pub async fn run(
+    command: Command,
+    config: &Config,
+    config_path: Option<&Path>,
+) -> anyhow::Result<()> {
+    // loading already validated the configuration
+    if let Command::Config {
+        command: ConfigCommand::Check,
+    } = command
+    {
+        match config_path {
+            Some(path) => eprintln!("Configuration is valid ({})", path.display()),
+            None => eprintln!("Configuration is valid (no config file, defaults and environment)"),
+        }
+        // the output ends up in terminals and CI logs
+        print!("{}", toml::to_string(&config.redacted())?);
+        return Ok(());
+    }
+
+    // the current database may be missing or broken, restore does not open it as a store
+    if let Command::Restore { input, check } = &command {
+        if Backend::from_url(&config.database.url) != Some(Backend::Sqlite) {
+            anyhow::bail!("Restore replaces a SQLite database, DATABASE_URL is not one");
+        }
+
+        let report = db::sqlite::backup::restore(&config.database, input, *check).await?;
+        if *check {
+            eprintln!(
+                "{} is a valid backup, {} migrations would be applied",
+                input.display(),
+                report.pending_migrations.len()
+            );
+        } else {
+            eprintln!("Restored {} to {}", input.display(), report.database);
+        }
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let db = db::connect(&config.database).await?;
+
+    if let Command::Migrate = command {
+        return url_shortener::run_migrations(db.as_ref(), config).await;
+    }
+
+    ensure_migrated(db.as_ref()).await?;
+
+    match command {
+        Command::Serve | Command::Migrate | Command::Restore { .. } | Command::Config { .. } => {
+            unreachable!("handled before")
+        }
+        Command::Create {
+            url,
+            code,
+            expires_at,
+            tags,
+            campaign,
+            owner,
+        } => {
+            let request = CreateUrlRequest {
+                url,
+                custom_code: code,
+                expires_at,
+                tags,
+                campaign,
+                owner,
+            };
+            let new_url =
+                shorten::prepare_link(db.as_ref(), request, config.policy.short_code_length)
+                    .await?;
+            let url = shorten::create_link(db.as_ref(), &new_url).await?;
+
+            println!("{}/{}", config.server.base_url, url.short_code);
+        }
+        Command::List => {
+            let urls = db.list_all_urls().await?;
+
+            let mut out = io::stdout().lock();
+            writeln!(
+                out,
+                "{:<20} {:>8}  {:<20} {:<20} URL",
+                "CODE", "CLICKS", "CREATED", "EXPIRES"
+            )?;
+            for url in urls {
+                writeln!(
+                    out,
+                    "{:<20} {:>8}  {:<20} {:<20} {}",
+                    url.short_code,
+                    url.click_count,
+                    url.created_at.format("%Y-%m-%d %H:%M"),
+                    url.expires_at
+                        .map(|expires_at| expires_at.format("%Y-%m-%d %H:%M").to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                    url.original_url
+                )?;
+            }
+        }
+        Command::Delete { code } => {
+            db.delete_url_by_code(&code).await?;
+            println!("Deleted {}", code);
+        }
+        Command::Stats { code, from, to } => {
+            let range = DateRange { from, to };
+            range.validate()?;
+
+            let url = db.get_url_by_code(&code).await?;
+            let stats = db.get_url_stats(url.id, &range).await?;
+
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        }
+        Command::Export { output, format } => {
+            let records = db.list_link_records().await?;
+
+            match output {
+                Some(path) => {
+                    let format = format
+                        .or_else(|| LinkFormat::from_path(&path))
+                        .unwrap_or_default();
+                    let file = File::create(&path)
+                        .with_context(|| format!("Failed to create {}", path.display()))?;
+                    transfer::write_links(format, &records, BufWriter::new(file))?;
+                    eprintln!("Exported {} links to {}", records.len(), path.display());
+                }
+                None => {
+                    let format = format.unwrap_or_default();
+                    let mut out = io::stdout().lock();
+                    transfer::write_links(format, &records, &mut out)?;
+                    // CSV rows end in a newline already
+                    if format == LinkFormat::Json {
+                        writeln!(out)?;
+                    }
+                }
+            }
+        }
+        Command::Import {
+            input,
+            format,
+            dry_run,
+        } => {
+            let format = format
+                .or_else(|| LinkFormat::from_path(&input))
+                .unwrap_or_default();
+            let rows = if input.as_os_str() == "-" {
+                transfer::read_links(format, io::stdin().lock())?
+            } else {
+                let file = File::open(&input)
+                    .with_context(|| format!("Failed to open {}", input.display()))?;
+                transfer::read_links(format, BufReader::new(file))?
+            };
+
+            let report = transfer::import_links(db.as_ref(), rows, dry_run).await?;
+            for row in &report.skipped {
+                eprintln!("#{} {}: {}", row.row, row.code, row.error);
+            }
+            println!(
+                "{} {} of {} links, skipped {}",
+                if report.dry_run {
+                    "Would import"
+                } else {
+                    "Imported"
+                },
+                report.imported,
+                report.total,
+                report.skipped.len()
+            );
+        }
+        Command::Backup { output } => {
+            let destination = output
+                .unwrap_or_else(|| backup::default_path(&config.database.backup_dir, Utc::now()));
+            let report = backup::backup(db.as_ref(), &destination).await?;
+
+            eprintln!("Backed up {} bytes to {}", report.size_bytes, report.path);
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        }
+    }
+
+    Ok(())
+}
+
+async fn ensure_migrated(db: &dyn Storage) -> anyhow::Result<()> {
+    match db.pending_migrations().await {
+        Ok(pending) if pending.is_empty() => Ok(()),
+        Ok(pending) => anyhow::bail!(
+            "Database has {} pending migrations, run `url-shortener migrate` first",
+            pending.len()
+        ),
+        // _sqlx_migrations does not exist yet
+        Err(_) => anyhow::bail!("Database is not initialised, run `url-shortener migrate` first"),
+    }
+}
@@ -1,32 +1,843 @@
-use std::env;
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-use anyhow::Ok;
+use anyhow::Context;
+use clap::Args;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+use crate::db::Backend;
+
+// read when neither --config nor CONFIG_FILE is given, skipped if missing
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+const MIN_ADMIN_TOKEN_LENGTH: usize = 16;
+
+/// Overrides applied on top of the config file and environment.
+#[derive(Clone, Debug, Default, Args)]
+pub struct ConfigArgs {
+    /// TOML config file, defaults to $CONFIG_FILE or ./config.toml if present.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub host: Option<String>,
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+    #[arg(long, global = true)]
+    pub base_url: Option<String>,
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+}
+
+/// Settings resolved from defaults, the TOML config file, environment
+/// variables and command line flags, later sources winning.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
-    pub database_url: String,
-    pub server_host: String,
-    pub server_port: u16,
-    pub short_code_length: usize,
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub cache: CacheConfig,
+    pub rate_limits: RateLimitConfig,
+    pub analytics: AnalyticsConfig,
+    pub policy: PolicyConfig,
+    pub webhooks: WebhookConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Public origin short links are built on, required.
     pub base_url: String,
-    pub requests_per_minute: u32,
+    pub trusted_proxies: Vec<IpNet>,
+    pub shutdown_drain_seconds: u64,
+    /// Bearer token the `/api/admin` routes require. Without one they are
+    /// not served at all.
+    pub admin_token: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// Size of the Postgres pool. SQLite uses it for its read pool and
+    /// writes through a single connection of its own.
+    pub max_connections: u32,
+    pub sqlite_journal_mode: JournalMode,
+    pub sqlite_synchronous: SynchronousMode,
+    /// How long a SQLite connection waits on a locked database before failing.
+    pub sqlite_busy_timeout_ms: u64,
+    pub vacuum_interval_seconds: Option<u64>,
+    /// Where backups taken without an explicit path are written.
+    pub backup_dir: PathBuf,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Short codes kept in the lookup cache, 0 disables it.
+    pub capacity: usize,
+    pub ttl_seconds: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub redirect_requests_per_minute: u32,
+    pub create_requests_per_minute: u32,
+    /// Links created through `POST /api/shorten/bulk`, counted per item.
+    pub bulk_items_per_minute: u32,
+    pub stats_requests_per_minute: u32,
+    pub cleanup_seconds: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyticsConfig {
+    pub click_queue_capacity: usize,
+    pub click_batch_size: usize,
+    pub privacy_mode: PrivacyMode,
+    pub click_retention_days: Option<u32>,
+    pub click_purge_interval_seconds: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicyConfig {
+    pub short_code_length: usize,
+    /// Most links one `POST /api/shorten/bulk` request may create.
+    pub bulk_max_items: usize,
+    pub expired_link_retention_days: Option<u32>,
+    pub expired_link_purge_interval_seconds: u64,
+    pub link_expiry_check_interval_seconds: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub delivery_interval_seconds: u64,
+    pub timeout_seconds: u64,
+    pub max_attempts: u32,
+    /// Lets webhooks target loopback and private addresses, for receivers on
+    /// the same host or network. Off by default.
+    pub allow_private_targets: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            base_url: String::new(),
+            trusted_proxies: Vec::new(),
+            shutdown_drain_seconds: 30,
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite:url_shortener.db".to_string(),
+            max_connections: 5,
+            sqlite_journal_mode: JournalMode::Wal,
+            sqlite_synchronous: SynchronousMode::Normal,
+            sqlite_busy_timeout_ms: 5000,
+            vacuum_interval_seconds: None,
+            backup_dir: PathBuf::from("backups"),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 10000,
+            ttl_seconds: 60,
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            redirect_requests_per_minute: 120,
+            create_requests_per_minute: 5,
+            bulk_items_per_minute: 100,
+            stats_requests_per_minute: 60,
+            cleanup_seconds: 60,
+        }
+    }
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            click_queue_capacity: 10000,
+            click_batch_size: 100,
+            privacy_mode: PrivacyMode::Full,
+            click_retention_days: None,
+            click_purge_interval_seconds: 3600,
+        }
+    }
+}
+
+impl Default for PolicyConfig {
+    fn default() -> Self {
+        Self {
+            short_code_length: 6,
+            bulk_max_items: 100,
+            expired_link_retention_days: None,
+            expired_link_purge_interval_seconds: 3600,
+            link_expiry_check_interval_seconds: 60,
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            delivery_interval_seconds: 5,
+            timeout_seconds: 10,
+            max_attempts: 8,
+            allow_private_targets: false,
+        }
+    }
+}
+
+/// How visitor IPs are stored on clicks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivacyMode {
+    /// The full address.
+    Full,
+    /// The /24 (IPv4) or /48 (IPv6) network the address belongs to.
+    Truncate,
+    /// A SHA-256 of the address with a salt that rotates daily.
+    Hash,
+}
+
+impl FromStr for PrivacyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "full" => Ok(PrivacyMode::Full),
+            "truncate" => Ok(PrivacyMode::Truncate),
+            "hash" => Ok(PrivacyMode::Hash),
+            other => Err(anyhow::anyhow!(
+                "Invalid privacy mode '{}', expected full, truncate or hash",
+                other
+            )),
+        }
+    }
+}
+
+/// SQLite journal mode. In `wal` mode readers keep going while a write is
+/// in progress, the others lock out readers for the length of a write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    Wal,
+    Delete,
+    Truncate,
+    Persist,
+}
+
+impl FromStr for JournalMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "wal" => Ok(JournalMode::Wal),
+            "delete" => Ok(JournalMode::Delete),
+            "truncate" => Ok(JournalMode::Truncate),
+            "persist" => Ok(JournalMode::Persist),
+            other => Err(anyhow::anyhow!(
+                "Invalid journal mode '{}', expected wal, delete, truncate or persist",
+                other
+            )),
+        }
+    }
+}
+
+/// How hard SQLite syncs to disk on commit. `normal` is safe in `wal` mode,
+/// a power loss can only roll back the last transactions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SynchronousMode {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl FromStr for SynchronousMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Ok(SynchronousMode::Off),
+            "normal" => Ok(SynchronousMode::Normal),
+            "full" => Ok(SynchronousMode::Full),
+            "extra" => Ok(SynchronousMode::Extra),
+            other => Err(anyhow::anyhow!(
+                "Invalid synchronous mode '{}', expected off, normal, full or extra",
+                other
+            )),
+        }
+    }
 }
 
 impl Config {
-    pub fn get_env_vars() -> anyhow::Result<Self> {
-        Ok(Self {
-            database_url: env::var("DATABASE_URL")?,
-            server_host: env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string()),
-            server_port: env::var("SERVER_PORT")
-                .unwrap_or_else(|_| "8080".to_string())
-                .parse()?,
-            short_code_length: env::var("SHORT_CODE_LENGTH")
-                .unwrap_or_else(|_| "6".to_string())
-                .parse()?,
-            base_url: env::var("BASE_URL")?,
-            requests_per_minute: env::var("RATE_LIMIT_PER_MINUTE")
-                .unwrap_or_else(|_| "5".to_string())
-                .parse()?,
+    /// Loads the config file, applies environment and command line overrides
+    /// and validates the result. Every problem found is reported at once.
+    pub fn load(args: &ConfigArgs) -> anyhow::Result<(Self, Option<PathBuf>)> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| env::var_os("CONFIG_FILE").map(PathBuf::from))
+            .or_else(|| {
+                Path::new(DEFAULT_CONFIG_FILE)
+                    .exists()
+                    .then(|| PathBuf::from(DEFAULT_CONFIG_FILE))
+            });
+
+        let mut config = match &path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        let mut errors = config.apply_env();
+        config.apply_args(args);
+        errors.extend(config.validate());
+
+        if !errors.is_empty() {
+            anyhow::bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+        }
+
+        Ok((config, path))
+    }
+
+    /// A copy safe to show over the admin API and in reload reports, with
+    /// the admin token and the password of the database URL masked.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+
+        if config.server.admin_token.is_some() {
+            config.server.admin_token = Some("***".to_string());
+        }
+
+        if let Ok(mut url) = url::Url::parse(&config.database.url)
+            && url.password().is_some()
+            && url.set_password(Some("***")).is_ok()
+        {
+            config.database.url = url.to_string();
+        }
+
+        config
+    }
+
+    /// Path of `server.base_url` without the trailing slash, empty when the
+    /// shortener is served from the root. Pages prefix their links with it.
+    pub fn base_path(&self) -> String {
+        url::Url::parse(&self.server.base_url)
+            .map(|url| url.path().trim_end_matches('/').to_string())
+            .unwrap_or_default()
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+
+        toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    fn apply_env(&mut self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let e = &mut errors;
+
+        env_override(e, "SERVER_HOST", &mut self.server.host);
+        env_override(e, "SERVER_PORT", &mut self.server.port);
+        env_override(e, "BASE_URL", &mut self.server.base_url);
+        if let Ok(value) = env::var("TRUSTED_PROXIES") {
+            match parse_trusted_proxies(&value) {
+                Ok(proxies) => self.server.trusted_proxies = proxies,
+                Err(err) => e.push(format!("TRUSTED_PROXIES: {}", err)),
+            }
+        }
+        env_override(
+            e,
+            "SHUTDOWN_DRAIN_SECONDS",
+            &mut self.server.shutdown_drain_seconds,
+        );
+        env_override_opt(e, "ADMIN_TOKEN", &mut self.server.admin_token);
+
+        let database = &mut self.database;
+        env_override(e, "DATABASE_URL", &mut database.url);
+        env_override(e, "DATABASE_MAX_CONNECTIONS", &mut database.max_connections);
+        env_override(e, "SQLITE_JOURNAL_MODE", &mut database.sqlite_journal_mode);
+        env_override(e, "SQLITE_SYNCHRONOUS", &mut database.sqlite_synchronous);
+        env_override(
+            e,
+            "SQLITE_BUSY_TIMEOUT_MS",
+            &mut database.sqlite_busy_timeout_ms,
+        );
+        env_override_opt(
+            e,
+            "VACUUM_INTERVAL_SECONDS",
+            &mut database.vacuum_interval_seconds,
+        );
+        env_override(e, "BACKUP_DIR", &mut database.backup_dir);
+
+        env_override(e, "URL_CACHE_CAPACITY", &mut self.cache.capacity);
+        env_override(e, "URL_CACHE_TTL_SECONDS", &mut self.cache.ttl_seconds);
+
+        let limits = &mut self.rate_limits;
+        env_override(
+            e,
+            "RATE_LIMIT_REDIRECT_PER_MINUTE",
+            &mut limits.redirect_requests_per_minute,
+        );
+        env_override(
+            e,
+            "RATE_LIMIT_PER_MINUTE",
+            &mut limits.create_requests_per_minute,
+        );
+        env_override(
+            e,
+            "RATE_LIMIT_BULK_ITEMS_PER_MINUTE",
+            &mut limits.bulk_items_per_minute,
+        );
+        env_override(
+            e,
+            "RATE_LIMIT_STATS_PER_MINUTE",
+            &mut limits.stats_requests_per_minute,
+        );
+        env_override(e, "RATE_LIMIT_CLEANUP_SECONDS", &mut limits.cleanup_seconds);
+
+        let analytics = &mut self.analytics;
+        env_override(
+            e,
+            "CLICK_QUEUE_CAPACITY",
+            &mut analytics.click_queue_capacity,
+        );
+        env_override(e, "CLICK_BATCH_SIZE", &mut analytics.click_batch_size);
+        env_override(e, "PRIVACY_MODE", &mut analytics.privacy_mode);
+        env_override_opt(
+            e,
+            "CLICK_RETENTION_DAYS",
+            &mut analytics.click_retention_days,
+        );
+        env_override(
+            e,
+            "CLICK_PURGE_INTERVAL_SECONDS",
+            &mut analytics.click_purge_interval_seconds,
+        );
+
+        let policy = &mut self.policy;
+        env_override(e, "SHORT_CODE_LENGTH", &mut policy.short_code_length);
+        env_override(e, "BULK_MAX_ITEMS", &mut policy.bulk_max_items);
+        env_override_opt(
+            e,
+            "EXPIRED_LINK_RETENTION_DAYS",
+            &mut policy.expired_link_retention_days,
+        );
+        env_override(
+            e,
+            "EXPIRED_LINK_PURGE_INTERVAL_SECONDS",
+            &mut policy.expired_link_purge_interval_seconds,
+        );
+        env_override(
+            e,
+            "LINK_EXPIRY_CHECK_INTERVAL_SECONDS",
+            &mut policy.link_expiry_check_interval_seconds,
+        );
+
+        let webhooks = &mut self.webhooks;
+        env_override(
+            e,
+            "WEBHOOK_DELIVERY_INTERVAL_SECONDS",
+            &mut webhooks.delivery_interval_seconds,
+        );
+        env_override(e, "WEBHOOK_TIMEOUT_SECONDS", &mut webhooks.timeout_seconds);
+        env_override(e, "WEBHOOK_MAX_ATTEMPTS", &mut webhooks.max_attempts);
+        env_override(
+            e,
+            "WEBHOOK_ALLOW_PRIVATE_TARGETS",
+            &mut webhooks.allow_private_targets,
+        );
+
+        errors
+    }
+
+    fn apply_args(&mut self, args: &ConfigArgs) {
+        if let Some(host) = &args.host {
+            self.server.host = host.clone();
+        }
+        if let Some(port) = args.port {
+            self.server.port = port;
+        }
+        if let Some(base_url) = &args.base_url {
+            self.server.base_url = base_url.clone();
+        }
+        if let Some(database_url) = &args.database_url {
+            self.database.url = database_url.clone();
+        }
+    }
+
+    /// Checks values that parse but would fail or panic later on, returning
+    /// one message per problem, none when the config is usable.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let e = &mut errors;
+
+        match url::Url::parse(&self.server.base_url) {
+            _ if self.server.base_url.is_empty() => {
+                e.push("server.base_url (BASE_URL) is required".to_string())
+            }
+            Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+            _ => e.push(format!(
+                "server.base_url (BASE_URL) must be an http or https URL, got '{}'",
+                self.server.base_url
+            )),
+        }
+
+        if let Some(token) = &self.server.admin_token
+            && token.len() < MIN_ADMIN_TOKEN_LENGTH
+        {
+            e.push(format!(
+                "server.admin_token (ADMIN_TOKEN) must be at least {} characters",
+                MIN_ADMIN_TOKEN_LENGTH
+            ));
+        }
+
+        if Backend::from_url(&self.database.url).is_none() {
+            e.push(format!(
+                "database.url (DATABASE_URL) must start with 'sqlite:', 'postgres://', 'postgresql://' or 'memory:', got '{}'",
+                self.database.url
+            ));
+        }
+        at_least(
+            e,
+            "database.max_connections (DATABASE_MAX_CONNECTIONS)",
+            self.database.max_connections,
+            1,
+        );
+        if let Some(seconds) = self.database.vacuum_interval_seconds {
+            at_least(
+                e,
+                "database.vacuum_interval_seconds (VACUUM_INTERVAL_SECONDS)",
+                seconds,
+                1,
+            );
+        }
+
+        let limits = &self.rate_limits;
+        at_least(
+            e,
+            "rate_limits.redirect_requests_per_minute (RATE_LIMIT_REDIRECT_PER_MINUTE)",
+            limits.redirect_requests_per_minute,
+            1,
+        );
+        at_least(
+            e,
+            "rate_limits.create_requests_per_minute (RATE_LIMIT_PER_MINUTE)",
+            limits.create_requests_per_minute,
+            1,
+        );
+        at_least(
+            e,
+            "rate_limits.bulk_items_per_minute (RATE_LIMIT_BULK_ITEMS_PER_MINUTE)",
+            limits.bulk_items_per_minute,
+            1,
+        );
+        at_least(
+            e,
+            "rate_limits.stats_requests_per_minute (RATE_LIMIT_STATS_PER_MINUTE)",
+            limits.stats_requests_per_minute,
+            1,
+        );
+        at_least(
+            e,
+            "rate_limits.cleanup_seconds (RATE_LIMIT_CLEANUP_SECONDS)",
+            limits.cleanup_seconds,
+            1,
+        );
+
+        let analytics = &self.analytics;
+        at_least(
+            e,
+            "analytics.click_queue_capacity (CLICK_QUEUE_CAPACITY)",
+            analytics.click_queue_capacity,
+            1,
+        );
+        at_least(
+            e,
+            "analytics.click_batch_size (CLICK_BATCH_SIZE)",
+            analytics.click_batch_size,
+            1,
+        );
+        if let Some(days) = analytics.click_retention_days {
+            at_least(
+                e,
+                "analytics.click_retention_days (CLICK_RETENTION_DAYS)",
+                days,
+                1,
+            );
+        }
+        at_least(
+            e,
+            "analytics.click_purge_interval_seconds (CLICK_PURGE_INTERVAL_SECONDS)",
+            analytics.click_purge_interval_seconds,
+            1,
+        );
+
+        let policy = &self.policy;
+        // custom codes are capped at 20 characters as well
+        if !(4..=20).contains(&policy.short_code_length) {
+            e.push(format!(
+                "policy.short_code_length (SHORT_CODE_LENGTH) must be between 4 and 20, got {}",
+                policy.short_code_length
+            ));
+        }
+        at_least(
+            e,
+            "policy.bulk_max_items (BULK_MAX_ITEMS)",
+            policy.bulk_max_items,
+            1,
+        );
+        // a batch is charged all at once, one larger than the quota never passes
+        if policy.bulk_max_items > limits.bulk_items_per_minute as usize {
+            e.push(format!(
+                "policy.bulk_max_items (BULK_MAX_ITEMS) must not exceed rate_limits.bulk_items_per_minute ({}), got {}",
+                limits.bulk_items_per_minute, policy.bulk_max_items
+            ));
+        }
+        if let Some(days) = policy.expired_link_retention_days {
+            at_least(
+                e,
+                "policy.expired_link_retention_days (EXPIRED_LINK_RETENTION_DAYS)",
+                days,
+                1,
+            );
+        }
+        at_least(
+            e,
+            "policy.expired_link_purge_interval_seconds (EXPIRED_LINK_PURGE_INTERVAL_SECONDS)",
+            policy.expired_link_purge_interval_seconds,
+            1,
+        );
+        at_least(
+            e,
+            "policy.link_expiry_check_interval_seconds (LINK_EXPIRY_CHECK_INTERVAL_SECONDS)",
+            policy.link_expiry_check_interval_seconds,
+            1,
+        );
+
+        let webhooks = &self.webhooks;
+        at_least(
+            e,
+            "webhooks.delivery_interval_seconds (WEBHOOK_DELIVERY_INTERVAL_SECONDS)",
+            webhooks.delivery_interval_seconds,
+            1,
+        );
+        at_least(
+            e,
+            "webhooks.timeout_seconds (WEBHOOK_TIMEOUT_SECONDS)",
+            webhooks.timeout_seconds,
+            1,
+        );
+        at_least(
+            e,
+            "webhooks.max_attempts (WEBHOOK_MAX_ATTEMPTS)",
+            webhooks.max_attempts,
+            1,
+        );
+
+        errors
+    }
+}
+
+fn env_override<T>(errors: &mut Vec<String>, var: &str, target: &mut T)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(value) = env::var(var) {
+        match value.trim().parse() {
+            Ok(parsed) => *target = parsed,
+            Err(err) => errors.push(format!("{}: invalid value '{}': {}", var, value, err)),
+        }
+    }
+}
+
+/// Like `env_override`, an empty value turns the setting off.
+fn env_override_opt<T>(errors: &mut Vec<String>, var: &str, target: &mut Option<T>)
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(var) {
+        Ok(value) if value.trim().is_empty() => *target = None,
+        Ok(value) => match value.trim().parse() {
+            Ok(parsed) => *target = Some(parsed),
+            Err(err) => errors.push(format!("{}: invalid value '{}': {}", var, value, err)),
+        },
+        Err(_) => {}
+    }
+}
+
+fn at_least<T: PartialOrd + Display>(errors: &mut Vec<String>, setting: &str, value: T, min: T) {
+    if value < min {
+        errors.push(format!(
+            "{} must be at least {}, got {}",
+            setting, min, value
+        ));
+    }
+}
+
+/// Comma separated CIDRs, bare addresses are treated as single hosts.
+fn parse_trusted_proxies(value: &str) -> anyhow::Result<Vec<IpNet>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<std::net::IpAddr>().map(IpNet::from))
+                .map_err(|_| anyhow::anyhow!("Invalid trusted proxy '{}'", entry))
         })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Change = fn(&mut Config);
+
+    fn valid() -> Config {
+        let mut config = Config::default();
+        config.server.base_url = "https://sho.rt".to_string();
+        config
+    }
+
+    fn rejects(config: &Config, setting: &str) {
+        let errors = config.validate();
+        assert!(
+            errors.iter().any(|error| error.starts_with(setting)),
+            "{} not rejected, errors: {:?}",
+            setting,
+            errors
+        );
+    }
+
+    #[test]
+    fn defaults_with_a_base_url_are_valid() {
+        assert_eq!(valid().validate(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn base_url_must_be_http() {
+        for base_url in ["", "ftp://sho.rt", "localhost:3000", "not a url"] {
+            let mut config = valid();
+            config.server.base_url = base_url.to_string();
+            rejects(&config, "server.base_url");
+        }
+
+        let mut config = valid();
+        config.server.base_url = "http://localhost:3000/links".to_string();
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn database_url_needs_a_known_scheme() {
+        let mut config = valid();
+        config.database.url = "mysql://localhost/links".to_string();
+        rejects(&config, "database.url");
+    }
+
+    #[test]
+    fn zero_quotas_and_intervals_are_rejected() {
+        let cases: [(&str, Change); 8] = [
+            ("rate_limits.redirect_requests_per_minute", |c| {
+                c.rate_limits.redirect_requests_per_minute = 0
+            }),
+            ("rate_limits.create_requests_per_minute", |c| {
+                c.rate_limits.create_requests_per_minute = 0
+            }),
+            ("rate_limits.bulk_items_per_minute", |c| {
+                c.rate_limits.bulk_items_per_minute = 0
+            }),
+            ("rate_limits.stats_requests_per_minute", |c| {
+                c.rate_limits.stats_requests_per_minute = 0
+            }),
+            ("rate_limits.cleanup_seconds", |c| {
+                c.rate_limits.cleanup_seconds = 0
+            }),
+            ("analytics.click_batch_size", |c| {
+                c.analytics.click_batch_size = 0
+            }),
+            ("database.vacuum_interval_seconds", |c| {
+                c.database.vacuum_interval_seconds = Some(0)
+            }),
+            ("webhooks.max_attempts", |c| c.webhooks.max_attempts = 0),
+        ];
+
+        for (setting, set) in cases {
+            let mut config = valid();
+            set(&mut config);
+            rejects(&config, setting);
+        }
+    }
+
+    #[test]
+    fn short_code_length_is_bounded() {
+        for length in [3, 21] {
+            let mut config = valid();
+            config.policy.short_code_length = length;
+            rejects(&config, "policy.short_code_length");
+        }
+    }
+
+    #[test]
+    fn bulk_batches_fit_the_bulk_quota() {
+        let mut config = valid();
+        config.policy.bulk_max_items = config.rate_limits.bulk_items_per_minute as usize + 1;
+        rejects(&config, "policy.bulk_max_items");
+
+        config.rate_limits.bulk_items_per_minute += 1;
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn admin_token_is_optional_but_long() {
+        let mut config = valid();
+        config.server.admin_token = None;
+        assert!(config.validate().is_empty());
+
+        config.server.admin_token = Some("short".to_string());
+        rejects(&config, "server.admin_token");
+
+        config.server.admin_token = Some("a".repeat(MIN_ADMIN_TOKEN_LENGTH));
+        assert!(config.validate().is_empty());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut config = Config::default();
+        config.rate_limits.create_requests_per_minute = 0;
+        config.server.admin_token = Some("short".to_string());
+
+        assert_eq!(config.validate().len(), 3);
     }
 }
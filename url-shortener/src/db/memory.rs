@@ -0,0 +1,1183 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
+use rand::RngCore;
+use sqlx::migrate::Migrator;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::db::{Backend, ClickStore, DIMENSIONS, LinkStore, PoolStats, Storage, WebhookStore};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AggregateStats, ChannelCount, Click, ClickCursor, ClickStats, CountryCount, DateCount,
+    DateRange, DeviceCount, LinkClickCount, LinkFilter, LinkRecord, LinkSeries, NewClick,
+    NewDelivery, NewUrl, PendingDelivery, RefererCount, RefererDomainCount, RollupReport, Url,
+    Webhook, WebhookDelivery, WebhookEvent,
+};
+use crate::services::referer::RefererInfo;
+use crate::services::webhooks::ClickOutbox;
+
+/// Nothing to migrate, the tables below are the schema.
+static MIGRATOR: Migrator = Migrator::DEFAULT;
+
+/// Keeps everything in process memory and loses it on exit. Meant for tests
+/// and for embedding the shortener where no database is at hand.
+#[derive(Default)]
+pub struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // no lock is held across an await, so a panic cannot leave a table half written
+    fn tables(&self) -> MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Same rows as the SQL schema, keyed like its primary keys.
+#[derive(Default)]
+struct Tables {
+    urls: HashMap<String, StoredUrl>,
+    url_ids_by_code: HashMap<String, String>,
+    // (url_id, tag)
+    url_tags: BTreeSet<(String, String)>,
+    clicks: Vec<Click>,
+    // (url_id, day)
+    daily_totals: BTreeMap<(String, NaiveDate), DailyTotal>,
+    // (url_id, day, dimension, value) -> clicks
+    daily_rollups: BTreeMap<(String, NaiveDate, String, String), i64>,
+    ip_salts: BTreeMap<NaiveDate, String>,
+    webhooks: Vec<StoredWebhook>,
+    webhook_deliveries: Vec<WebhookDelivery>,
+}
+
+struct StoredUrl {
+    url: Url,
+    expiry_notified: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+struct DailyTotal {
+    clicks: i64,
+    unique_ips: i64,
+    ips_purged: bool,
+}
+
+struct StoredWebhook {
+    webhook: Webhook,
+    secret: String,
+}
+
+type RawAggregates = (
+    BTreeMap<(String, NaiveDate), DailyTotal>,
+    BTreeMap<(String, NaiveDate, String, String), i64>,
+);
+
+// matches the ids SQLite generates with lower(hex(randomblob(16)))
+fn random_id() -> String {
+    let mut id = [0u8; 16];
+    rand::rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
+
+// timestamps are stored with second precision by the SQL backends too
+fn now() -> DateTime<Utc> {
+    Utc::now().trunc_subsecs(0)
+}
+
+fn in_range(day: NaiveDate, range: &DateRange) -> bool {
+    range.from.is_none_or(|from| day >= from) && range.to.is_none_or(|to| day <= to)
+}
+
+/// Value a click adds to a rollup dimension, see [`DIMENSIONS`].
+fn dimension_value<'a>(click: &'a Click, dimension: &str) -> Option<&'a str> {
+    match dimension {
+        "country" => click.country.as_deref(),
+        "referer" => click.referer.as_deref(),
+        "referer_domain" => click.referer_domain.as_deref(),
+        "channel" => click.channel.as_deref(),
+        "device" => click.device.as_deref(),
+        "ip" => click.ip_address.as_deref(),
+        _ => None,
+    }
+}
+
+fn symmetric_difference<T: Ord>(left: &BTreeSet<T>, right: &BTreeSet<T>) -> i64 {
+    left.symmetric_difference(right).count() as i64
+}
+
+impl Tables {
+    fn url_by_code(&self, short_code: &str) -> Option<&StoredUrl> {
+        self.url_ids_by_code
+            .get(short_code)
+            .and_then(|id| self.urls.get(id))
+    }
+
+    fn tags_of(&self, url_id: &str) -> Vec<String> {
+        self.url_tags
+            .iter()
+            .filter(|(id, _)| id == url_id)
+            .map(|(_, tag)| tag.clone())
+            .collect()
+    }
+
+    /// Every link, newest first.
+    fn urls_newest_first(&self) -> Vec<Url> {
+        let mut urls: Vec<Url> = self
+            .urls
+            .values()
+            .map(|stored| stored.url.clone())
+            .collect();
+        urls.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| a.short_code.cmp(&b.short_code))
+        });
+        urls
+    }
+
+    fn insert_url(&mut self, new_url: &NewUrl) -> AppResult<Url> {
+        if self.url_ids_by_code.contains_key(&new_url.short_code) {
+            return Err(AppError::CodeAlreadyExists);
+        }
+
+        let url = Url {
+            id: random_id(),
+            original_url: new_url.original_url.clone(),
+            short_code: new_url.short_code.clone(),
+            created_at: new_url.created_at.unwrap_or_else(now).trunc_subsecs(0),
+            expires_at: new_url.expires_at,
+            click_count: 0,
+            campaign: new_url.campaign.clone(),
+            owner: new_url.owner.clone(),
+        };
+
+        for tag in &new_url.tags {
+            self.url_tags.insert((url.id.clone(), tag.clone()));
+        }
+        self.url_ids_by_code
+            .insert(url.short_code.clone(), url.id.clone());
+        self.urls.insert(
+            url.id.clone(),
+            StoredUrl {
+                url: url.clone(),
+                expiry_notified: false,
+            },
+        );
+
+        Ok(url)
+    }
+
+    /// Removes the links matching `remove`, cascading to everything that
+    /// references them, and returns them.
+    fn remove_urls(&mut self, remove: impl Fn(&Url) -> bool) -> Vec<Url> {
+        let ids: Vec<String> = self
+            .urls
+            .values()
+            .filter(|stored| remove(&stored.url))
+            .map(|stored| stored.url.id.clone())
+            .collect();
+        if ids.is_empty() {
+            return Vec::new();
+        }
+
+        let removed: Vec<Url> = ids
+            .iter()
+            .filter_map(|id| self.urls.remove(id))
+            .map(|stored| stored.url)
+            .collect();
+        let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+
+        for url in &removed {
+            self.url_ids_by_code.remove(&url.short_code);
+        }
+        self.url_tags.retain(|(id, _)| !ids.contains(id.as_str()));
+        self.clicks
+            .retain(|click| !ids.contains(click.url_id.as_str()));
+        self.daily_totals
+            .retain(|(id, _), _| !ids.contains(id.as_str()));
+        self.daily_rollups
+            .retain(|(id, _, _, _), _| !ids.contains(id.as_str()));
+
+        removed
+    }
+
+    fn matches_filter(&self, url: &Url, filter: &LinkFilter) -> bool {
+        filter
+            .owner
+            .as_ref()
+            .is_none_or(|owner| url.owner.as_ref() == Some(owner))
+            && filter
+                .campaign
+                .as_ref()
+                .is_none_or(|campaign| url.campaign.as_ref() == Some(campaign))
+            && filter
+                .tag
+                .as_ref()
+                .is_none_or(|tag| self.url_tags.contains(&(url.id.clone(), tag.clone())))
+    }
+
+    fn select(&self, links: LinkSelector<'_>, range: &DateRange) -> Selection<'_> {
+        let url_ids = match links {
+            LinkSelector::Url(url_id) => HashSet::from([url_id.to_string()]),
+            LinkSelector::Filter(filter) => self
+                .urls
+                .values()
+                .filter(|stored| self.matches_filter(&stored.url, filter))
+                .map(|stored| stored.url.id.clone())
+                .collect(),
+        };
+
+        Selection {
+            tables: self,
+            url_ids,
+            range: range.clone(),
+            today: Utc::now().date_naive(),
+        }
+    }
+
+    fn record_clicks(
+        &mut self,
+        clicks: &[NewClick],
+        outbox: &ClickOutbox,
+    ) -> AppResult<Vec<Click>> {
+        // the whole batch fails, like a foreign key violation would roll it back
+        if clicks
+            .iter()
+            .any(|click| !self.urls.contains_key(&click.url_id))
+        {
+            return Err(AppError::UrLNotFound);
+        }
+
+        let mut recorded = Vec::with_capacity(clicks.len());
+        let mut per_url: HashMap<&str, i64> = HashMap::new();
+
+        for click in clicks {
+            let row = Click {
+                id: random_id(),
+                url_id: click.url_id.clone(),
+                clicked_at: click.clicked_at.trunc_subsecs(0),
+                ip_address: click.ip_address.clone(),
+                user_agent: click.user_agent.clone(),
+                referer: click.referer.clone(),
+                country: None,
+                city: None,
+                device: Some(click.device.to_string()),
+                referer_domain: click.referer_domain.clone(),
+                channel: Some(click.channel.to_string()),
+            };
+
+            if let Some(stored) = self.urls.get_mut(&click.url_id) {
+                stored.url.click_count += 1;
+            }
+
+            let day = row.clicked_at.date_naive();
+            self.daily_totals
+                .entry((click.url_id.clone(), day))
+                .or_default()
+                .clicks += 1;
+
+            for (dimension, value) in click.dimensions() {
+                let clicks = self
+                    .daily_rollups
+                    .entry((
+                        click.url_id.clone(),
+                        day,
+                        dimension.to_string(),
+                        value.to_string(),
+                    ))
+                    .or_default();
+                *clicks += 1;
+
+                // an ip seen for the first time that day is a new unique visitor
+                if dimension == "ip"
+                    && *clicks == 1
+                    && let Some(total) = self.daily_totals.get_mut(&(click.url_id.clone(), day))
+                {
+                    total.unique_ips += 1;
+                }
+            }
+
+            self.clicks.push(row.clone());
+            recorded.push(row);
+            *per_url.entry(click.url_id.as_str()).or_default() += 1;
+        }
+
+        let counters: Vec<(String, i64, i64)> = per_url
+            .into_iter()
+            .map(|(url_id, count)| {
+                (
+                    url_id.to_string(),
+                    count,
+                    i64::from(self.urls[url_id].url.click_count),
+                )
+            })
+            .collect();
+        self.insert_deliveries(&outbox.deliveries(&counters)?);
+
+        Ok(recorded)
+    }
+
+    fn insert_deliveries(&mut self, deliveries: &[NewDelivery]) {
+        let now = now();
+
+        for delivery in deliveries {
+            self.webhook_deliveries.push(WebhookDelivery {
+                id: random_id(),
+                webhook_id: delivery.webhook_id.clone(),
+                event: delivery.event.as_str().to_string(),
+                payload: delivery.payload.clone(),
+                status: "pending".to_string(),
+                attempts: 0,
+                next_attempt_at: now,
+                last_status_code: None,
+                last_error: None,
+                created_at: now,
+                delivered_at: None,
+                locked_until: None,
+            });
+        }
+    }
+
+    /// Clicks of a url in the range, in (clicked_at, id) order, from `cursor` on.
+    fn page_clicks(
+        &self,
+        url_id: &str,
+        range: &DateRange,
+        cursor: Option<&ClickCursor>,
+    ) -> Vec<&Click> {
+        let mut clicks: Vec<&Click> = self
+            .clicks
+            .iter()
+            .filter(|click| {
+                click.url_id == url_id && in_range(click.clicked_at.date_naive(), range)
+            })
+            .filter(|click| {
+                cursor.is_none_or(|cursor| {
+                    (click.clicked_at, click.id.as_str()) >= (cursor.clicked_at, cursor.id.as_str())
+                })
+            })
+            .collect();
+        clicks.sort_by(|a, b| (a.clicked_at, &a.id).cmp(&(b.clicked_at, &b.id)));
+        clicks
+    }
+
+    fn purge_clicks_before(&mut self, cutoff: NaiveDate) -> u64 {
+        for ((_, day), total) in self.daily_totals.iter_mut() {
+            if *day < cutoff {
+                total.ips_purged = true;
+            }
+        }
+        self.daily_rollups
+            .retain(|(_, day, dimension, _), _| !(dimension == "ip" && *day < cutoff));
+
+        let before = self.clicks.len();
+        self.clicks
+            .retain(|click| click.clicked_at.date_naive() >= cutoff);
+
+        (before - self.clicks.len()) as u64
+    }
+
+    /// Daily totals and rollups computed straight from the raw clicks.
+    fn aggregate_clicks(&self) -> RawAggregates {
+        let mut totals: BTreeMap<(String, NaiveDate), DailyTotal> = BTreeMap::new();
+        let mut ips: HashMap<(String, NaiveDate), HashSet<&str>> = HashMap::new();
+        let mut rollups = BTreeMap::new();
+
+        for click in &self.clicks {
+            let day = click.clicked_at.date_naive();
+            totals
+                .entry((click.url_id.clone(), day))
+                .or_default()
+                .clicks += 1;
+
+            if let Some(ip) = click.ip_address.as_deref() {
+                ips.entry((click.url_id.clone(), day))
+                    .or_default()
+                    .insert(ip);
+            }
+
+            for (dimension, _) in DIMENSIONS {
+                if let Some(value) = dimension_value(click, dimension) {
+                    *rollups
+                        .entry((
+                            click.url_id.clone(),
+                            day,
+                            dimension.to_string(),
+                            value.to_string(),
+                        ))
+                        .or_default() += 1;
+                }
+            }
+        }
+
+        for (key, ips) in ips {
+            if let Some(total) = totals.get_mut(&key) {
+                total.unique_ips = ips.len() as i64;
+            }
+        }
+
+        (totals, rollups)
+    }
+
+    /// Recomputes the rollups of every day that still has raw clicks. Purged
+    /// days are left alone since there is nothing to rebuild them from.
+    fn rebuild_rollups(&mut self) {
+        let (raw_totals, raw_rollups) = self.aggregate_clicks();
+
+        let purged: HashSet<(String, NaiveDate)> = self
+            .daily_totals
+            .iter()
+            .filter(|(_, total)| total.ips_purged)
+            .map(|(key, _)| key.clone())
+            .collect();
+        self.daily_rollups
+            .retain(|(id, day, _, _), _| purged.contains(&(id.clone(), *day)));
+        self.daily_totals.retain(|_, total| total.ips_purged);
+
+        for (key, raw) in raw_totals {
+            let total = self.daily_totals.entry(key).or_default();
+            total.clicks += raw.clicks;
+            total.unique_ips += raw.unique_ips;
+        }
+        for (key, clicks) in raw_rollups {
+            *self.daily_rollups.entry(key).or_default() += clicks;
+        }
+    }
+
+    /// Compares the rollups of every day that still has raw clicks against
+    /// aggregates computed straight from the clicks.
+    fn verify_rollups(&self) -> RollupReport {
+        let (raw_totals, raw_rollups) = self.aggregate_clicks();
+
+        let days_checked = self
+            .daily_totals
+            .values()
+            .filter(|total| !total.ips_purged)
+            .count() as i64;
+
+        let raw_totals: BTreeSet<_> = raw_totals.into_iter().collect();
+        let stored_totals: BTreeSet<_> = self
+            .daily_totals
+            .iter()
+            .filter(|(_, total)| !total.ips_purged)
+            .map(|(key, total)| (key.clone(), *total))
+            .collect();
+        let mismatched_totals = symmetric_difference(&raw_totals, &stored_totals);
+
+        let raw_rollups: BTreeSet<_> = raw_rollups.into_iter().collect();
+        let stored_rollups: BTreeSet<_> = self
+            .daily_rollups
+            .iter()
+            .filter(|((id, day, _, _), _)| {
+                self.daily_totals
+                    .get(&(id.clone(), *day))
+                    .is_some_and(|total| !total.ips_purged)
+            })
+            .map(|(key, clicks)| (key.clone(), *clicks))
+            .collect();
+        let mismatched_rollups = symmetric_difference(&raw_rollups, &stored_rollups);
+
+        RollupReport {
+            consistent: mismatched_totals == 0 && mismatched_rollups == 0,
+            days_checked,
+            mismatched_totals,
+            mismatched_rollups,
+        }
+    }
+
+    fn webhook(&self, id: &str) -> Option<&StoredWebhook> {
+        self.webhooks.iter().find(|stored| stored.webhook.id == id)
+    }
+
+    fn delivery_mut(&mut self, id: &str) -> Option<&mut WebhookDelivery> {
+        self.webhook_deliveries
+            .iter_mut()
+            .find(|delivery| delivery.id == id)
+    }
+}
+
+/// The links a stats query aggregates over.
+enum LinkSelector<'a> {
+    Url(&'a str),
+    Filter(&'a LinkFilter),
+}
+
+/// Links and date range of a stats query. Past days are read from the daily
+/// rollups, only today's clicks are aggregated from the raw clicks.
+struct Selection<'a> {
+    tables: &'a Tables,
+    url_ids: HashSet<String>,
+    range: DateRange,
+    today: NaiveDate,
+}
+
+impl Selection<'_> {
+    fn today_clicks(&self) -> impl Iterator<Item = &Click> {
+        self.tables.clicks.iter().filter(|click| {
+            let day = click.clicked_at.date_naive();
+            self.url_ids.contains(&click.url_id) && day >= self.today && in_range(day, &self.range)
+        })
+    }
+
+    fn past_totals(&self) -> impl Iterator<Item = (&String, NaiveDate, &DailyTotal)> {
+        self.tables
+            .daily_totals
+            .iter()
+            .filter(|((url_id, day), _)| self.is_past(url_id, *day))
+            .map(|((url_id, day), total)| (url_id, *day, total))
+    }
+
+    fn past_rollups(&self, dimension: &str) -> impl Iterator<Item = (&str, i64)> {
+        self.tables
+            .daily_rollups
+            .iter()
+            .filter(move |((url_id, day, rollup, _), _)| {
+                rollup == dimension && self.is_past(url_id, *day)
+            })
+            .map(|((_, _, _, value), clicks)| (value.as_str(), *clicks))
+    }
+
+    fn is_past(&self, url_id: &str, day: NaiveDate) -> bool {
+        self.url_ids.contains(url_id) && day < self.today && in_range(day, &self.range)
+    }
+
+    fn total_clicks(&self) -> i64 {
+        self.today_clicks().count() as i64
+            + self
+                .past_totals()
+                .map(|(_, _, total)| total.clicks)
+                .sum::<i64>()
+    }
+
+    // distinct across days where per-ip rollups exist, purged days only kept a per-day count
+    fn unique_ips(&self) -> i64 {
+        let ips: HashSet<&str> = self
+            .past_rollups("ip")
+            .map(|(ip, _)| ip)
+            .chain(
+                self.today_clicks()
+                    .filter_map(|click| click.ip_address.as_deref()),
+            )
+            .collect();
+        let purged: i64 = self
+            .past_totals()
+            .filter(|(_, _, total)| total.ips_purged)
+            .map(|(_, _, total)| total.unique_ips)
+            .sum();
+
+        ips.len() as i64 + purged
+    }
+
+    fn clicks_by_date(&self) -> Vec<DateCount> {
+        let mut counts: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+        for click in self.today_clicks() {
+            *counts.entry(click.clicked_at.date_naive()).or_default() += 1;
+        }
+        for (_, day, total) in self.past_totals() {
+            *counts.entry(day).or_default() += total.clicks;
+        }
+
+        counts
+            .into_iter()
+            .rev()
+            .take(30)
+            .map(|(date, count)| DateCount { date, count })
+            .collect()
+    }
+
+    /// Top 10 values of a rollup dimension, most clicked first.
+    fn top_values(&self, dimension: &str) -> Vec<(String, i64)> {
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for value in self
+            .today_clicks()
+            .filter_map(|click| dimension_value(click, dimension))
+        {
+            *counts.entry(value).or_default() += 1;
+        }
+        for (value, clicks) in self.past_rollups(dimension) {
+            *counts.entry(value).or_default() += clicks;
+        }
+
+        let mut counts: Vec<(String, i64)> = counts
+            .into_iter()
+            .map(|(value, count)| (value.to_string(), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(10);
+        counts
+    }
+
+    fn top_links(&self) -> Vec<LinkClickCount> {
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for click in self.today_clicks() {
+            *counts.entry(&click.url_id).or_default() += 1;
+        }
+        for (url_id, _, total) in self.past_totals() {
+            *counts.entry(url_id).or_default() += total.clicks;
+        }
+
+        let mut links: Vec<LinkClickCount> = counts
+            .into_iter()
+            .filter_map(|(url_id, clicks)| {
+                self.tables.urls.get(url_id).map(|stored| LinkClickCount {
+                    short_code: stored.url.short_code.clone(),
+                    original_url: stored.url.original_url.clone(),
+                    clicks,
+                })
+            })
+            .collect();
+        links.sort_by(|a, b| {
+            b.clicks
+                .cmp(&a.clicks)
+                .then_with(|| a.short_code.cmp(&b.short_code))
+        });
+        links.truncate(10);
+        links
+    }
+
+    fn click_stats(&self) -> ClickStats {
+        ClickStats {
+            total_clicks: self.total_clicks(),
+            unique_ips: self.unique_ips(),
+            clicks_by_date: self.clicks_by_date(),
+            top_countries: self
+                .top_values("country")
+                .into_iter()
+                .map(|(country, count)| CountryCount { country, count })
+                .collect(),
+            top_referers: self
+                .top_values("referer")
+                .into_iter()
+                .map(|(referer, count)| RefererCount { referer, count })
+                .collect(),
+            top_referer_domains: self
+                .top_values("referer_domain")
+                .into_iter()
+                .map(|(domain, count)| RefererDomainCount { domain, count })
+                .collect(),
+            channels: self
+                .top_values("channel")
+                .into_iter()
+                .map(|(channel, count)| ChannelCount { channel, count })
+                .collect(),
+            top_devices: self
+                .top_values("device")
+                .into_iter()
+                .map(|(device, count)| DeviceCount { device, count })
+                .collect(),
+            metadata: None,
+        }
+    }
+}
+
+#[async_trait]
+impl LinkStore for MemoryStore {
+    async fn create_url(&self, new_url: &NewUrl) -> AppResult<Url> {
+        self.tables().insert_url(new_url)
+    }
+
+    async fn create_urls(&self, new_urls: &[NewUrl]) -> AppResult<Vec<Url>> {
+        let mut tables = self.tables();
+
+        // check every code up front so a conflict leaves nothing behind
+        let mut codes = HashSet::new();
+        for new_url in new_urls {
+            if tables.url_ids_by_code.contains_key(&new_url.short_code)
+                || !codes.insert(new_url.short_code.as_str())
+            {
+                return Err(AppError::CodeAlreadyExists);
+            }
+        }
+
+        new_urls
+            .iter()
+            .map(|new_url| tables.insert_url(new_url))
+            .collect()
+    }
+
+    async fn get_url_by_code(&self, short_code: &str) -> AppResult<Url> {
+        self.tables()
+            .url_by_code(short_code)
+            .map(|stored| stored.url.clone())
+            .ok_or(AppError::UrLNotFound)
+    }
+
+    async fn code_exists(&self, short_code: &str) -> AppResult<bool> {
+        Ok(self.tables().url_ids_by_code.contains_key(short_code))
+    }
+
+    async fn get_url_tags(&self, url_id: &str) -> AppResult<Vec<String>> {
+        Ok(self.tables().tags_of(url_id))
+    }
+
+    async fn list_all_urls(&self) -> AppResult<Vec<Url>> {
+        Ok(self.tables().urls_newest_first())
+    }
+
+    async fn list_link_records(&self) -> AppResult<Vec<LinkRecord>> {
+        let tables = self.tables();
+
+        Ok(tables
+            .urls_newest_first()
+            .into_iter()
+            .map(|url| LinkRecord {
+                tags: tables.tags_of(&url.id),
+                url: url.original_url,
+                code: url.short_code,
+                expires_at: url.expires_at,
+                created_at: Some(url.created_at),
+                campaign: url.campaign,
+                owner: url.owner,
+            })
+            .collect())
+    }
+
+    async fn delete_url_by_code(&self, short_code: &str) -> AppResult<()> {
+        let removed = self
+            .tables()
+            .remove_urls(|url| url.short_code == short_code);
+
+        if removed.is_empty() {
+            return Err(AppError::UrLNotFound);
+        }
+
+        Ok(())
+    }
+
+    async fn delete_urls_expired_before(&self, cutoff: DateTime<Utc>) -> AppResult<Vec<String>> {
+        Ok(self
+            .tables()
+            .remove_urls(|url| url.expires_at.is_some_and(|expires_at| expires_at < cutoff))
+            .into_iter()
+            .map(|url| url.short_code)
+            .collect())
+    }
+
+    async fn take_newly_expired_urls(&self, now: DateTime<Utc>) -> AppResult<Vec<Url>> {
+        let mut tables = self.tables();
+
+        Ok(tables
+            .urls
+            .values_mut()
+            .filter(|stored| {
+                !stored.expiry_notified
+                    && stored
+                        .url
+                        .expires_at
+                        .is_some_and(|expires_at| expires_at <= now)
+            })
+            .map(|stored| {
+                stored.expiry_notified = true;
+                stored.url.clone()
+            })
+            .collect())
+    }
+
+    async fn click_counts(&self, url_ids: &[&str]) -> AppResult<Vec<(String, i64)>> {
+        let tables = self.tables();
+
+        Ok(url_ids
+            .iter()
+            .filter_map(|id| tables.urls.get(*id))
+            .map(|stored| (stored.url.id.clone(), i64::from(stored.url.click_count)))
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ClickStore for MemoryStore {
+    async fn record_clicks(
+        &self,
+        clicks: &[NewClick],
+        outbox: &ClickOutbox,
+    ) -> AppResult<Vec<Click>> {
+        self.tables().record_clicks(clicks, outbox)
+    }
+
+    async fn get_url_stats(&self, url_id: String, range: &DateRange) -> AppResult<ClickStats> {
+        Ok(self
+            .tables()
+            .select(LinkSelector::Url(&url_id), range)
+            .click_stats())
+    }
+
+    async fn get_aggregate_stats(
+        &self,
+        filter: &LinkFilter,
+        range: &DateRange,
+    ) -> AppResult<AggregateStats> {
+        let tables = self.tables();
+        let selection = tables.select(LinkSelector::Filter(filter), range);
+
+        Ok(AggregateStats {
+            total_links: selection.url_ids.len() as i64,
+            top_links: selection.top_links(),
+            stats: selection.click_stats(),
+        })
+    }
+
+    async fn get_link_series(&self, url: &Url, range: &DateRange) -> AppResult<LinkSeries> {
+        let tables = self.tables();
+        let selection = tables.select(LinkSelector::Url(&url.id), range);
+
+        Ok(LinkSeries {
+            short_code: url.short_code.clone(),
+            total_clicks: selection.total_clicks(),
+            clicks_by_date: selection.clicks_by_date(),
+        })
+    }
+
+    async fn clicks_missing_channel(&self, limit: i64) -> AppResult<Vec<(String, Option<String>)>> {
+        Ok(self
+            .tables()
+            .clicks
+            .iter()
+            .filter(|click| click.channel.is_none())
+            .take(usize::try_from(limit).unwrap_or(0))
+            .map(|click| (click.id.clone(), click.referer.clone()))
+            .collect())
+    }
+
+    async fn set_click_channels(&self, clicks: &[(String, RefererInfo)]) -> AppResult<()> {
+        let infos: HashMap<&str, &RefererInfo> = clicks
+            .iter()
+            .map(|(id, info)| (id.as_str(), info))
+            .collect();
+
+        for click in self.tables().clicks.iter_mut() {
+            if let Some(info) = infos.get(click.id.as_str()) {
+                click.referer_domain = info.domain.clone();
+                click.channel = Some(info.channel.as_str().to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stream_clicks(
+        &self,
+        url_id: String,
+        range: DateRange,
+        cursor: Option<ClickCursor>,
+        limit: u32,
+    ) -> ReceiverStream<AppResult<Click>> {
+        let page: Vec<Click> = self
+            .tables()
+            .page_clicks(&url_id, &range, cursor.as_ref())
+            .into_iter()
+            .take(limit as usize)
+            .cloned()
+            .collect();
+
+        // the page is already in memory, so the channel holds all of it
+        let (tx, rx) = mpsc::channel(page.len().max(1));
+        for click in page {
+            let _ = tx.try_send(Ok(click));
+        }
+
+        ReceiverStream::new(rx)
+    }
+
+    async fn next_click_cursor(
+        &self,
+        url_id: &str,
+        range: &DateRange,
+        cursor: Option<&ClickCursor>,
+        limit: u32,
+    ) -> AppResult<Option<ClickCursor>> {
+        Ok(self
+            .tables()
+            .page_clicks(url_id, range, cursor)
+            .get(limit as usize)
+            .map(|click| ClickCursor {
+                clicked_at: click.clicked_at,
+                id: click.id.clone(),
+            }))
+    }
+
+    async fn purge_clicks_before(&self, cutoff: NaiveDate) -> AppResult<u64> {
+        Ok(self.tables().purge_clicks_before(cutoff))
+    }
+
+    async fn rebuild_rollups(&self) -> AppResult<RollupReport> {
+        let mut tables = self.tables();
+        tables.rebuild_rollups();
+
+        Ok(tables.verify_rollups())
+    }
+
+    async fn verify_rollups(&self) -> AppResult<RollupReport> {
+        Ok(self.tables().verify_rollups())
+    }
+
+    async fn get_or_create_ip_salt(&self, day: NaiveDate, candidate: &str) -> AppResult<String> {
+        Ok(self
+            .tables()
+            .ip_salts
+            .entry(day)
+            .or_insert_with(|| candidate.to_string())
+            .clone())
+    }
+
+    async fn delete_ip_salts_before(&self, day: NaiveDate) -> AppResult<()> {
+        self.tables()
+            .ip_salts
+            .retain(|salt_day, _| *salt_day >= day);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WebhookStore for MemoryStore {
+    async fn create_webhook(
+        &self,
+        owner: Option<&str>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        click_threshold: Option<i64>,
+    ) -> AppResult<Webhook> {
+        let webhook = Webhook {
+            id: random_id(),
+            owner: owner.map(str::to_string),
+            url: url.to_string(),
+            events: events
+                .iter()
+                .map(WebhookEvent::as_str)
+                .collect::<Vec<_>>()
+                .join(","),
+            click_threshold,
+            created_at: now(),
+        };
+
+        self.tables().webhooks.push(StoredWebhook {
+            webhook: webhook.clone(),
+            secret: secret.to_string(),
+        });
+
+        Ok(webhook)
+    }
+
+    async fn get_webhook(&self, id: &str) -> AppResult<Webhook> {
+        self.tables()
+            .webhook(id)
+            .map(|stored| stored.webhook.clone())
+            .ok_or(AppError::WebhookNotFound)
+    }
+
+    async fn list_webhooks(&self, owner: Option<&str>) -> AppResult<Vec<Webhook>> {
+        let mut webhooks: Vec<Webhook> = self
+            .tables()
+            .webhooks
+            .iter()
+            .map(|stored| &stored.webhook)
+            .filter(|webhook| owner.is_none_or(|owner| webhook.owner.as_deref() == Some(owner)))
+            .cloned()
+            .collect();
+        webhooks.sort_by_key(|webhook| Reverse(webhook.created_at));
+
+        Ok(webhooks)
+    }
+
+    async fn delete_webhook(&self, id: &str) -> AppResult<()> {
+        let mut tables = self.tables();
+
+        if tables.webhook(id).is_none() {
+            return Err(AppError::WebhookNotFound);
+        }
+
+        tables.webhooks.retain(|stored| stored.webhook.id != id);
+        tables
+            .webhook_deliveries
+            .retain(|delivery| delivery.webhook_id != id);
+
+        Ok(())
+    }
+
+    async fn any_webhooks(&self) -> AppResult<bool> {
+        Ok(!self.tables().webhooks.is_empty())
+    }
+
+    async fn subscribed_webhooks(
+        &self,
+        url_id: &str,
+        events: &[WebhookEvent],
+    ) -> AppResult<Vec<Webhook>> {
+        let tables = self.tables();
+        let Some(stored) = tables.urls.get(url_id) else {
+            return Ok(Vec::new());
+        };
+
+        Ok(tables
+            .webhooks
+            .iter()
+            .map(|stored| &stored.webhook)
+            .filter(|webhook| webhook.owner.is_none() || webhook.owner == stored.url.owner)
+            .filter(|webhook| events.iter().any(|event| webhook.subscribes(*event)))
+            .cloned()
+            .collect())
+    }
+
+    async fn enqueue_deliveries(&self, deliveries: &[NewDelivery]) -> AppResult<()> {
+        self.tables().insert_deliveries(deliveries);
+
+        Ok(())
+    }
+
+    async fn due_deliveries(&self, limit: i64, lease: Duration) -> AppResult<Vec<PendingDelivery>> {
+        let now = Utc::now();
+        let locked_until = now + chrono::Duration::from_std(lease).unwrap_or_default();
+        let mut tables = self.tables();
+
+        let mut due: Vec<(DateTime<Utc>, String)> = tables
+            .webhook_deliveries
+            .iter()
+            .filter(|delivery| match delivery.status.as_str() {
+                "pending" => delivery.next_attempt_at <= now,
+                "sending" => delivery.locked_until.is_some_and(|until| until <= now),
+                _ => false,
+            })
+            .map(|delivery| (delivery.next_attempt_at, delivery.id.clone()))
+            .collect();
+        due.sort();
+        due.truncate(usize::try_from(limit).unwrap_or(0));
+
+        let mut claimed = Vec::with_capacity(due.len());
+        for (_, id) in due {
+            let Some(delivery) = tables.delivery_mut(&id) else {
+                continue;
+            };
+            delivery.status = "sending".to_string();
+            delivery.locked_until = Some(locked_until);
+            let delivery = delivery.clone();
+
+            if let Some(stored) = tables.webhook(&delivery.webhook_id) {
+                claimed.push(PendingDelivery {
+                    id: delivery.id,
+                    event: delivery.event,
+                    payload: delivery.payload,
+                    attempts: delivery.attempts,
+                    url: stored.webhook.url.clone(),
+                    secret: stored.secret.clone(),
+                });
+            }
+        }
+
+        Ok(claimed)
+    }
+
+    async fn mark_delivered(&self, id: &str, status_code: u16) -> AppResult<()> {
+        if let Some(delivery) = self.tables().delivery_mut(id) {
+            delivery.status = "delivered".to_string();
+            delivery.attempts += 1;
+            delivery.last_status_code = Some(i64::from(status_code));
+            delivery.last_error = None;
+            delivery.delivered_at = Some(now());
+            delivery.locked_until = None;
+        }
+
+        Ok(())
+    }
+
+    async fn mark_attempt_failed(
+        &self,
+        id: &str,
+        status_code: Option<u16>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> AppResult<()> {
+        let (status, next_attempt_at) = match retry_at {
+            Some(retry_at) => ("pending", retry_at),
+            None => ("failed", now()),
+        };
+
+        if let Some(delivery) = self.tables().delivery_mut(id) {
+            delivery.status = status.to_string();
+            delivery.attempts += 1;
+            delivery.next_attempt_at = next_attempt_at;
+            delivery.last_status_code = status_code.map(i64::from);
+            delivery.last_error = Some(error.to_string());
+            delivery.locked_until = None;
+        }
+
+        Ok(())
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: &str,
+        status: Option<&str>,
+        limit: u32,
+    ) -> AppResult<Vec<WebhookDelivery>> {
+        let tables = self.tables();
+
+        let mut deliveries: Vec<&WebhookDelivery> = tables
+            .webhook_deliveries
+            .iter()
+            .filter(|delivery| delivery.webhook_id == webhook_id)
+            .filter(|delivery| status.is_none_or(|status| delivery.status == status))
+            .collect();
+        deliveries.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+
+        Ok(deliveries
+            .into_iter()
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStore {
+    fn backend(&self) -> Backend {
+        Backend::Memory
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    async fn migrate(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> AppResult<Vec<i64>> {
+        Ok(Vec::new())
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn vacuum(&self) -> AppResult<()> {
+        Ok(())
+    }
+
+    async fn backup(&self, _destination: &Path) -> AppResult<()> {
+        Err(AppError::Validation(
+            "The in-memory store has nothing on disk to back up".to_string(),
+        ))
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: 0,
+            idle: 0,
+            max_connections: 0,
+        }
+    }
+}
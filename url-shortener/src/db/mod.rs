@@ -1 +1,281 @@
-pub mod queries;
+use std::{path::Path, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::migrate::Migrator;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::config::DatabaseConfig;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AggregateStats, Click, ClickCursor, ClickStats, DateRange, LinkFilter, LinkRecord, LinkSeries,
+    NewClick, NewDelivery, NewUrl, PendingDelivery, RollupReport, Url, Webhook, WebhookDelivery,
+    WebhookEvent,
+};
+use crate::services::referer::RefererInfo;
+use crate::services::webhooks::ClickOutbox;
+
+pub mod memory;
+pub mod postgres;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+pub use sqlite::SqliteStore;
+
+/// Rollup dimensions and the `clicks` column each one groups by.
+pub const DIMENSIONS: [(&str, &str); 6] = [
+    ("country", "country"),
+    ("referer", "referer"),
+    ("referer_domain", "referer_domain"),
+    ("channel", "channel"),
+    ("device", "device"),
+    ("ip", "ip_address"),
+];
+
+/// Database engine, picked from the scheme of `DATABASE_URL`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+    Memory,
+}
+
+impl Backend {
+    pub fn from_url(database_url: &str) -> Option<Self> {
+        if database_url.starts_with("sqlite:") {
+            Some(Backend::Sqlite)
+        } else if database_url.starts_with("postgres://")
+            || database_url.starts_with("postgresql://")
+        {
+            Some(Backend::Postgres)
+        } else if database_url.starts_with("memory:") {
+            Some(Backend::Memory)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Postgres => "postgres",
+            Backend::Memory => "memory",
+        }
+    }
+}
+
+/// Reports a link insert that hit the unique index on `short_code` as
+/// `CodeAlreadyExists`, the way the memory store does, instead of a
+/// database error.
+pub(crate) fn code_conflict(error: sqlx::Error) -> AppError {
+    match &error {
+        sqlx::Error::Database(e) if e.is_unique_violation() => AppError::CodeAlreadyExists,
+        _ => AppError::Database(error),
+    }
+}
+
+/// Links, their tags and counters.
+#[async_trait]
+pub trait LinkStore: Send + Sync {
+    async fn create_url(&self, new_url: &NewUrl) -> AppResult<Url>;
+
+    /// Creates every link in one transaction, none of them if one fails.
+    async fn create_urls(&self, new_urls: &[NewUrl]) -> AppResult<Vec<Url>>;
+
+    async fn get_url_by_code(&self, short_code: &str) -> AppResult<Url>;
+
+    async fn code_exists(&self, short_code: &str) -> AppResult<bool>;
+
+    async fn get_url_tags(&self, url_id: &str) -> AppResult<Vec<String>>;
+
+    /// Every link, newest first.
+    async fn list_all_urls(&self) -> AppResult<Vec<Url>>;
+
+    /// Every link with its tags, newest first.
+    async fn list_link_records(&self) -> AppResult<Vec<LinkRecord>>;
+
+    /// Deletes a link, its clicks and aggregates cascade with it.
+    async fn delete_url_by_code(&self, short_code: &str) -> AppResult<()>;
+
+    /// Deletes urls that expired before `cutoff` and returns their short codes.
+    async fn delete_urls_expired_before(&self, cutoff: DateTime<Utc>) -> AppResult<Vec<String>>;
+
+    /// Flags links that expired by `now` and have not been announced yet, and
+    /// returns them.
+    async fn take_newly_expired_urls(&self, now: DateTime<Utc>) -> AppResult<Vec<Url>>;
+
+    /// Click counters of the given links.
+    async fn click_counts(&self, url_ids: &[&str]) -> AppResult<Vec<(String, i64)>>;
+}
+
+/// Recorded clicks, their daily rollups and the salts of hashed visitor IPs.
+#[async_trait]
+pub trait ClickStore: Send + Sync {
+    /// Inserts a batch of clicks, bumps the matching click counters, updates
+    /// the daily rollups and adds the webhook deliveries of `outbox` in a
+    /// single transaction.
+    async fn record_clicks(
+        &self,
+        clicks: &[NewClick],
+        outbox: &ClickOutbox,
+    ) -> AppResult<Vec<Click>>;
+
+    /// Click statistics for a url. Past days are read from the daily rollups,
+    /// only today's clicks are aggregated from the raw clicks.
+    async fn get_url_stats(&self, url_id: String, range: &DateRange) -> AppResult<ClickStats>;
+
+    /// Click statistics summed over every link matching the filter, plus the
+    /// links with the most clicks in the range.
+    async fn get_aggregate_stats(
+        &self,
+        filter: &LinkFilter,
+        range: &DateRange,
+    ) -> AppResult<AggregateStats>;
+
+    /// Total clicks and clicks per day of a single url, for overlaying links.
+    async fn get_link_series(&self, url: &Url, range: &DateRange) -> AppResult<LinkSeries>;
+
+    /// Clicks whose referer has not been classified yet, as (id, referer).
+    async fn clicks_missing_channel(&self, limit: i64) -> AppResult<Vec<(String, Option<String>)>>;
+
+    async fn set_click_channels(&self, clicks: &[(String, RefererInfo)]) -> AppResult<()>;
+
+    /// Streams the clicks of a url in (clicked_at, id) order, starting at
+    /// `cursor`. Rows are pulled as the receiver consumes them.
+    fn stream_clicks(
+        &self,
+        url_id: String,
+        range: DateRange,
+        cursor: Option<ClickCursor>,
+        limit: u32,
+    ) -> ReceiverStream<AppResult<Click>>;
+
+    /// Returns the cursor of the page following the one starting at `cursor`,
+    /// or `None` when that page is the last one.
+    async fn next_click_cursor(
+        &self,
+        url_id: &str,
+        range: &DateRange,
+        cursor: Option<&ClickCursor>,
+        limit: u32,
+    ) -> AppResult<Option<ClickCursor>>;
+
+    /// Deletes raw clicks from before `cutoff`. Their days stay in the
+    /// rollups, minus the per-ip rows, which only keep a unique count.
+    async fn purge_clicks_before(&self, cutoff: NaiveDate) -> AppResult<u64>;
+
+    /// Recomputes the rollups of every day that still has raw clicks.
+    async fn rebuild_rollups(&self) -> AppResult<RollupReport>;
+
+    /// Compares the rollups of every day that still has raw clicks against
+    /// aggregates computed straight from the clicks.
+    async fn verify_rollups(&self) -> AppResult<RollupReport>;
+
+    /// Returns the salt stored for `day`, storing `candidate` if there is none yet.
+    async fn get_or_create_ip_salt(&self, day: NaiveDate, candidate: &str) -> AppResult<String>;
+
+    async fn delete_ip_salts_before(&self, day: NaiveDate) -> AppResult<()>;
+}
+
+/// Webhook subscriptions and their delivery outbox.
+#[async_trait]
+pub trait WebhookStore: Send + Sync {
+    async fn create_webhook(
+        &self,
+        owner: Option<&str>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        click_threshold: Option<i64>,
+    ) -> AppResult<Webhook>;
+
+    async fn get_webhook(&self, id: &str) -> AppResult<Webhook>;
+
+    async fn list_webhooks(&self, owner: Option<&str>) -> AppResult<Vec<Webhook>>;
+
+    async fn delete_webhook(&self, id: &str) -> AppResult<()>;
+
+    async fn any_webhooks(&self) -> AppResult<bool>;
+
+    /// Webhooks of the link's owner, or without an owner, that subscribe to
+    /// any of `events`.
+    async fn subscribed_webhooks(
+        &self,
+        url_id: &str,
+        events: &[WebhookEvent],
+    ) -> AppResult<Vec<Webhook>>;
+
+    /// Adds the outbox rows in one transaction, due right away.
+    async fn enqueue_deliveries(&self, deliveries: &[NewDelivery]) -> AppResult<()>;
+
+    /// Claims up to `limit` due deliveries as `sending` for `lease`, so
+    /// another dispatcher doesn't send them too. Claims not settled by
+    /// [`mark_delivered`](Self::mark_delivered) or
+    /// [`mark_attempt_failed`](Self::mark_attempt_failed) before the lease
+    /// runs out are due again.
+    async fn due_deliveries(&self, limit: i64, lease: Duration) -> AppResult<Vec<PendingDelivery>>;
+
+    async fn mark_delivered(&self, id: &str, status_code: u16) -> AppResult<()>;
+
+    /// Records a failed attempt, retrying at `retry_at` or giving up when it is `None`.
+    async fn mark_attempt_failed(
+        &self,
+        id: &str,
+        status_code: Option<u16>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> AppResult<()>;
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: &str,
+        status: Option<&str>,
+        limit: u32,
+    ) -> AppResult<Vec<WebhookDelivery>>;
+}
+
+/// Connection pool usage, exported as metrics.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: u32,
+    pub max_connections: u32,
+}
+
+/// A database backend holding everything the server persists.
+#[async_trait]
+pub trait Storage: LinkStore + ClickStore + WebhookStore {
+    fn backend(&self) -> Backend;
+
+    /// Migrations embedded at compile time, the schema this binary expects.
+    fn migrator(&self) -> &'static Migrator;
+
+    async fn migrate(&self) -> anyhow::Result<()>;
+
+    /// Versions of embedded migrations that have not been applied successfully.
+    async fn pending_migrations(&self) -> AppResult<Vec<i64>>;
+
+    async fn ping(&self) -> AppResult<()>;
+
+    async fn vacuum(&self) -> AppResult<()>;
+
+    /// Writes a consistent snapshot of the database to `destination`, which
+    /// must not exist yet, while reads and writes carry on.
+    async fn backup(&self, destination: &Path) -> AppResult<()>;
+
+    fn pool_stats(&self) -> PoolStats;
+}
+
+/// Opens the backend matching the scheme of `config.url`.
+pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Arc<dyn Storage>> {
+    match Backend::from_url(&config.url) {
+        Some(Backend::Postgres) => Ok(Arc::new(PostgresStore::connect(config).await?)),
+        Some(Backend::Sqlite) => Ok(Arc::new(SqliteStore::connect(config).await?)),
+        Some(Backend::Memory) => {
+            tracing::warn!("Using the in-memory store, nothing is persisted across restarts");
+            Ok(Arc::new(MemoryStore::new()))
+        }
+        None => anyhow::bail!("Unsupported database URL '{}'", config.url),
+    }
+}
@@ -0,0 +1,295 @@
+use std::{path::Path, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{
+    PgPool,
+    migrate::Migrator,
+    postgres::{PgConnectOptions, PgPoolOptions},
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::config::DatabaseConfig;
+use crate::db::{Backend, ClickStore, LinkStore, PoolStats, Storage, WebhookStore};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AggregateStats, Click, ClickCursor, ClickStats, DateRange, LinkFilter, LinkRecord, LinkSeries,
+    NewClick, NewDelivery, NewUrl, PendingDelivery, RollupReport, Url, Webhook, WebhookDelivery,
+    WebhookEvent,
+};
+use crate::services::referer::RefererInfo;
+use crate::services::webhooks::ClickOutbox;
+
+pub mod queries;
+pub mod rollups;
+pub mod webhooks;
+
+/// Migrations embedded at compile time, the schema this binary expects.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// A shared Postgres database, lets several instances serve the same links.
+#[derive(Clone)]
+pub struct PostgresStore {
+    pool: PgPool,
+}
+
+impl PostgresStore {
+    pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        // days of clicks and rollups are UTC dates
+        let connecting_options = PgConnectOptions::from_str(&config.url)
+            .context("Invalid Postgres database URL")?
+            .options([("timezone", "UTC")]);
+
+        // the URL may carry a password, only log where we connect to
+        let target = format!(
+            "{}:{}/{}",
+            connecting_options.get_host(),
+            connecting_options.get_port(),
+            connecting_options.get_database().unwrap_or_default()
+        );
+
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect_with(connecting_options)
+            .await
+            .with_context(|| format!("Failed to connect to database at {}", target))?;
+
+        tracing::info!("Connected to Postgres at {}", target);
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl LinkStore for PostgresStore {
+    async fn create_url(&self, new_url: &NewUrl) -> AppResult<Url> {
+        queries::create_url(&self.pool, new_url).await
+    }
+
+    async fn create_urls(&self, new_urls: &[NewUrl]) -> AppResult<Vec<Url>> {
+        queries::create_urls(&self.pool, new_urls).await
+    }
+
+    async fn get_url_by_code(&self, short_code: &str) -> AppResult<Url> {
+        queries::get_url_by_code(&self.pool, short_code).await
+    }
+
+    async fn code_exists(&self, short_code: &str) -> AppResult<bool> {
+        queries::code_exists(&self.pool, short_code).await
+    }
+
+    async fn get_url_tags(&self, url_id: &str) -> AppResult<Vec<String>> {
+        queries::get_url_tags(&self.pool, url_id).await
+    }
+
+    async fn list_all_urls(&self) -> AppResult<Vec<Url>> {
+        queries::list_all_urls(&self.pool).await
+    }
+
+    async fn list_link_records(&self) -> AppResult<Vec<LinkRecord>> {
+        queries::list_link_records(&self.pool).await
+    }
+
+    async fn delete_url_by_code(&self, short_code: &str) -> AppResult<()> {
+        queries::delete_url_by_code(&self.pool, short_code).await
+    }
+
+    async fn delete_urls_expired_before(&self, cutoff: DateTime<Utc>) -> AppResult<Vec<String>> {
+        queries::delete_urls_expired_before(&self.pool, cutoff).await
+    }
+
+    async fn take_newly_expired_urls(&self, now: DateTime<Utc>) -> AppResult<Vec<Url>> {
+        webhooks::take_newly_expired_urls(&self.pool, now).await
+    }
+
+    async fn click_counts(&self, url_ids: &[&str]) -> AppResult<Vec<(String, i64)>> {
+        webhooks::click_counts(&self.pool, url_ids).await
+    }
+}
+
+#[async_trait]
+impl ClickStore for PostgresStore {
+    async fn record_clicks(
+        &self,
+        clicks: &[NewClick],
+        outbox: &ClickOutbox,
+    ) -> AppResult<Vec<Click>> {
+        queries::record_clicks(&self.pool, clicks, outbox).await
+    }
+
+    async fn get_url_stats(&self, url_id: String, range: &DateRange) -> AppResult<ClickStats> {
+        queries::get_url_stats(&self.pool, url_id, range).await
+    }
+
+    async fn get_aggregate_stats(
+        &self,
+        filter: &LinkFilter,
+        range: &DateRange,
+    ) -> AppResult<AggregateStats> {
+        queries::get_aggregate_stats(&self.pool, filter, range).await
+    }
+
+    async fn get_link_series(&self, url: &Url, range: &DateRange) -> AppResult<LinkSeries> {
+        queries::get_link_series(&self.pool, url, range).await
+    }
+
+    async fn clicks_missing_channel(&self, limit: i64) -> AppResult<Vec<(String, Option<String>)>> {
+        queries::clicks_missing_channel(&self.pool, limit).await
+    }
+
+    async fn set_click_channels(&self, clicks: &[(String, RefererInfo)]) -> AppResult<()> {
+        queries::set_click_channels(&self.pool, clicks).await
+    }
+
+    fn stream_clicks(
+        &self,
+        url_id: String,
+        range: DateRange,
+        cursor: Option<ClickCursor>,
+        limit: u32,
+    ) -> ReceiverStream<AppResult<Click>> {
+        queries::stream_clicks(self.pool.clone(), url_id, range, cursor, limit)
+    }
+
+    async fn next_click_cursor(
+        &self,
+        url_id: &str,
+        range: &DateRange,
+        cursor: Option<&ClickCursor>,
+        limit: u32,
+    ) -> AppResult<Option<ClickCursor>> {
+        queries::next_click_cursor(&self.pool, url_id, range, cursor, limit).await
+    }
+
+    async fn purge_clicks_before(&self, cutoff: NaiveDate) -> AppResult<u64> {
+        rollups::purge_clicks_before(&self.pool, cutoff).await
+    }
+
+    async fn rebuild_rollups(&self) -> AppResult<RollupReport> {
+        rollups::rebuild(&self.pool).await
+    }
+
+    async fn verify_rollups(&self) -> AppResult<RollupReport> {
+        rollups::verify(&self.pool).await
+    }
+
+    async fn get_or_create_ip_salt(&self, day: NaiveDate, candidate: &str) -> AppResult<String> {
+        queries::get_or_create_ip_salt(&self.pool, day, candidate).await
+    }
+
+    async fn delete_ip_salts_before(&self, day: NaiveDate) -> AppResult<()> {
+        queries::delete_ip_salts_before(&self.pool, day).await
+    }
+}
+
+#[async_trait]
+impl WebhookStore for PostgresStore {
+    async fn create_webhook(
+        &self,
+        owner: Option<&str>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        click_threshold: Option<i64>,
+    ) -> AppResult<Webhook> {
+        webhooks::create_webhook(&self.pool, owner, url, secret, events, click_threshold).await
+    }
+
+    async fn get_webhook(&self, id: &str) -> AppResult<Webhook> {
+        webhooks::get_webhook(&self.pool, id).await
+    }
+
+    async fn list_webhooks(&self, owner: Option<&str>) -> AppResult<Vec<Webhook>> {
+        webhooks::list_webhooks(&self.pool, owner).await
+    }
+
+    async fn delete_webhook(&self, id: &str) -> AppResult<()> {
+        webhooks::delete_webhook(&self.pool, id).await
+    }
+
+    async fn any_webhooks(&self) -> AppResult<bool> {
+        webhooks::any_webhooks(&self.pool).await
+    }
+
+    async fn subscribed_webhooks(
+        &self,
+        url_id: &str,
+        events: &[WebhookEvent],
+    ) -> AppResult<Vec<Webhook>> {
+        webhooks::subscribed_webhooks(&self.pool, url_id, events).await
+    }
+
+    async fn enqueue_deliveries(&self, deliveries: &[NewDelivery]) -> AppResult<()> {
+        webhooks::enqueue_deliveries(&self.pool, deliveries).await
+    }
+
+    async fn due_deliveries(&self, limit: i64, lease: Duration) -> AppResult<Vec<PendingDelivery>> {
+        webhooks::due_deliveries(&self.pool, limit, lease).await
+    }
+
+    async fn mark_delivered(&self, id: &str, status_code: u16) -> AppResult<()> {
+        webhooks::mark_delivered(&self.pool, id, status_code).await
+    }
+
+    async fn mark_attempt_failed(
+        &self,
+        id: &str,
+        status_code: Option<u16>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> AppResult<()> {
+        webhooks::mark_attempt_failed(&self.pool, id, status_code, error, retry_at).await
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: &str,
+        status: Option<&str>,
+        limit: u32,
+    ) -> AppResult<Vec<WebhookDelivery>> {
+        webhooks::list_deliveries(&self.pool, webhook_id, status, limit).await
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStore {
+    fn backend(&self) -> Backend {
+        Backend::Postgres
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    async fn migrate(&self) -> anyhow::Result<()> {
+        MIGRATOR.run(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> AppResult<Vec<i64>> {
+        queries::pending_migrations(&self.pool).await
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        queries::ping(&self.pool).await
+    }
+
+    async fn vacuum(&self) -> AppResult<()> {
+        queries::vacuum(&self.pool).await
+    }
+
+    async fn backup(&self, _destination: &Path) -> AppResult<()> {
+        Err(AppError::Validation(
+            "Backups are taken of SQLite databases only, use pg_dump for PostgreSQL".to_string(),
+        ))
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max_connections: self.pool.options().get_max_connections(),
+        }
+    }
+}
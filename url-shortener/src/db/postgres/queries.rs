@@ -0,0 +1,698 @@
+use super::{MIGRATOR, rollups, webhooks};
+use crate::db::code_conflict;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    AggregateStats, ChannelCount, Click, ClickCursor, ClickStats, CountryCount, DateCount,
+    DateRange, DeviceCount, LinkClickCount, LinkFilter, LinkRecord, LinkSeries, NewClick, NewUrl,
+    RefererCount, RefererDomainCount, Url,
+};
+use crate::services::referer::RefererInfo;
+use crate::services::webhooks::ClickOutbox;
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, SubsecRound, Utc};
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder, postgres::PgRow};
+use tokio::sync::mpsc;
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+
+const EXPORT_BUFFER_SIZE: usize = 256;
+
+pub async fn create_url(pool: &PgPool, new_url: &NewUrl) -> AppResult<Url> {
+    let mut tx = pool.begin().await?;
+    let url = insert_url(&mut tx, new_url).await?;
+    tx.commit().await?;
+
+    Ok(url)
+}
+
+pub async fn create_urls(pool: &PgPool, new_urls: &[NewUrl]) -> AppResult<Vec<Url>> {
+    let mut tx = pool.begin().await?;
+    let mut urls = Vec::with_capacity(new_urls.len());
+    for new_url in new_urls {
+        urls.push(insert_url(&mut tx, new_url).await?);
+    }
+    tx.commit().await?;
+
+    Ok(urls)
+}
+
+/// Inserts a link and its tags on an open connection, so callers can group
+/// several links in one transaction.
+pub async fn insert_url(conn: &mut PgConnection, new_url: &NewUrl) -> AppResult<Url> {
+    let url = sqlx::query_as::<_, Url>(
+        r#"
+        INSERT INTO urls (original_url, short_code, expires_at, campaign, owner, created_at)
+        VALUES ($1, $2, $3, $4, $5, COALESCE($6, date_trunc('second', now())))
+        RETURNING *
+        "#,
+    )
+    .bind(&new_url.original_url)
+    .bind(&new_url.short_code)
+    .bind(new_url.expires_at)
+    .bind(&new_url.campaign)
+    .bind(&new_url.owner)
+    .bind(new_url.created_at.map(|dt| dt.trunc_subsecs(0)))
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(code_conflict)?;
+
+    for tag in &new_url.tags {
+        sqlx::query(
+            r#"
+            INSERT INTO url_tags (url_id, tag) VALUES ($1, $2) ON CONFLICT DO NOTHING
+            "#,
+        )
+        .bind(&url.id)
+        .bind(tag)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(url)
+}
+
+pub async fn get_url_by_code(pool: &PgPool, short_code: &str) -> AppResult<Url> {
+    let url = sqlx::query_as::<_, Url>(
+        r#"
+        SELECT * FROM urls WHERE short_code = $1
+        "#,
+    )
+    .bind(short_code)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::UrLNotFound)?;
+
+    Ok(url)
+}
+
+pub async fn code_exists(pool: &PgPool, short_code: &str) -> AppResult<bool> {
+    let exists = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM urls WHERE short_code = $1)
+        "#,
+    )
+    .bind(short_code)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+pub async fn get_url_tags(pool: &PgPool, url_id: &str) -> AppResult<Vec<String>> {
+    let tags = sqlx::query_scalar(
+        r#"
+        SELECT tag FROM url_tags WHERE url_id = $1 ORDER BY tag
+        "#,
+    )
+    .bind(url_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(tags)
+}
+
+/// Every link with its tags, newest first.
+pub async fn list_link_records(pool: &PgPool) -> AppResult<Vec<LinkRecord>> {
+    let urls = list_all_urls(pool).await?;
+
+    let tags: Vec<(String, String)> = sqlx::query_as(
+        r#"
+        SELECT url_id, tag FROM url_tags ORDER BY tag
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let mut tags_by_url: HashMap<String, Vec<String>> = HashMap::new();
+    for (url_id, tag) in tags {
+        tags_by_url.entry(url_id).or_default().push(tag);
+    }
+
+    Ok(urls
+        .into_iter()
+        .map(|url| LinkRecord {
+            tags: tags_by_url.remove(&url.id).unwrap_or_default(),
+            url: url.original_url,
+            code: url.short_code,
+            expires_at: url.expires_at,
+            created_at: Some(url.created_at),
+            campaign: url.campaign,
+            owner: url.owner,
+        })
+        .collect())
+}
+
+/// Deletes a link, its clicks and aggregates cascade with it.
+pub async fn delete_url_by_code(pool: &PgPool, short_code: &str) -> AppResult<()> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM urls WHERE short_code = $1
+        "#,
+    )
+    .bind(short_code)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::UrLNotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn list_all_urls(pool: &PgPool) -> AppResult<Vec<Url>> {
+    let urls = sqlx::query_as::<_, Url>(
+        r#"
+        SELECT * FROM urls ORDER BY created_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(urls)
+}
+
+/// Inserts a batch of clicks, bumps the matching click counters and updates
+/// the daily rollups in a single transaction.
+pub async fn record_clicks(
+    pool: &PgPool,
+    clicks: &[NewClick],
+    outbox: &ClickOutbox,
+) -> AppResult<Vec<Click>> {
+    let mut tx = pool.begin().await?;
+    let mut recorded = Vec::with_capacity(clicks.len());
+    let mut per_url: HashMap<&str, i32> = HashMap::new();
+
+    for click in clicks {
+        // whole seconds like the SQLite backend, export cursors depend on it
+        let row = sqlx::query_as::<_, Click>(
+            r#"
+            INSERT INTO clicks (
+                url_id, clicked_at, ip_address, user_agent, referer, referer_domain, channel, device
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+        )
+        .bind(&click.url_id)
+        .bind(click.clicked_at.trunc_subsecs(0))
+        .bind(&click.ip_address)
+        .bind(&click.user_agent)
+        .bind(&click.referer)
+        .bind(&click.referer_domain)
+        .bind(click.channel)
+        .bind(click.device)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        recorded.push(row);
+        *per_url.entry(click.url_id.as_str()).or_default() += 1;
+    }
+
+    // a fixed order keeps concurrent batches from deadlocking on the counters
+    let mut per_url: Vec<_> = per_url.into_iter().collect();
+    per_url.sort_unstable();
+
+    let mut counters = Vec::with_capacity(per_url.len());
+    for (url_id, count) in per_url {
+        let click_count: Option<i32> = sqlx::query_scalar(
+            r#"
+            UPDATE urls SET click_count = click_count + $1 WHERE id = $2 RETURNING click_count
+            "#,
+        )
+        .bind(count)
+        .bind(url_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        // the link may have been deleted since the click
+        if let Some(click_count) = click_count {
+            counters.push((url_id.to_string(), i64::from(count), i64::from(click_count)));
+        }
+    }
+
+    webhooks::insert_deliveries(&mut tx, &outbox.deliveries(&counters)?).await?;
+
+    rollups::record(&mut tx, clicks).await?;
+
+    tx.commit().await?;
+
+    Ok(recorded)
+}
+
+/// Click statistics for a url. Past days are read from the daily rollups,
+/// only today's clicks are aggregated from the raw `clicks` table.
+pub async fn get_url_stats(
+    pool: &PgPool,
+    url_id: String,
+    range: &DateRange,
+) -> AppResult<ClickStats> {
+    get_click_stats(pool, &LinkSelector::Url(&url_id), range).await
+}
+
+/// Click statistics summed over every link matching the filter, plus the
+/// links with the most clicks in the range.
+pub async fn get_aggregate_stats(
+    pool: &PgPool,
+    filter: &LinkFilter,
+    range: &DateRange,
+) -> AppResult<AggregateStats> {
+    let today = Utc::now().date_naive();
+    let links = LinkSelector::Filter(filter);
+
+    let mut query = QueryBuilder::new("SELECT COUNT(*) FROM urls");
+    push_url_filters(&mut query, filter);
+    let total_links: i64 = query.build_query_scalar().fetch_one(pool).await?;
+
+    let mut query = QueryBuilder::new(
+        "SELECT u.short_code, u.original_url, SUM(c.clicks)::BIGINT AS clicks FROM (SELECT url_id, COUNT(*) AS clicks FROM clicks",
+    );
+    push_today_filters(&mut query, &links, range, today);
+    query.push(" GROUP BY url_id UNION ALL SELECT url_id, SUM(clicks) FROM click_daily_totals");
+    push_past_filters(&mut query, &links, range, today);
+    query.push(
+        " GROUP BY url_id) c JOIN urls u ON u.id = c.url_id GROUP BY u.id ORDER BY clicks DESC LIMIT 10",
+    );
+    let top_links: Vec<LinkClickCount> = query.build_query_as().fetch_all(pool).await?;
+
+    let stats = get_click_stats(pool, &links, range).await?;
+
+    Ok(AggregateStats {
+        total_links,
+        top_links,
+        stats,
+    })
+}
+
+/// Total clicks and clicks per day of a single url, for overlaying links.
+pub async fn get_link_series(pool: &PgPool, url: &Url, range: &DateRange) -> AppResult<LinkSeries> {
+    let today = Utc::now().date_naive();
+    let link = LinkSelector::Url(&url.id);
+
+    Ok(LinkSeries {
+        short_code: url.short_code.clone(),
+        total_clicks: total_clicks(pool, &link, range, today).await?,
+        clicks_by_date: clicks_by_date(pool, &link, range, today).await?,
+    })
+}
+
+async fn get_click_stats(
+    pool: &PgPool,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+) -> AppResult<ClickStats> {
+    let today = Utc::now().date_naive();
+
+    let total_clicks = total_clicks(pool, links, range, today).await?;
+
+    // distinct across days where per-ip rollups exist, purged days only kept a per-day count
+    let mut query = QueryBuilder::new(
+        "SELECT ((SELECT COUNT(DISTINCT ip) FROM (SELECT value AS ip FROM click_daily_rollups",
+    );
+    push_past_filters(&mut query, links, range, today);
+    query.push(" AND dimension = 'ip' UNION SELECT ip_address FROM clicks");
+    push_today_filters(&mut query, links, range, today);
+    query.push(
+        " AND ip_address IS NOT NULL) ips) + (SELECT COALESCE(SUM(unique_ips), 0) FROM click_daily_totals",
+    );
+    push_past_filters(&mut query, links, range, today);
+    query.push(" AND ips_purged))::BIGINT");
+    let unique_ips: i64 = query.build_query_scalar().fetch_one(pool).await?;
+
+    let clicks_by_date = clicks_by_date(pool, links, range, today).await?;
+
+    let top_countries: Vec<CountryCount> =
+        top_dimension_values(pool, links, range, today, "country").await?;
+
+    let top_referers: Vec<RefererCount> =
+        top_dimension_values(pool, links, range, today, "referer").await?;
+
+    let top_referer_domains: Vec<RefererDomainCount> =
+        top_dimension_values(pool, links, range, today, "referer_domain").await?;
+
+    let channels: Vec<ChannelCount> =
+        top_dimension_values(pool, links, range, today, "channel").await?;
+
+    let top_devices: Vec<DeviceCount> =
+        top_dimension_values(pool, links, range, today, "device").await?;
+
+    Ok(ClickStats {
+        total_clicks,
+        unique_ips,
+        clicks_by_date,
+        top_countries,
+        top_referers,
+        top_referer_domains,
+        channels,
+        top_devices,
+        metadata: None,
+    })
+}
+
+async fn total_clicks(
+    pool: &PgPool,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+    today: NaiveDate,
+) -> AppResult<i64> {
+    let mut query = QueryBuilder::new("SELECT ((SELECT COUNT(*) FROM clicks");
+    push_today_filters(&mut query, links, range, today);
+    query.push(") + (SELECT COALESCE(SUM(clicks), 0) FROM click_daily_totals");
+    push_past_filters(&mut query, links, range, today);
+    query.push("))::BIGINT");
+
+    Ok(query.build_query_scalar().fetch_one(pool).await?)
+}
+
+async fn clicks_by_date(
+    pool: &PgPool,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+    today: NaiveDate,
+) -> AppResult<Vec<DateCount>> {
+    let mut query = QueryBuilder::new(
+        "SELECT date, SUM(count)::BIGINT AS count FROM (SELECT clicked_at::date AS date, COUNT(*) AS count FROM clicks",
+    );
+    push_today_filters(&mut query, links, range, today);
+    query.push(
+        " GROUP BY clicked_at::date UNION ALL SELECT day, SUM(clicks) FROM click_daily_totals",
+    );
+    push_past_filters(&mut query, links, range, today);
+    query.push(" GROUP BY day) d GROUP BY date ORDER BY date DESC LIMIT 30");
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+/// Top 10 values of a click column, which doubles as the rollup dimension name.
+async fn top_dimension_values<T>(
+    pool: &PgPool,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+    today: NaiveDate,
+    column: &'static str,
+) -> AppResult<Vec<T>>
+where
+    T: for<'r> sqlx::FromRow<'r, PgRow> + Send + Unpin,
+{
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {column}, SUM(count)::BIGINT AS count FROM (SELECT {column}, COUNT(*) AS count FROM clicks"
+    ));
+    push_today_filters(&mut query, links, range, today);
+    query.push(format!(
+        " AND {column} IS NOT NULL GROUP BY {column} UNION ALL SELECT value, SUM(clicks) FROM click_daily_rollups"
+    ));
+    push_past_filters(&mut query, links, range, today);
+    query.push(" AND dimension = ");
+    query.push_bind(column);
+    query.push(format!(
+        " GROUP BY value) v GROUP BY {column} ORDER BY count DESC LIMIT 10"
+    ));
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+/// Clicks whose referer has not been classified yet, as (id, referer).
+pub async fn clicks_missing_channel(
+    pool: &PgPool,
+    limit: i64,
+) -> AppResult<Vec<(String, Option<String>)>> {
+    let clicks = sqlx::query_as(
+        r#"
+        SELECT id, referer FROM clicks WHERE channel IS NULL LIMIT $1
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(clicks)
+}
+
+pub async fn set_click_channels(pool: &PgPool, clicks: &[(String, RefererInfo)]) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+
+    for (id, info) in clicks {
+        sqlx::query(
+            r#"
+            UPDATE clicks SET referer_domain = $1, channel = $2 WHERE id = $3
+            "#,
+        )
+        .bind(&info.domain)
+        .bind(info.channel.as_str())
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Deletes urls that expired before `cutoff` and returns their short codes.
+pub async fn delete_urls_expired_before(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+) -> AppResult<Vec<String>> {
+    let short_codes = sqlx::query_scalar(
+        r#"
+        DELETE FROM urls WHERE expires_at IS NOT NULL AND expires_at < $1
+        RETURNING short_code
+        "#,
+    )
+    .bind(cutoff)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(short_codes)
+}
+
+pub async fn ping(pool: &PgPool) -> AppResult<()> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
+/// Versions of embedded migrations that have not been applied successfully.
+pub async fn pending_migrations(pool: &PgPool) -> AppResult<Vec<i64>> {
+    let applied: Vec<i64> = sqlx::query_scalar(
+        r#"
+        SELECT version FROM _sqlx_migrations WHERE success
+        "#,
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+pub async fn vacuum(pool: &PgPool) -> AppResult<()> {
+    // VACUUM refuses to run in a transaction, send it as a simple query
+    sqlx::raw_sql("VACUUM ANALYZE").execute(pool).await?;
+    Ok(())
+}
+
+/// Streams the clicks of a url in (clicked_at, id) order, starting at `cursor`.
+/// Rows are pulled from Postgres as the receiver consumes them, so large
+/// exports never sit in memory all at once.
+pub fn stream_clicks(
+    pool: PgPool,
+    url_id: String,
+    range: DateRange,
+    cursor: Option<ClickCursor>,
+    limit: u32,
+) -> ReceiverStream<AppResult<Click>> {
+    let (tx, rx) = mpsc::channel(EXPORT_BUFFER_SIZE);
+
+    tokio::spawn(async move {
+        let mut query = QueryBuilder::new("SELECT * FROM clicks");
+        push_click_filters(&mut query, &LinkSelector::Url(&url_id), &range);
+        push_click_cursor(&mut query, cursor.as_ref());
+        query.push(" ORDER BY clicked_at, id LIMIT ");
+        query.push_bind(limit as i64);
+
+        let mut rows = query.build_query_as::<Click>().fetch(&pool);
+        while let Some(row) = rows.next().await {
+            // receiver is gone when the client disconnects
+            if tx.send(row.map_err(AppError::from)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+/// Returns the cursor of the page following the one starting at `cursor`,
+/// or `None` when that page is the last one.
+pub async fn next_click_cursor(
+    pool: &PgPool,
+    url_id: &str,
+    range: &DateRange,
+    cursor: Option<&ClickCursor>,
+    limit: u32,
+) -> AppResult<Option<ClickCursor>> {
+    let mut query = QueryBuilder::new("SELECT clicked_at, id FROM clicks");
+    push_click_filters(&mut query, &LinkSelector::Url(url_id), range);
+    push_click_cursor(&mut query, cursor);
+    query.push(" ORDER BY clicked_at, id LIMIT 1 OFFSET ");
+    query.push_bind(limit as i64);
+
+    let next: Option<(DateTime<Utc>, String)> = query.build_query_as().fetch_optional(pool).await?;
+
+    Ok(next.map(|(clicked_at, id)| ClickCursor { clicked_at, id }))
+}
+
+/// Returns the salt stored for `day`, storing `candidate` if there is none yet.
+pub async fn get_or_create_ip_salt(
+    pool: &PgPool,
+    day: NaiveDate,
+    candidate: &str,
+) -> AppResult<String> {
+    sqlx::query(
+        r#"
+        INSERT INTO ip_salts (day, salt) VALUES ($1, $2) ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(day)
+    .bind(candidate)
+    .execute(pool)
+    .await?;
+
+    let salt: String = sqlx::query_scalar(
+        r#"
+        SELECT salt FROM ip_salts WHERE day = $1
+        "#,
+    )
+    .bind(day)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(salt)
+}
+
+pub async fn delete_ip_salts_before(pool: &PgPool, day: NaiveDate) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        DELETE FROM ip_salts WHERE day < $1
+        "#,
+    )
+    .bind(day)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// The links a stats query aggregates over.
+enum LinkSelector<'a> {
+    Url(&'a str),
+    Filter(&'a LinkFilter),
+}
+
+fn push_link_filter(query: &mut QueryBuilder<'_, Postgres>, links: &LinkSelector<'_>) {
+    match links {
+        LinkSelector::Url(url_id) => {
+            query.push(" WHERE url_id = ");
+            query.push_bind(url_id.to_string());
+        }
+        LinkSelector::Filter(filter) => {
+            query.push(" WHERE url_id IN (SELECT id FROM urls");
+            push_url_filters(query, filter);
+            query.push(")");
+        }
+    }
+}
+
+fn push_url_filters(query: &mut QueryBuilder<'_, Postgres>, filter: &LinkFilter) {
+    let mut separator = " WHERE ";
+
+    if let Some(owner) = &filter.owner {
+        query.push(separator);
+        query.push("owner = ");
+        query.push_bind(owner.clone());
+        separator = " AND ";
+    }
+
+    if let Some(campaign) = &filter.campaign {
+        query.push(separator);
+        query.push("campaign = ");
+        query.push_bind(campaign.clone());
+        separator = " AND ";
+    }
+
+    if let Some(tag) = &filter.tag {
+        query.push(separator);
+        query.push("id IN (SELECT url_id FROM url_tags WHERE tag = ");
+        query.push_bind(tag.clone());
+        query.push(")");
+    }
+}
+
+fn push_click_filters(
+    query: &mut QueryBuilder<'_, Postgres>,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+) {
+    push_link_filter(query, links);
+
+    // dates compare as midnight UTC, the session time zone set on connect
+    if let Some(from) = range.from {
+        query.push(" AND clicked_at >= ");
+        query.push_bind(from);
+    }
+
+    if let Some(to) = range.to.and_then(|to| to.succ_opt()) {
+        query.push(" AND clicked_at < ");
+        query.push_bind(to);
+    }
+}
+
+fn push_today_filters(
+    query: &mut QueryBuilder<'_, Postgres>,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+    today: NaiveDate,
+) {
+    push_click_filters(query, links, range);
+    query.push(" AND clicked_at >= ");
+    query.push_bind(today);
+}
+
+fn push_past_filters(
+    query: &mut QueryBuilder<'_, Postgres>,
+    links: &LinkSelector<'_>,
+    range: &DateRange,
+    today: NaiveDate,
+) {
+    push_link_filter(query, links);
+    query.push(" AND day < ");
+    query.push_bind(today);
+
+    if let Some(from) = range.from {
+        query.push(" AND day >= ");
+        query.push_bind(from);
+    }
+
+    if let Some(to) = range.to {
+        query.push(" AND day <= ");
+        query.push_bind(to);
+    }
+}
+
+fn push_click_cursor(query: &mut QueryBuilder<'_, Postgres>, cursor: Option<&ClickCursor>) {
+    if let Some(cursor) = cursor {
+        query.push(" AND (clicked_at > ");
+        query.push_bind(cursor.clicked_at);
+        query.push(" OR (clicked_at = ");
+        query.push_bind(cursor.clicked_at);
+        query.push(" AND id >= ");
+        query.push_bind(cursor.id.clone());
+        query.push("))");
+    }
+}
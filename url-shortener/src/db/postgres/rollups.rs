@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use sqlx::{PgConnection, PgPool};
+
+use crate::db::DIMENSIONS;
+use crate::error::AppResult;
+use crate::models::{NewClick, RollupReport};
+
+/// Adds a batch of freshly inserted clicks to the daily totals and rollups.
+pub async fn record(conn: &mut PgConnection, clicks: &[NewClick]) -> AppResult<()> {
+    let mut totals: HashMap<(&str, NaiveDate), i64> = HashMap::new();
+    let mut dimensions: HashMap<(&str, NaiveDate, &str, &str), i64> = HashMap::new();
+
+    for click in clicks {
+        let day = click.clicked_at.date_naive();
+        *totals.entry((&click.url_id, day)).or_default() += 1;
+
+        for (dimension, value) in click.dimensions() {
+            *dimensions
+                .entry((&click.url_id, day, dimension, value))
+                .or_default() += 1;
+        }
+    }
+
+    // rows are locked in key order so concurrent batches cannot deadlock
+    let mut totals: Vec<_> = totals.into_iter().collect();
+    totals.sort_unstable();
+    let mut dimensions: Vec<_> = dimensions.into_iter().collect();
+    dimensions.sort_unstable();
+
+    for ((url_id, day), count) in totals {
+        sqlx::query(
+            r#"
+            INSERT INTO click_daily_totals (url_id, day, clicks)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (url_id, day) DO UPDATE SET
+                clicks = click_daily_totals.clicks + excluded.clicks
+            "#,
+        )
+        .bind(url_id)
+        .bind(day)
+        .bind(count)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for ((url_id, day, dimension, value), count) in dimensions {
+        let clicks: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET
+                clicks = click_daily_rollups.clicks + excluded.clicks
+            RETURNING clicks
+            "#,
+        )
+        .bind(url_id)
+        .bind(day)
+        .bind(dimension)
+        .bind(value)
+        .bind(count)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // an ip seen for the first time that day is a new unique visitor
+        if dimension == "ip" && clicks == count {
+            sqlx::query(
+                r#"
+                UPDATE click_daily_totals SET unique_ips = unique_ips + 1
+                WHERE url_id = $1 AND day = $2
+                "#,
+            )
+            .bind(url_id)
+            .bind(day)
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes raw clicks from before `cutoff`. Their days stay in the rollups,
+/// minus the per-ip rows, which only keep a unique count in the totals.
+pub async fn purge_clicks_before(pool: &PgPool, cutoff: NaiveDate) -> AppResult<u64> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE click_daily_totals SET ips_purged = TRUE WHERE day < $1
+        "#,
+    )
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM click_daily_rollups WHERE dimension = 'ip' AND day < $1
+        "#,
+    )
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await?;
+
+    let purged = sqlx::query(
+        r#"
+        DELETE FROM clicks WHERE clicked_at < $1
+        "#,
+    )
+    .bind(cutoff)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(purged)
+}
+
+/// Recomputes the rollups of every day that still has raw clicks. Purged
+/// days are left alone since there is nothing to rebuild them from.
+pub async fn rebuild(pool: &PgPool) -> AppResult<RollupReport> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM click_daily_rollups
+        WHERE NOT EXISTS (
+            SELECT 1 FROM click_daily_totals t
+            WHERE t.url_id = click_daily_rollups.url_id
+              AND t.day = click_daily_rollups.day
+              AND t.ips_purged
+        )
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM click_daily_totals WHERE NOT ips_purged
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO click_daily_totals (url_id, day, clicks, unique_ips)
+        SELECT url_id, clicked_at::date, COUNT(*), COUNT(DISTINCT ip_address)
+        FROM clicks
+        GROUP BY url_id, clicked_at::date
+        ON CONFLICT (url_id, day) DO UPDATE SET
+            clicks = click_daily_totals.clicks + excluded.clicks,
+            unique_ips = click_daily_totals.unique_ips + excluded.unique_ips
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for (dimension, column) in DIMENSIONS {
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+            SELECT url_id, clicked_at::date, '{dimension}', {column}, COUNT(*)
+            FROM clicks
+            WHERE {column} IS NOT NULL
+            GROUP BY url_id, clicked_at::date, {column}
+            ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET
+                clicks = click_daily_rollups.clicks + excluded.clicks
+            "#
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    verify(pool).await
+}
+
+/// Compares the rollups of every day that still has raw clicks against
+/// aggregates computed straight from the `clicks` table.
+pub async fn verify(pool: &PgPool) -> AppResult<RollupReport> {
+    let days_checked: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM click_daily_totals WHERE NOT ips_purged
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let raw_totals = r#"
+        SELECT url_id, clicked_at::date AS day, COUNT(*), COUNT(DISTINCT ip_address)
+        FROM clicks GROUP BY url_id, clicked_at::date
+    "#;
+    let stored_totals = r#"
+        SELECT url_id, day, clicks, unique_ips FROM click_daily_totals WHERE NOT ips_purged
+    "#;
+    let mismatched_totals = symmetric_difference(pool, raw_totals, stored_totals).await?;
+
+    let raw_rollups = DIMENSIONS
+        .iter()
+        .map(|(dimension, column)| {
+            format!(
+                "SELECT url_id, clicked_at::date, '{dimension}'::text, {column}, COUNT(*) FROM clicks \
+                 WHERE {column} IS NOT NULL GROUP BY url_id, clicked_at::date, {column}"
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    let stored_rollups = r#"
+        SELECT r.url_id, r.day, r.dimension, r.value, r.clicks
+        FROM click_daily_rollups r
+        JOIN click_daily_totals t ON t.url_id = r.url_id AND t.day = r.day
+        WHERE NOT t.ips_purged
+    "#;
+    let mismatched_rollups = symmetric_difference(pool, &raw_rollups, stored_rollups).await?;
+
+    Ok(RollupReport {
+        consistent: mismatched_totals == 0 && mismatched_rollups == 0,
+        days_checked,
+        mismatched_totals,
+        mismatched_rollups,
+    })
+}
+
+/// Number of rows that appear in only one of the two queries.
+async fn symmetric_difference(pool: &PgPool, left: &str, right: &str) -> AppResult<i64> {
+    let count = sqlx::query_scalar(&format!(
+        "SELECT \
+         (SELECT COUNT(*) FROM (SELECT * FROM ({left}) l EXCEPT SELECT * FROM ({right}) r) d) + \
+         (SELECT COUNT(*) FROM (SELECT * FROM ({right}) r EXCEPT SELECT * FROM ({left}) l) d)"
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
@@ -0,0 +1,284 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Postgres, QueryBuilder, Transaction};
+
+use crate::error::{AppError, AppResult};
+use crate::models::{NewDelivery, PendingDelivery, Url, Webhook, WebhookDelivery, WebhookEvent};
+
+pub async fn create_webhook(
+    pool: &PgPool,
+    owner: Option<&str>,
+    url: &str,
+    secret: &str,
+    events: &[WebhookEvent],
+    click_threshold: Option<i64>,
+) -> AppResult<Webhook> {
+    let events = events
+        .iter()
+        .map(WebhookEvent::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let webhook = sqlx::query_as::<_, Webhook>(
+        r#"
+        INSERT INTO webhooks (owner, url, secret, events, click_threshold)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING *
+        "#,
+    )
+    .bind(owner)
+    .bind(url)
+    .bind(secret)
+    .bind(events)
+    .bind(click_threshold)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+pub async fn get_webhook(pool: &PgPool, id: &str) -> AppResult<Webhook> {
+    let webhook = sqlx::query_as::<_, Webhook>(
+        r#"
+        SELECT * FROM webhooks WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::WebhookNotFound)?;
+
+    Ok(webhook)
+}
+
+pub async fn list_webhooks(pool: &PgPool, owner: Option<&str>) -> AppResult<Vec<Webhook>> {
+    let mut query = QueryBuilder::new("SELECT * FROM webhooks");
+    if let Some(owner) = owner {
+        query.push(" WHERE owner = ");
+        query.push_bind(owner.to_string());
+    }
+    query.push(" ORDER BY created_at DESC");
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+pub async fn delete_webhook(pool: &PgPool, id: &str) -> AppResult<()> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM webhooks WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::WebhookNotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn any_webhooks(pool: &PgPool) -> AppResult<bool> {
+    let exists = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM webhooks)
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists)
+}
+
+/// Webhooks of the link's owner, or without an owner, that subscribe to
+/// any of `events`.
+pub async fn subscribed_webhooks(
+    pool: &PgPool,
+    url_id: &str,
+    events: &[WebhookEvent],
+) -> AppResult<Vec<Webhook>> {
+    let events: Vec<&str> = events.iter().map(WebhookEvent::as_str).collect();
+
+    let webhooks = sqlx::query_as::<_, Webhook>(
+        r#"
+        SELECT w.* FROM webhooks w JOIN urls u ON u.id = $1
+        WHERE (w.owner IS NULL OR w.owner = u.owner)
+        AND string_to_array(w.events, ',') && $2::text[]
+        "#,
+    )
+    .bind(url_id)
+    .bind(events)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(webhooks)
+}
+
+/// Adds the outbox rows in one transaction, due right away.
+pub async fn enqueue_deliveries(pool: &PgPool, deliveries: &[NewDelivery]) -> AppResult<()> {
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    insert_deliveries(&mut tx, deliveries).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Outbox inserts shared by [`enqueue_deliveries`] and the click batches.
+pub(super) async fn insert_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    deliveries: &[NewDelivery],
+) -> AppResult<()> {
+    for delivery in deliveries {
+        sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (webhook_id, event, payload) VALUES ($1, $2, $3)
+            "#,
+        )
+        .bind(&delivery.webhook_id)
+        .bind(delivery.event.as_str())
+        .bind(&delivery.payload)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Claims up to `limit` due deliveries for `lease`, skipping rows another
+/// dispatcher holds. Claims that outlived their lease are due again.
+pub async fn due_deliveries(
+    pool: &PgPool,
+    limit: i64,
+    lease: Duration,
+) -> AppResult<Vec<PendingDelivery>> {
+    let deliveries = sqlx::query_as::<_, PendingDelivery>(
+        r#"
+        UPDATE webhook_deliveries d
+        SET status = 'sending', locked_until = now() + make_interval(secs => $2)
+        FROM webhooks w
+        WHERE w.id = d.webhook_id AND d.id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE (status = 'pending' AND next_attempt_at <= now())
+               OR (status = 'sending' AND locked_until <= now())
+            ORDER BY next_attempt_at
+            FOR UPDATE SKIP LOCKED
+            LIMIT $1
+        )
+        RETURNING d.id, d.event, d.payload, d.attempts, w.url, w.secret
+        "#,
+    )
+    .bind(limit)
+    .bind(lease.as_secs_f64())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(deliveries)
+}
+
+pub async fn mark_delivered(pool: &PgPool, id: &str, status_code: u16) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = 'delivered', attempts = attempts + 1, last_status_code = $1,
+            last_error = NULL, delivered_at = now(), locked_until = NULL
+        WHERE id = $2
+        "#,
+    )
+    .bind(i64::from(status_code))
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records a failed attempt, retrying at `retry_at` or giving up when it is `None`.
+pub async fn mark_attempt_failed(
+    pool: &PgPool,
+    id: &str,
+    status_code: Option<u16>,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> AppResult<()> {
+    let (status, next_attempt_at) = match retry_at {
+        Some(retry_at) => ("pending", retry_at),
+        None => ("failed", Utc::now()),
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = $1, attempts = attempts + 1, next_attempt_at = $2,
+            last_status_code = $3, last_error = $4, locked_until = NULL
+        WHERE id = $5
+        "#,
+    )
+    .bind(status)
+    .bind(next_attempt_at)
+    .bind(status_code.map(i64::from))
+    .bind(error)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn list_deliveries(
+    pool: &PgPool,
+    webhook_id: &str,
+    status: Option<&str>,
+    limit: u32,
+) -> AppResult<Vec<WebhookDelivery>> {
+    let mut query = QueryBuilder::new("SELECT * FROM webhook_deliveries WHERE webhook_id = ");
+    query.push_bind(webhook_id.to_string());
+    if let Some(status) = status {
+        query.push(" AND status = ");
+        query.push_bind(status.to_string());
+    }
+    query.push(" ORDER BY created_at DESC, id LIMIT ");
+    query.push_bind(i64::from(limit));
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+/// Flags links that expired by `now` and have not been announced yet, and
+/// returns them.
+pub async fn take_newly_expired_urls(pool: &PgPool, now: DateTime<Utc>) -> AppResult<Vec<Url>> {
+    let urls = sqlx::query_as::<_, Url>(
+        r#"
+        UPDATE urls SET expiry_notified = TRUE
+        WHERE NOT expiry_notified AND expires_at IS NOT NULL AND expires_at <= $1
+        RETURNING *
+        "#,
+    )
+    .bind(now)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(urls)
+}
+
+/// Click counters of the given links.
+pub async fn click_counts(pool: &PgPool, url_ids: &[&str]) -> AppResult<Vec<(String, i64)>> {
+    if url_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let counts = sqlx::query_as(
+        r#"
+        SELECT id, click_count::BIGINT FROM urls WHERE id = ANY($1)
+        "#,
+    )
+    .bind(url_ids)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(counts)
+}
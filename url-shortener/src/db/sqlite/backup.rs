@@ -0,0 +1,203 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{
+    Connection, SqliteConnection, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteLockingMode, SqlitePoolOptions},
+};
+
+use super::{MIGRATOR, db_path};
+use crate::config::DatabaseConfig;
+
+/// Outcome of [`restore`].
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    pub database: String,
+    /// Where the database that was replaced has been moved to.
+    pub previous: Option<String>,
+    /// Latest migration applied to the restored database.
+    pub schema_version: i64,
+    /// Migrations the backup predates, applied to it before the swap.
+    pub pending_migrations: Vec<i64>,
+    pub links: i64,
+}
+
+/// Replaces the configured database with the backup at `source`, which is
+/// left untouched. The backup is checked on a copy first: it must pass an
+/// integrity check and every migration it went through must be one of ours,
+/// unchanged. Migrations it predates are applied to the copy before it is
+/// moved into place. With `check_only` nothing is replaced.
+///
+/// The server must be stopped, it would keep writing to the old file.
+pub async fn restore(
+    config: &DatabaseConfig,
+    source: &Path,
+    check_only: bool,
+) -> anyhow::Result<RestoreReport> {
+    if !source.is_file() {
+        anyhow::bail!("Backup {} does not exist", source.display());
+    }
+
+    let target = PathBuf::from(db_path(config));
+    let staging = with_suffix(&target, ".restore");
+    tokio::fs::copy(source, &staging).await.with_context(|| {
+        format!(
+            "Failed to copy {} to {}",
+            source.display(),
+            staging.display()
+        )
+    })?;
+
+    let checked = check(&staging, check_only).await;
+    if check_only || checked.is_err() {
+        let _ = tokio::fs::remove_file(&staging).await;
+    }
+    let (schema_version, pending_migrations, links) =
+        checked.with_context(|| format!("Backup {} was not restored", source.display()))?;
+
+    let mut report = RestoreReport {
+        database: target.display().to_string(),
+        previous: None,
+        schema_version,
+        pending_migrations,
+        links,
+    };
+    if check_only {
+        return Ok(report);
+    }
+
+    if target.exists() {
+        if let Err(e) = checkpoint(&target).await {
+            let _ = tokio::fs::remove_file(&staging).await;
+            return Err(e);
+        }
+
+        let previous = with_suffix(
+            &target,
+            &format!(".before-restore-{}", Utc::now().format("%Y%m%dT%H%M%SZ")),
+        );
+        tokio::fs::rename(&target, &previous).await?;
+        report.previous = Some(previous.display().to_string());
+    }
+
+    // a leftover log of the old file would be replayed into the restored one
+    for suffix in ["-wal", "-shm"] {
+        let _ = tokio::fs::remove_file(with_suffix(&target, suffix)).await;
+    }
+    tokio::fs::rename(&staging, &target).await?;
+
+    Ok(report)
+}
+
+/// Validates the copy and brings its schema up to date unless `check_only`.
+async fn check(path: &Path, check_only: bool) -> anyhow::Result<(i64, Vec<i64>, i64)> {
+    let pool = open(path).await?;
+
+    let integrity: String = sqlx::query_scalar("PRAGMA integrity_check")
+        .fetch_one(&pool)
+        .await?;
+    if integrity != "ok" {
+        anyhow::bail!("Integrity check failed: {}", integrity);
+    }
+
+    let applied: Vec<(i64, bool, Vec<u8>)> = sqlx::query_as(
+        r#"
+        SELECT version, success, checksum FROM _sqlx_migrations ORDER BY version
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .context("Not a url-shortener database, it has no migrations table")?;
+
+    for (version, success, checksum) in &applied {
+        let Some(migration) = MIGRATOR.iter().find(|m| m.version == *version) else {
+            anyhow::bail!(
+                "Migration {} is not in migrations/sqlite, the backup is from a newer version",
+                version
+            );
+        };
+        if !success {
+            anyhow::bail!("Migration {} failed on the backed up database", version);
+        }
+        if migration.checksum.as_ref() != checksum.as_slice() {
+            anyhow::bail!(
+                "Migration {} was changed since the backup was taken",
+                version
+            );
+        }
+    }
+
+    let pending: Vec<i64> = MIGRATOR
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| !applied.iter().any(|(applied, _, _)| applied == version))
+        .collect();
+
+    let mut schema_version = applied.last().map(|(version, _, _)| *version).unwrap_or(0);
+    if !check_only && !pending.is_empty() {
+        MIGRATOR.run(&pool).await?;
+        schema_version = pending.iter().copied().max().unwrap_or(schema_version);
+    }
+
+    let links: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM urls")
+        .fetch_one(&pool)
+        .await?;
+
+    pool.close().await;
+
+    Ok((schema_version, pending, links))
+}
+
+/// Folds the write-ahead log into the database file so that moving the file
+/// aside keeps everything committed to it. Fails while other connections
+/// have the database open, a running server holds some in WAL mode even
+/// when idle.
+async fn checkpoint(path: &Path) -> anyhow::Result<()> {
+    let options = SqliteConnectOptions::new()
+        .filename(path)
+        .busy_timeout(Duration::ZERO)
+        .locking_mode(SqliteLockingMode::Exclusive);
+    let mut conn = SqliteConnection::connect_with(&options)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    // in exclusive locking mode the lock is kept after the transaction ends
+    if sqlx::query("BEGIN EXCLUSIVE")
+        .execute(&mut conn)
+        .await
+        .is_err()
+    {
+        anyhow::bail!(
+            "{} is in use, stop the server before restoring",
+            path.display()
+        );
+    }
+    sqlx::query("COMMIT").execute(&mut conn).await?;
+    sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+        .execute(&mut conn)
+        .await?;
+
+    conn.close().await?;
+
+    Ok(())
+}
+
+async fn open(path: &Path) -> anyhow::Result<SqlitePool> {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(SqliteConnectOptions::new().filename(path))
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
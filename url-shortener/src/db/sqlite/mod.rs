@@ -0,0 +1,349 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, Utc};
+use sqlx::{
+    SqlitePool,
+    migrate::Migrator,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::config::{DatabaseConfig, JournalMode, SynchronousMode};
+use crate::db::{Backend, ClickStore, LinkStore, PoolStats, Storage, WebhookStore};
+use crate::error::AppResult;
+use crate::models::{
+    AggregateStats, Click, ClickCursor, ClickStats, DateRange, LinkFilter, LinkRecord, LinkSeries,
+    NewClick, NewDelivery, NewUrl, PendingDelivery, RollupReport, Url, Webhook, WebhookDelivery,
+    WebhookEvent,
+};
+use crate::services::referer::RefererInfo;
+use crate::services::webhooks::ClickOutbox;
+
+pub mod backup;
+pub mod queries;
+pub mod rollups;
+pub mod webhooks;
+
+/// Migrations embedded at compile time, the schema this binary expects.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+/// A single SQLite file, the default backend. Writes go through one
+/// connection, so they queue in the pool instead of failing on a locked
+/// database, reads use a pool of read-only connections beside it.
+#[derive(Clone)]
+pub struct SqliteStore {
+    reader: SqlitePool,
+    writer: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Opens both pools, creating the database file if it does not exist.
+    pub async fn connect(config: &DatabaseConfig) -> anyhow::Result<Self> {
+        // create if not existant sqlite db file
+        let db_path = db_path(config);
+
+        if !Path::new(db_path).exists() {
+            tracing::info!(
+                "Databasae file not found. Created new database at {}",
+                db_path
+            );
+        } else {
+            tracing::info!("Using existing database at: {}", db_path);
+        }
+
+        let connecting_options = SqliteConnectOptions::new()
+            .filename(db_path)
+            .busy_timeout(Duration::from_millis(config.sqlite_busy_timeout_ms));
+
+        // the journal mode is stored in the file, so readers pick it up from the writer
+        let writer = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(
+                connecting_options
+                    .clone()
+                    .create_if_missing(true)
+                    .journal_mode(journal_mode(config.sqlite_journal_mode))
+                    .synchronous(synchronous(config.sqlite_synchronous)),
+            )
+            .await
+            .with_context(|| format!("Failed to connect to database at {}", db_path))?;
+
+        let reader = SqlitePoolOptions::new()
+            .max_connections(config.max_connections)
+            .connect_with(connecting_options.read_only(true))
+            .await
+            .with_context(|| format!("Failed to connect to database at {}", db_path))?;
+
+        tracing::info!(
+            "Database connected successfully ({:?} journal, {} readers, 1 writer)",
+            config.sqlite_journal_mode,
+            config.max_connections
+        );
+
+        Ok(Self { reader, writer })
+    }
+}
+
+fn db_path(config: &DatabaseConfig) -> &str {
+    config
+        .url
+        .strip_prefix("sqlite:")
+        .unwrap_or("url_shortener.db")
+}
+
+fn journal_mode(mode: JournalMode) -> SqliteJournalMode {
+    match mode {
+        JournalMode::Wal => SqliteJournalMode::Wal,
+        JournalMode::Delete => SqliteJournalMode::Delete,
+        JournalMode::Truncate => SqliteJournalMode::Truncate,
+        JournalMode::Persist => SqliteJournalMode::Persist,
+    }
+}
+
+fn synchronous(mode: SynchronousMode) -> SqliteSynchronous {
+    match mode {
+        SynchronousMode::Off => SqliteSynchronous::Off,
+        SynchronousMode::Normal => SqliteSynchronous::Normal,
+        SynchronousMode::Full => SqliteSynchronous::Full,
+        SynchronousMode::Extra => SqliteSynchronous::Extra,
+    }
+}
+
+#[async_trait]
+impl LinkStore for SqliteStore {
+    async fn create_url(&self, new_url: &NewUrl) -> AppResult<Url> {
+        queries::create_url(&self.writer, new_url).await
+    }
+
+    async fn create_urls(&self, new_urls: &[NewUrl]) -> AppResult<Vec<Url>> {
+        queries::create_urls(&self.writer, new_urls).await
+    }
+
+    async fn get_url_by_code(&self, short_code: &str) -> AppResult<Url> {
+        queries::get_url_by_code(&self.reader, short_code).await
+    }
+
+    async fn code_exists(&self, short_code: &str) -> AppResult<bool> {
+        queries::code_exists(&self.reader, short_code).await
+    }
+
+    async fn get_url_tags(&self, url_id: &str) -> AppResult<Vec<String>> {
+        queries::get_url_tags(&self.reader, url_id).await
+    }
+
+    async fn list_all_urls(&self) -> AppResult<Vec<Url>> {
+        queries::list_all_urls(&self.reader).await
+    }
+
+    async fn list_link_records(&self) -> AppResult<Vec<LinkRecord>> {
+        queries::list_link_records(&self.reader).await
+    }
+
+    async fn delete_url_by_code(&self, short_code: &str) -> AppResult<()> {
+        queries::delete_url_by_code(&self.writer, short_code).await
+    }
+
+    async fn delete_urls_expired_before(&self, cutoff: DateTime<Utc>) -> AppResult<Vec<String>> {
+        queries::delete_urls_expired_before(&self.writer, cutoff).await
+    }
+
+    async fn take_newly_expired_urls(&self, now: DateTime<Utc>) -> AppResult<Vec<Url>> {
+        webhooks::take_newly_expired_urls(&self.writer, now).await
+    }
+
+    async fn click_counts(&self, url_ids: &[&str]) -> AppResult<Vec<(String, i64)>> {
+        webhooks::click_counts(&self.reader, url_ids).await
+    }
+}
+
+#[async_trait]
+impl ClickStore for SqliteStore {
+    async fn record_clicks(
+        &self,
+        clicks: &[NewClick],
+        outbox: &ClickOutbox,
+    ) -> AppResult<Vec<Click>> {
+        queries::record_clicks(&self.writer, clicks, outbox).await
+    }
+
+    async fn get_url_stats(&self, url_id: String, range: &DateRange) -> AppResult<ClickStats> {
+        queries::get_url_stats(&self.reader, url_id, range).await
+    }
+
+    async fn get_aggregate_stats(
+        &self,
+        filter: &LinkFilter,
+        range: &DateRange,
+    ) -> AppResult<AggregateStats> {
+        queries::get_aggregate_stats(&self.reader, filter, range).await
+    }
+
+    async fn get_link_series(&self, url: &Url, range: &DateRange) -> AppResult<LinkSeries> {
+        queries::get_link_series(&self.reader, url, range).await
+    }
+
+    async fn clicks_missing_channel(&self, limit: i64) -> AppResult<Vec<(String, Option<String>)>> {
+        queries::clicks_missing_channel(&self.reader, limit).await
+    }
+
+    async fn set_click_channels(&self, clicks: &[(String, RefererInfo)]) -> AppResult<()> {
+        queries::set_click_channels(&self.writer, clicks).await
+    }
+
+    fn stream_clicks(
+        &self,
+        url_id: String,
+        range: DateRange,
+        cursor: Option<ClickCursor>,
+        limit: u32,
+    ) -> ReceiverStream<AppResult<Click>> {
+        queries::stream_clicks(self.reader.clone(), url_id, range, cursor, limit)
+    }
+
+    async fn next_click_cursor(
+        &self,
+        url_id: &str,
+        range: &DateRange,
+        cursor: Option<&ClickCursor>,
+        limit: u32,
+    ) -> AppResult<Option<ClickCursor>> {
+        queries::next_click_cursor(&self.reader, url_id, range, cursor, limit).await
+    }
+
+    async fn purge_clicks_before(&self, cutoff: NaiveDate) -> AppResult<u64> {
+        rollups::purge_clicks_before(&self.writer, cutoff).await
+    }
+
+    async fn rebuild_rollups(&self) -> AppResult<RollupReport> {
+        rollups::rebuild(&self.writer).await
+    }
+
+    async fn verify_rollups(&self) -> AppResult<RollupReport> {
+        rollups::verify(&self.reader).await
+    }
+
+    async fn get_or_create_ip_salt(&self, day: NaiveDate, candidate: &str) -> AppResult<String> {
+        queries::get_or_create_ip_salt(&self.writer, day, candidate).await
+    }
+
+    async fn delete_ip_salts_before(&self, day: NaiveDate) -> AppResult<()> {
+        queries::delete_ip_salts_before(&self.writer, day).await
+    }
+}
+
+#[async_trait]
+impl WebhookStore for SqliteStore {
+    async fn create_webhook(
+        &self,
+        owner: Option<&str>,
+        url: &str,
+        secret: &str,
+        events: &[WebhookEvent],
+        click_threshold: Option<i64>,
+    ) -> AppResult<Webhook> {
+        webhooks::create_webhook(&self.writer, owner, url, secret, events, click_threshold).await
+    }
+
+    async fn get_webhook(&self, id: &str) -> AppResult<Webhook> {
+        webhooks::get_webhook(&self.reader, id).await
+    }
+
+    async fn list_webhooks(&self, owner: Option<&str>) -> AppResult<Vec<Webhook>> {
+        webhooks::list_webhooks(&self.reader, owner).await
+    }
+
+    async fn delete_webhook(&self, id: &str) -> AppResult<()> {
+        webhooks::delete_webhook(&self.writer, id).await
+    }
+
+    async fn any_webhooks(&self) -> AppResult<bool> {
+        webhooks::any_webhooks(&self.reader).await
+    }
+
+    async fn subscribed_webhooks(
+        &self,
+        url_id: &str,
+        events: &[WebhookEvent],
+    ) -> AppResult<Vec<Webhook>> {
+        webhooks::subscribed_webhooks(&self.reader, url_id, events).await
+    }
+
+    async fn enqueue_deliveries(&self, deliveries: &[NewDelivery]) -> AppResult<()> {
+        webhooks::enqueue_deliveries(&self.writer, deliveries).await
+    }
+
+    async fn due_deliveries(&self, limit: i64, lease: Duration) -> AppResult<Vec<PendingDelivery>> {
+        webhooks::due_deliveries(&self.writer, limit, lease).await
+    }
+
+    async fn mark_delivered(&self, id: &str, status_code: u16) -> AppResult<()> {
+        webhooks::mark_delivered(&self.writer, id, status_code).await
+    }
+
+    async fn mark_attempt_failed(
+        &self,
+        id: &str,
+        status_code: Option<u16>,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> AppResult<()> {
+        webhooks::mark_attempt_failed(&self.writer, id, status_code, error, retry_at).await
+    }
+
+    async fn list_deliveries(
+        &self,
+        webhook_id: &str,
+        status: Option<&str>,
+        limit: u32,
+    ) -> AppResult<Vec<WebhookDelivery>> {
+        webhooks::list_deliveries(&self.reader, webhook_id, status, limit).await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStore {
+    fn backend(&self) -> Backend {
+        Backend::Sqlite
+    }
+
+    fn migrator(&self) -> &'static Migrator {
+        &MIGRATOR
+    }
+
+    async fn migrate(&self) -> anyhow::Result<()> {
+        MIGRATOR.run(&self.writer).await?;
+        Ok(())
+    }
+
+    async fn pending_migrations(&self) -> AppResult<Vec<i64>> {
+        queries::pending_migrations(&self.reader).await
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        queries::ping(&self.reader).await
+    }
+
+    async fn vacuum(&self) -> AppResult<()> {
+        queries::vacuum(&self.writer).await
+    }
+
+    async fn backup(&self, destination: &Path) -> AppResult<()> {
+        // a read transaction sees one snapshot, so writes need not wait for it
+        queries::backup(&self.reader, destination).await
+    }
+
+    fn pool_stats(&self) -> PoolStats {
+        let pools = [&self.reader, &self.writer];
+
+        PoolStats {
+            size: pools.iter().map(|pool| pool.size()).sum(),
+            idle: pools.iter().map(|pool| pool.num_idle() as u32).sum(),
+            max_connections: pools
+                .iter()
+                .map(|pool| pool.options().get_max_connections())
+                .sum(),
+        }
+    }
+}
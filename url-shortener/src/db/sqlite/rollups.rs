@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use sqlx::{SqliteConnection, SqlitePool};
+
+use crate::db::DIMENSIONS;
+use crate::error::AppResult;
+use crate::models::{NewClick, RollupReport};
+
+/// Adds a batch of freshly inserted clicks to the daily totals and rollups.
+pub async fn record(conn: &mut SqliteConnection, clicks: &[NewClick]) -> AppResult<()> {
+    let mut totals: HashMap<(&str, NaiveDate), i64> = HashMap::new();
+    let mut dimensions: HashMap<(&str, NaiveDate, &str, &str), i64> = HashMap::new();
+
+    for click in clicks {
+        let day = click.clicked_at.date_naive();
+        *totals.entry((&click.url_id, day)).or_default() += 1;
+
+        for (dimension, value) in click.dimensions() {
+            *dimensions
+                .entry((&click.url_id, day, dimension, value))
+                .or_default() += 1;
+        }
+    }
+
+    for ((url_id, day), count) in totals {
+        sqlx::query(
+            r#"
+            INSERT INTO click_daily_totals (url_id, day, clicks)
+            VALUES (?, ?, ?)
+            ON CONFLICT (url_id, day) DO UPDATE SET clicks = clicks + excluded.clicks
+            "#,
+        )
+        .bind(url_id)
+        .bind(day.to_string())
+        .bind(count)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for ((url_id, day, dimension, value), count) in dimensions {
+        let clicks: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET
+                clicks = clicks + excluded.clicks
+            RETURNING clicks
+            "#,
+        )
+        .bind(url_id)
+        .bind(day.to_string())
+        .bind(dimension)
+        .bind(value)
+        .bind(count)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // an ip seen for the first time that day is a new unique visitor
+        if dimension == "ip" && clicks == count {
+            sqlx::query(
+                r#"
+                UPDATE click_daily_totals SET unique_ips = unique_ips + 1
+                WHERE url_id = ? AND day = ?
+                "#,
+            )
+            .bind(url_id)
+            .bind(day.to_string())
+            .execute(&mut *conn)
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Deletes raw clicks from before `cutoff`. Their days stay in the rollups,
+/// minus the per-ip rows, which only keep a unique count in the totals.
+pub async fn purge_clicks_before(pool: &SqlitePool, cutoff: NaiveDate) -> AppResult<u64> {
+    let cutoff = cutoff.to_string();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE click_daily_totals SET ips_purged = 1 WHERE day < ?
+        "#,
+    )
+    .bind(&cutoff)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM click_daily_rollups WHERE dimension = 'ip' AND day < ?
+        "#,
+    )
+    .bind(&cutoff)
+    .execute(&mut *tx)
+    .await?;
+
+    let purged = sqlx::query(
+        r#"
+        DELETE FROM clicks WHERE clicked_at < ?
+        "#,
+    )
+    .bind(&cutoff)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(purged)
+}
+
+/// Recomputes the rollups of every day that still has raw clicks. Purged
+/// days are left alone since there is nothing to rebuild them from.
+pub async fn rebuild(pool: &SqlitePool) -> AppResult<RollupReport> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM click_daily_rollups
+        WHERE NOT EXISTS (
+            SELECT 1 FROM click_daily_totals t
+            WHERE t.url_id = click_daily_rollups.url_id
+              AND t.day = click_daily_rollups.day
+              AND t.ips_purged = 1
+        )
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM click_daily_totals WHERE ips_purged = 0
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO click_daily_totals (url_id, day, clicks, unique_ips)
+        SELECT url_id, DATE(clicked_at), COUNT(*), COUNT(DISTINCT ip_address)
+        FROM clicks
+        WHERE true
+        GROUP BY url_id, DATE(clicked_at)
+        ON CONFLICT (url_id, day) DO UPDATE SET
+            clicks = clicks + excluded.clicks,
+            unique_ips = unique_ips + excluded.unique_ips
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    for (dimension, column) in DIMENSIONS {
+        sqlx::query(&format!(
+            r#"
+            INSERT INTO click_daily_rollups (url_id, day, dimension, value, clicks)
+            SELECT url_id, DATE(clicked_at), '{dimension}', {column}, COUNT(*)
+            FROM clicks
+            WHERE {column} IS NOT NULL
+            GROUP BY url_id, DATE(clicked_at), {column}
+            ON CONFLICT (url_id, day, dimension, value) DO UPDATE SET
+                clicks = clicks + excluded.clicks
+            "#
+        ))
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    verify(pool).await
+}
+
+/// Compares the rollups of every day that still has raw clicks against
+/// aggregates computed straight from the `clicks` table.
+pub async fn verify(pool: &SqlitePool) -> AppResult<RollupReport> {
+    let days_checked: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM click_daily_totals WHERE ips_purged = 0
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let raw_totals = r#"
+        SELECT url_id, DATE(clicked_at) AS day, COUNT(*), COUNT(DISTINCT ip_address)
+        FROM clicks GROUP BY url_id, DATE(clicked_at)
+    "#;
+    let stored_totals = r#"
+        SELECT url_id, day, clicks, unique_ips FROM click_daily_totals WHERE ips_purged = 0
+    "#;
+    let mismatched_totals = symmetric_difference(pool, raw_totals, stored_totals).await?;
+
+    let raw_rollups = DIMENSIONS
+        .iter()
+        .map(|(dimension, column)| {
+            format!(
+                "SELECT url_id, DATE(clicked_at), '{dimension}', {column}, COUNT(*) FROM clicks \
+                 WHERE {column} IS NOT NULL GROUP BY url_id, DATE(clicked_at), {column}"
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ");
+    let stored_rollups = r#"
+        SELECT r.url_id, r.day, r.dimension, r.value, r.clicks
+        FROM click_daily_rollups r
+        JOIN click_daily_totals t ON t.url_id = r.url_id AND t.day = r.day
+        WHERE t.ips_purged = 0
+    "#;
+    let mismatched_rollups = symmetric_difference(pool, &raw_rollups, stored_rollups).await?;
+
+    Ok(RollupReport {
+        consistent: mismatched_totals == 0 && mismatched_rollups == 0,
+        days_checked,
+        mismatched_totals,
+        mismatched_rollups,
+    })
+}
+
+/// Number of rows that appear in only one of the two queries.
+async fn symmetric_difference(pool: &SqlitePool, left: &str, right: &str) -> AppResult<i64> {
+    let count = sqlx::query_scalar(&format!(
+        "SELECT \
+         (SELECT COUNT(*) FROM (SELECT * FROM ({left}) EXCEPT SELECT * FROM ({right}))) + \
+         (SELECT COUNT(*) FROM (SELECT * FROM ({right}) EXCEPT SELECT * FROM ({left})))"
+    ))
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count)
+}
@@ -0,0 +1,290 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite, SqlitePool, Transaction};
+
+use crate::error::{AppError, AppResult};
+use crate::models::{NewDelivery, PendingDelivery, Url, Webhook, WebhookDelivery, WebhookEvent};
+
+// format of sqlite's datetime('now'), so due dates compare lexically
+const SQLITE_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub async fn create_webhook(
+    pool: &SqlitePool,
+    owner: Option<&str>,
+    url: &str,
+    secret: &str,
+    events: &[WebhookEvent],
+    click_threshold: Option<i64>,
+) -> AppResult<Webhook> {
+    let events = events
+        .iter()
+        .map(WebhookEvent::as_str)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let webhook = sqlx::query_as::<_, Webhook>(
+        r#"
+        INSERT INTO webhooks (owner, url, secret, events, click_threshold)
+        VALUES (?, ?, ?, ?, ?)
+        RETURNING *
+        "#,
+    )
+    .bind(owner)
+    .bind(url)
+    .bind(secret)
+    .bind(events)
+    .bind(click_threshold)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(webhook)
+}
+
+pub async fn get_webhook(pool: &SqlitePool, id: &str) -> AppResult<Webhook> {
+    let webhook = sqlx::query_as::<_, Webhook>(
+        r#"
+        SELECT * FROM webhooks WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::WebhookNotFound)?;
+
+    Ok(webhook)
+}
+
+pub async fn list_webhooks(pool: &SqlitePool, owner: Option<&str>) -> AppResult<Vec<Webhook>> {
+    let mut query = QueryBuilder::new("SELECT * FROM webhooks");
+    if let Some(owner) = owner {
+        query.push(" WHERE owner = ");
+        query.push_bind(owner.to_string());
+    }
+    query.push(" ORDER BY created_at DESC");
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+pub async fn delete_webhook(pool: &SqlitePool, id: &str) -> AppResult<()> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM webhooks WHERE id = ?
+        "#,
+    )
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::WebhookNotFound);
+    }
+
+    Ok(())
+}
+
+pub async fn any_webhooks(pool: &SqlitePool) -> AppResult<bool> {
+    let exists: i32 = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(SELECT 1 FROM webhooks)
+        "#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(exists != 0)
+}
+
+/// Webhooks of the link's owner, or without an owner, that subscribe to
+/// any of `events`.
+pub async fn subscribed_webhooks(
+    pool: &SqlitePool,
+    url_id: &str,
+    events: &[WebhookEvent],
+) -> AppResult<Vec<Webhook>> {
+    let mut query = QueryBuilder::new("SELECT w.* FROM webhooks w JOIN urls u ON u.id = ");
+    query.push_bind(url_id.to_string());
+    query.push(" WHERE (w.owner IS NULL OR w.owner = u.owner) AND (");
+    for (i, event) in events.iter().enumerate() {
+        if i > 0 {
+            query.push(" OR ");
+        }
+        query.push("instr(',' || w.events || ',', ',' || ");
+        query.push_bind(event.as_str());
+        query.push(" || ',') > 0");
+    }
+    if events.is_empty() {
+        query.push("0");
+    }
+    query.push(")");
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+/// Adds the outbox rows in one transaction, due right away.
+pub async fn enqueue_deliveries(pool: &SqlitePool, deliveries: &[NewDelivery]) -> AppResult<()> {
+    if deliveries.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    insert_deliveries(&mut tx, deliveries).await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Outbox inserts shared by [`enqueue_deliveries`] and the click batches.
+pub(super) async fn insert_deliveries(
+    tx: &mut Transaction<'_, Sqlite>,
+    deliveries: &[NewDelivery],
+) -> AppResult<()> {
+    for delivery in deliveries {
+        sqlx::query(
+            r#"
+            INSERT INTO webhook_deliveries (webhook_id, event, payload) VALUES (?, ?, ?)
+            "#,
+        )
+        .bind(&delivery.webhook_id)
+        .bind(delivery.event.as_str())
+        .bind(&delivery.payload)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Claims up to `limit` due deliveries for `lease`. Writes go through one
+/// connection, so two dispatchers can't claim the same row. Claims that
+/// outlived their lease are due again.
+pub async fn due_deliveries(
+    pool: &SqlitePool,
+    limit: i64,
+    lease: Duration,
+) -> AppResult<Vec<PendingDelivery>> {
+    let locked_until = Utc::now() + chrono::Duration::from_std(lease).unwrap_or_default();
+
+    let deliveries = sqlx::query_as::<_, PendingDelivery>(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = 'sending', locked_until = ?
+        WHERE id IN (
+            SELECT id FROM webhook_deliveries
+            WHERE (status = 'pending' AND next_attempt_at <= datetime('now'))
+               OR (status = 'sending' AND locked_until <= datetime('now'))
+            ORDER BY next_attempt_at
+            LIMIT ?
+        )
+        RETURNING id, event, payload, attempts,
+            (SELECT url FROM webhooks WHERE webhooks.id = webhook_id) AS url,
+            (SELECT secret FROM webhooks WHERE webhooks.id = webhook_id) AS secret
+        "#,
+    )
+    .bind(locked_until.format(SQLITE_DATETIME_FORMAT).to_string())
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(deliveries)
+}
+
+pub async fn mark_delivered(pool: &SqlitePool, id: &str, status_code: u16) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = 'delivered', attempts = attempts + 1, last_status_code = ?,
+            last_error = NULL, delivered_at = datetime('now'), locked_until = NULL
+        WHERE id = ?
+        "#,
+    )
+    .bind(status_code)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Records a failed attempt, retrying at `retry_at` or giving up when it is `None`.
+pub async fn mark_attempt_failed(
+    pool: &SqlitePool,
+    id: &str,
+    status_code: Option<u16>,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> AppResult<()> {
+    let (status, next_attempt_at) = match retry_at {
+        Some(retry_at) => ("pending", retry_at),
+        None => ("failed", Utc::now()),
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE webhook_deliveries
+        SET status = ?, attempts = attempts + 1, next_attempt_at = ?,
+            last_status_code = ?, last_error = ?, locked_until = NULL
+        WHERE id = ?
+        "#,
+    )
+    .bind(status)
+    .bind(next_attempt_at.format(SQLITE_DATETIME_FORMAT).to_string())
+    .bind(status_code)
+    .bind(error)
+    .bind(id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn list_deliveries(
+    pool: &SqlitePool,
+    webhook_id: &str,
+    status: Option<&str>,
+    limit: u32,
+) -> AppResult<Vec<WebhookDelivery>> {
+    let mut query = QueryBuilder::new("SELECT * FROM webhook_deliveries WHERE webhook_id = ");
+    query.push_bind(webhook_id.to_string());
+    if let Some(status) = status {
+        query.push(" AND status = ");
+        query.push_bind(status.to_string());
+    }
+    query.push(" ORDER BY created_at DESC, id LIMIT ");
+    query.push_bind(limit);
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
+
+/// Flags links that expired by `now` and have not been announced yet, and
+/// returns them.
+pub async fn take_newly_expired_urls(pool: &SqlitePool, now: DateTime<Utc>) -> AppResult<Vec<Url>> {
+    let urls = sqlx::query_as::<_, Url>(
+        r#"
+        UPDATE urls SET expiry_notified = 1
+        WHERE expiry_notified = 0 AND expires_at IS NOT NULL AND expires_at <= ?
+        RETURNING *
+        "#,
+    )
+    .bind(now.to_rfc3339())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(urls)
+}
+
+/// Click counters of the given links.
+pub async fn click_counts(pool: &SqlitePool, url_ids: &[&str]) -> AppResult<Vec<(String, i64)>> {
+    if url_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut query = QueryBuilder::new("SELECT id, click_count FROM urls WHERE id IN (");
+    let mut separated = query.separated(", ");
+    for url_id in url_ids {
+        separated.push_bind(url_id.to_string());
+    }
+    query.push(")");
+
+    Ok(query.build_query_as().fetch_all(pool).await?)
+}
@@ -1,6 +1,8 @@
+use std::time::Duration;
+
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header},
     response::{IntoResponse, Response},
 };
 use serde_json::json;
@@ -23,27 +25,57 @@ pub enum AppError {
     #[error("URL has expired")]
     UrlExpired,
 
+    #[error("Webhook not found")]
+    WebhookNotFound,
+
     #[error("Validation error: {0}")]
     Validation(String),
 
     #[error("Internal server errror")]
     Internal(#[from] anyhow::Error),
 
+    #[error("Missing or invalid admin token")]
+    Unauthorized,
+
     #[error("Rate limit exceeded")]
-    RateLimitExceeded,
+    RateLimitExceeded { limit: u32, retry_after: Duration },
+}
+
+impl AppError {
+    /// Variant name used as the `kind` label of `app_errors_total`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::Database(_) => "database",
+            AppError::UrLNotFound => "url_not_found",
+            AppError::InvalidUrl => "invalid_url",
+            AppError::CodeAlreadyExists => "code_already_exists",
+            AppError::UrlExpired => "url_expired",
+            AppError::WebhookNotFound => "webhook_not_found",
+            AppError::Validation(_) => "validation",
+            AppError::Internal(_) => "internal",
+            AppError::Unauthorized => "unauthorized",
+            AppError::RateLimitExceeded { .. } => "rate_limit_exceeded",
+        }
+    }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        metrics::counter!("app_errors_total", "kind" => self.kind()).increment(1);
+
         let (status, message) = match self {
             AppError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database erorr"),
             AppError::UrLNotFound => (StatusCode::NOT_FOUND, "URL not found"),
             AppError::InvalidUrl => (StatusCode::BAD_REQUEST, "Invalid URL format"),
             AppError::CodeAlreadyExists => (StatusCode::CONFLICT, "Short code already exists"),
             AppError::UrlExpired => (StatusCode::GONE, "Url has expired"),
+            AppError::WebhookNotFound => (StatusCode::NOT_FOUND, "Webhook not found"),
             AppError::Validation(ref message) => (StatusCode::BAD_REQUEST, message.as_str()),
             AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error"),
-            AppError::RateLimitExceeded => (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded"),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized"),
+            AppError::RateLimitExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded")
+            }
         };
 
         let body = Json(json!({
@@ -51,7 +83,26 @@ impl IntoResponse for AppError {
             "details": self.to_string(),
         }));
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+
+        if matches!(self, AppError::Unauthorized) {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+
+        if let AppError::RateLimitExceeded { limit, retry_after } = self {
+            // round up so clients never retry before the quota allows it
+            let retry_after_secs =
+                retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            let headers = response.headers_mut();
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+            headers.insert("x-ratelimit-limit", HeaderValue::from(limit));
+            headers.insert("x-ratelimit-remaining", HeaderValue::from(0));
+            headers.insert("x-ratelimit-reset", HeaderValue::from(retry_after_secs));
+        }
+
+        response
     }
 }
 
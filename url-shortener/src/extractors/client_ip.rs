@@ -0,0 +1,259 @@
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, request::Parts},
+};
+use ipnet::IpNet;
+
+use crate::AppState;
+use crate::error::AppError;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// The address of the client that made the request. Forwarding headers are
+/// only honoured when the connecting peer is one of `Config::trusted_proxies`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(peer) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .copied()
+            .ok_or_else(|| AppError::Internal(anyhow::anyhow!("Missing connection info")))?;
+
+        Ok(ClientIp(resolve_client_ip(
+            peer.ip(),
+            &parts.headers,
+            &state.config.load().server.trusted_proxies,
+        )))
+    }
+}
+
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+
+    if !is_trusted(&peer) {
+        return peer;
+    }
+
+    if let Some(hops) = forwarded_for(headers).or_else(|| x_forwarded_for(headers)) {
+        // walk back from the closest hop, the first untrusted address is the client
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            let Some(ip) = hop else {
+                break;
+            };
+            client = *ip;
+            if !is_trusted(ip) {
+                break;
+            }
+        }
+        return client;
+    }
+
+    header_values(headers, X_REAL_IP)
+        .next()
+        .and_then(parse_ip)
+        .unwrap_or(peer)
+}
+
+// RFC 7239: `Forwarded: for=192.0.2.60;proto=http, for="[2001:db8::17]:4711"`
+fn forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let hops: Vec<_> = header_values(headers, "forwarded")
+        .flat_map(|value| value.split(','))
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| parse_ip(value))
+            })
+        })
+        .collect();
+
+    (!hops.is_empty()).then_some(hops)
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let hops: Vec<_> = header_values(headers, X_FORWARDED_FOR)
+        .flat_map(|value| value.split(','))
+        .map(parse_ip)
+        .collect();
+
+    (!hops.is_empty()).then_some(hops)
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|value| value.to_str().ok())
+}
+
+/// Accepts `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` and `[2001:db8::1]:80`,
+/// optionally quoted.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_matches('"');
+
+    if let Ok(ip) = value.parse() {
+        return Some(ip);
+    }
+
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+
+    value
+        .strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(ip, _)| ip.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: &str = "10.0.0.1";
+    const CLIENT: &str = "203.0.113.7";
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn trusted() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn resolve(peer: &str, pairs: &[(&'static str, &str)]) -> IpAddr {
+        resolve_client_ip(ip(peer), &headers(pairs), &trusted())
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_set_the_client_ip() {
+        let spoofed = [
+            ("forwarded", "for=198.51.100.1"),
+            (X_FORWARDED_FOR, "198.51.100.1"),
+            (X_REAL_IP, "198.51.100.1"),
+        ];
+        assert_eq!(resolve(CLIENT, &spoofed), ip(CLIENT));
+
+        // without trusted proxies nobody can
+        let headers = headers(&spoofed);
+        assert_eq!(resolve_client_ip(ip(PROXY), &headers, &[]), ip(PROXY));
+    }
+
+    #[test]
+    fn trusted_peers_forward_the_client_ip() {
+        assert_eq!(resolve(PROXY, &[(X_FORWARDED_FOR, CLIENT)]), ip(CLIENT));
+        // no forwarding headers, the proxy itself is the client
+        assert_eq!(resolve(PROXY, &[]), ip(PROXY));
+    }
+
+    #[test]
+    fn forwarding_chains_are_walked_back_to_the_first_untrusted_hop() {
+        // the client prepended a spoofed address, the proxies appended theirs
+        let chain = format!("198.51.100.1, {}, 10.0.0.3, 10.0.0.2", CLIENT);
+        assert_eq!(resolve(PROXY, &[(X_FORWARDED_FOR, &chain)]), ip(CLIENT));
+
+        // hops split across repeated headers count as one list
+        let split = [
+            (X_FORWARDED_FOR, "198.51.100.1"),
+            (X_FORWARDED_FOR, CLIENT),
+            (X_FORWARDED_FOR, "10.0.0.2"),
+        ];
+        assert_eq!(resolve(PROXY, &split), ip(CLIENT));
+
+        // every hop trusted, the farthest one is the client
+        assert_eq!(
+            resolve(PROXY, &[(X_FORWARDED_FOR, "10.0.0.3, 10.0.0.2")]),
+            ip("10.0.0.3")
+        );
+    }
+
+    #[test]
+    fn forwarded_takes_precedence_and_accepts_quoted_ipv6_with_a_port() {
+        let pairs = [
+            (
+                "forwarded",
+                r#"for=198.51.100.1;proto=http, For="[2001:db8::17]:4711";by=10.0.0.2"#,
+            ),
+            (X_FORWARDED_FOR, "192.0.2.99"),
+        ];
+        assert_eq!(resolve(PROXY, &pairs), ip("2001:db8::17"));
+        assert_eq!(
+            resolve("::1", &[("forwarded", "for=192.0.2.60:8080")]),
+            ip("192.0.2.60")
+        );
+    }
+
+    #[test]
+    fn x_real_ip_is_the_fallback() {
+        assert_eq!(resolve(PROXY, &[(X_REAL_IP, CLIENT)]), ip(CLIENT));
+        assert_eq!(
+            resolve(
+                PROXY,
+                &[(X_REAL_IP, CLIENT), (X_FORWARDED_FOR, "192.0.2.99")]
+            ),
+            ip("192.0.2.99")
+        );
+        assert_eq!(resolve(PROXY, &[(X_REAL_IP, "unknown")]), ip(PROXY));
+    }
+
+    #[test]
+    fn garbage_hops_stop_the_walk() {
+        assert_eq!(resolve(PROXY, &[(X_FORWARDED_FOR, "nonsense")]), ip(PROXY));
+        // an unparsable hop is not skipped over, what lies beyond it is unverified
+        assert_eq!(
+            resolve(
+                PROXY,
+                &[(X_FORWARDED_FOR, "198.51.100.1, unknown, 10.0.0.2")]
+            ),
+            ip("10.0.0.2")
+        );
+        assert_eq!(
+            resolve(PROXY, &[("forwarded", "for=_hidden, for=unknown")]),
+            ip(PROXY)
+        );
+    }
+
+    #[test]
+    fn ip_formats() {
+        for (value, expected) in [
+            ("192.0.2.1", "192.0.2.1"),
+            (" 192.0.2.1:443 ", "192.0.2.1"),
+            ("2001:db8::1", "2001:db8::1"),
+            ("[2001:db8::1]:80", "2001:db8::1"),
+            ("\"[2001:db8::1]\"", "2001:db8::1"),
+        ] {
+            assert_eq!(parse_ip(value), Some(ip(expected)), "{}", value);
+        }
+
+        for value in [
+            "",
+            "unknown",
+            "300.1.1.1",
+            "[2001:db8::1",
+            "[nope]:80",
+            "1.2.3",
+        ] {
+            assert_eq!(parse_ip(value), None, "{}", value);
+        }
+    }
+}
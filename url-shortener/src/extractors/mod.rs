@@ -0,0 +1,3 @@
+pub mod client_ip;
+
+pub use client_ip::ClientIp;
@@ -0,0 +1,134 @@
+use crate::AppState;
+use crate::config::Config;
+use crate::error::{AppError, AppResult};
+use crate::models::{LinkExportParams, LinkImportParams, RollupReport};
+use crate::services::{
+    backup::{self, BackupReport},
+    click_pipeline::ClickPipelineStats,
+    config_reload::ReloadReport,
+    scheduler::JobStatus,
+    transfer::{self, ImportReport, LinkFormat},
+    url_cache::UrlCacheStats,
+};
+use axum::{
+    Json,
+    body::Bytes,
+    extract::{Query, State},
+    http::{HeaderMap, header},
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+
+pub async fn click_pipeline(State(state): State<AppState>) -> Json<ClickPipelineStats> {
+    Json(state.clicks.stats())
+}
+
+pub async fn url_cache(State(state): State<AppState>) -> Json<UrlCacheStats> {
+    Json(state.url_cache.stats())
+}
+
+/// The settings currently in effect, secrets masked.
+pub async fn config(State(state): State<AppState>) -> Json<Config> {
+    Json(state.config.load().redacted())
+}
+
+pub async fn reload_config(State(state): State<AppState>) -> AppResult<Json<ReloadReport>> {
+    let report = state
+        .config_reloader
+        .reload()
+        .await
+        .map_err(|e| AppError::Validation(format!("{:#}", e)))?;
+
+    Ok(Json(report))
+}
+
+/// Snapshots the database into `database.backup_dir`.
+pub async fn backup(State(state): State<AppState>) -> AppResult<Json<BackupReport>> {
+    let destination = backup::default_path(&state.config.load().database.backup_dir, Utc::now());
+    Ok(Json(backup::backup(state.db.as_ref(), &destination).await?))
+}
+
+/// Every link as JSON or CSV, in the format the CLI exports and imports.
+pub async fn export_links(
+    State(state): State<AppState>,
+    Query(params): Query<LinkExportParams>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let format = params.format.unwrap_or_else(|| {
+        if accepts(&headers, header::ACCEPT, "text/csv") {
+            LinkFormat::Csv
+        } else {
+            LinkFormat::Json
+        }
+    });
+
+    let records = state.db.list_link_records().await?;
+    let mut body = Vec::new();
+    transfer::write_links(format, &records, &mut body)?;
+
+    let filename = match format {
+        LinkFormat::Json => "attachment; filename=\"links.json\"",
+        LinkFormat::Csv => "attachment; filename=\"links.csv\"",
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type()),
+            (header::CONTENT_DISPOSITION, filename),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// Imports a JSON or CSV body, picked by its `Content-Type`, like the
+/// `import` command does.
+pub async fn import_links(
+    State(state): State<AppState>,
+    Query(params): Query<LinkImportParams>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> AppResult<Json<ImportReport>> {
+    let format = if accepts(&headers, header::CONTENT_TYPE, "text/csv") {
+        LinkFormat::Csv
+    } else {
+        LinkFormat::Json
+    };
+
+    let rows = transfer::read_links(format, body.as_ref())
+        .map_err(|e| AppError::Validation(format!("{:#}", e)))?;
+    let report = transfer::import_links(state.db.as_ref(), rows, params.dry_run).await?;
+
+    tracing::info!(
+        "[IMPORT] {} {} of {} links, skipped {}",
+        if report.dry_run {
+            "would import"
+        } else {
+            "imported"
+        },
+        report.imported,
+        report.total,
+        report.skipped.len()
+    );
+    Ok(Json(report))
+}
+
+fn accepts(headers: &HeaderMap, name: header::HeaderName, media_type: &str) -> bool {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains(media_type))
+}
+
+pub async fn jobs(State(state): State<AppState>) -> Json<Vec<JobStatus>> {
+    Json(state.scheduler.statuses())
+}
+
+pub async fn rebuild_rollups(State(state): State<AppState>) -> AppResult<Json<RollupReport>> {
+    let report = state.db.rebuild_rollups().await?;
+    tracing::info!("[ROLLUPS] rebuilt, consistent: {}", report.consistent);
+    Ok(Json(report))
+}
+
+pub async fn verify_rollups(State(state): State<AppState>) -> AppResult<Json<RollupReport>> {
+    Ok(Json(state.db.verify_rollups().await?))
+}
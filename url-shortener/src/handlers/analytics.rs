@@ -1,32 +1,173 @@
-use crate::db::queries;
-use crate::error::AppResult;
+use crate::error::{AppError, AppResult};
+use crate::extractors::ClientIp;
+use crate::models::{
+    AggregateStats, ClickCursor, ClickExportParams, CompareParams, DateRange, LinkFilter,
+    LinkSeries, StatsMetadata,
+};
+use crate::services::admin_auth::is_admin;
+use crate::services::export::{ExportEncoder, ExportFormat};
 use crate::services::qr_code;
+use crate::services::rate_limiter::RateLimitScope;
 use crate::{AppState, models::ClickStats};
 use axum::{
     Json,
-    extract::{Path, State},
-    http::{StatusCode, header},
-    response::IntoResponse,
+    body::Body,
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
 };
+use std::net::IpAddr;
+use tokio_stream::StreamExt;
+
+const DEFAULT_EXPORT_LIMIT: u32 = 1000;
+const MAX_EXPORT_LIMIT: u32 = 50_000;
+const MAX_COMPARE_CODES: usize = 10;
 
 pub async fn get_url_stats(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Path(short_code): Path<String>,
+    Query(range): Query<DateRange>,
 ) -> AppResult<Json<ClickStats>> {
-    let url = queries::get_url_by_code(&state.db, &short_code).await?;
+    check_stats_rate_limit(&state, ip)?;
+
+    range.validate()?;
 
-    let stats = queries::get_url_stats(&state.db, url.id).await?;
+    let url = state
+        .url_cache
+        .get_url_by_code(state.db.as_ref(), &short_code)
+        .await?;
+
+    let mut stats = state.db.get_url_stats(url.id, &range).await?;
+    stats.metadata = Some(StatsMetadata::new(state.ip_anonymizer.mode()));
 
     Ok(Json(stats))
 }
 
+pub async fn get_aggregate_stats(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Query(filter): Query<LinkFilter>,
+    Query(range): Query<DateRange>,
+) -> AppResult<Json<AggregateStats>> {
+    check_stats_rate_limit(&state, ip)?;
+
+    range.validate()?;
+
+    let mut stats = state.db.get_aggregate_stats(&filter, &range).await?;
+    stats.stats.metadata = Some(StatsMetadata::new(state.ip_anonymizer.mode()));
+
+    Ok(Json(stats))
+}
+
+pub async fn compare_links(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Query(params): Query<CompareParams>,
+    Query(range): Query<DateRange>,
+) -> AppResult<Json<Vec<LinkSeries>>> {
+    check_stats_rate_limit(&state, ip)?;
+
+    range.validate()?;
+
+    let mut codes: Vec<&str> = Vec::new();
+    for code in params.codes.split(',').map(str::trim) {
+        if !code.is_empty() && !codes.contains(&code) {
+            codes.push(code);
+        }
+    }
+
+    if codes.is_empty() || codes.len() > MAX_COMPARE_CODES {
+        return Err(AppError::Validation(format!(
+            "'codes' must list between 1 and {} short codes",
+            MAX_COMPARE_CODES
+        )));
+    }
+
+    let mut series = Vec::with_capacity(codes.len());
+    for code in codes {
+        let url = state
+            .url_cache
+            .get_url_by_code(state.db.as_ref(), code)
+            .await?;
+        series.push(state.db.get_link_series(&url, &range).await?);
+    }
+
+    Ok(Json(series))
+}
+
+/// Raw clicks of one link, for the admin token only.
+pub async fn export_clicks(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(short_code): Path<String>,
+    Query(range): Query<DateRange>,
+    Query(params): Query<ClickExportParams>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    // raw rows carry visitor IPs and user agents, unlike the aggregated stats
+    if !is_admin(&headers, state.config.load().server.admin_token.as_deref()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    check_stats_rate_limit(&state, ip)?;
+
+    range.validate()?;
+
+    let url = state
+        .url_cache
+        .get_url_by_code(state.db.as_ref(), &short_code)
+        .await?;
+
+    let cursor = params
+        .cursor
+        .as_deref()
+        .map(str::parse::<ClickCursor>)
+        .transpose()?;
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_EXPORT_LIMIT)
+        .clamp(1, MAX_EXPORT_LIMIT);
+
+    let next_cursor = state
+        .db
+        .next_click_cursor(&url.id, &range, cursor.as_ref(), limit)
+        .await?;
+
+    let format = ExportFormat::from_headers(&headers);
+    let mut encoder = ExportEncoder::new(format);
+    let body = state
+        .db
+        .stream_clicks(url.id, range, cursor, limit)
+        .map(move |click| click.and_then(|click| encoder.encode(&click)));
+
+    let mut response = Body::from_stream(body).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.content_type()),
+    );
+    if let Some(next_cursor) = next_cursor {
+        let value = HeaderValue::from_str(&next_cursor.to_string())
+            .map_err(|e| anyhow::anyhow!("Invalid cursor header: {}", e))?;
+        response.headers_mut().insert("x-next-cursor", value);
+    }
+
+    Ok(response)
+}
+
 pub async fn get_qr_code(
     State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     Path(short_code): Path<String>,
 ) -> AppResult<impl IntoResponse> {
-    let _url = queries::get_url_by_code(&state.db, &short_code).await?;
+    check_stats_rate_limit(&state, ip)?;
+
+    let _url = state
+        .url_cache
+        .get_url_by_code(state.db.as_ref(), &short_code)
+        .await?;
 
-    let short_url = format!("{}/{}", state.config.base_url, short_code);
+    let short_url = format!("{}/{}", state.config.load().server.base_url, short_code);
 
     let qr_image = qr_code::generate_qr_code(&short_url);
 
@@ -36,3 +177,10 @@ pub async fn get_qr_code(
         qr_image,
     ))
 }
+
+fn check_stats_rate_limit(state: &AppState, ip: IpAddr) -> AppResult<()> {
+    state
+        .rate_limiter
+        .check(RateLimitScope::Stats, ip)
+        .inspect_err(|_| tracing::warn!("[STATS] rate limit exceeded for IP: {}", ip))
+}
@@ -0,0 +1,66 @@
+use crate::AppState;
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Readiness {
+    pub status: &'static str,
+    pub database: &'static str,
+    pub backend: &'static str,
+    pub expected_migrations: usize,
+    pub pending_migrations: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Liveness, the process is up and serving requests.
+pub async fn healthz() -> Json<Health> {
+    Json(Health { status: "ok" })
+}
+
+/// Readiness, the database answers and every embedded migration is applied.
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let mut readiness = Readiness {
+        status: "ready",
+        database: "ok",
+        backend: state.db.backend().as_str(),
+        expected_migrations: state.db.migrator().iter().count(),
+        pending_migrations: Vec::new(),
+        error: None,
+    };
+
+    let checked = match state.db.ping().await {
+        Ok(()) => state.db.pending_migrations().await,
+        Err(e) => {
+            readiness.database = "unreachable";
+            Err(e)
+        }
+    };
+
+    match checked {
+        Ok(pending) if pending.is_empty() => {}
+        Ok(pending) => {
+            readiness.status = "not_ready";
+            readiness.pending_migrations = pending;
+        }
+        Err(e) => {
+            // the probe is public, the details stay in the log
+            tracing::warn!("[READYZ] readiness check failed: {}", e);
+            readiness.status = "not_ready";
+            readiness.error = Some("database unavailable".to_string());
+        }
+    }
+
+    let status = if readiness.status == "ready" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(readiness))
+}
@@ -0,0 +1,72 @@
+use std::{convert::Infallible, time::Duration};
+
+use crate::AppState;
+use crate::error::AppResult;
+use crate::models::ClickEvent;
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use tokio::sync::{broadcast, watch};
+use tokio_stream::{
+    Stream, StreamExt,
+    wrappers::{BroadcastStream, WatchStream},
+};
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+pub async fn url_clicks(
+    State(state): State<AppState>,
+    Path(short_code): Path<String>,
+) -> AppResult<Sse<impl Stream<Item = Result<Event, Infallible>>>> {
+    let url = state
+        .url_cache
+        .get_url_by_code(state.db.as_ref(), &short_code)
+        .await?;
+
+    Ok(click_stream(
+        state.click_events.subscribe(),
+        state.shutdown.clone(),
+        move |event| event.url_id == url.id,
+    ))
+}
+
+/// Clicks on every link. Public like the dashboard that lists them and the
+/// per-link streams, events carry no visitor IPs or user agents.
+pub async fn all_clicks(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    click_stream(
+        state.click_events.subscribe(),
+        state.shutdown.clone(),
+        |_| true,
+    )
+}
+
+/// Ends when the server starts shutting down, an open stream would
+/// otherwise hold up the graceful shutdown forever.
+fn click_stream(
+    receiver: broadcast::Receiver<ClickEvent>,
+    shutdown: watch::Receiver<bool>,
+    filter: impl Fn(&ClickEvent) -> bool + Send + 'static,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = BroadcastStream::new(receiver).filter_map(move |event| {
+        match event {
+            Ok(event) if filter(&event) => Event::default()
+                .event("click")
+                .json_data(&event)
+                .ok()
+                .map(|event| Some(Ok(event))),
+            Ok(_) => None,
+            // the subscriber fell behind and missed some clicks
+            Err(_) => Some(Some(Ok(Event::default().event("lagged").data("")))),
+        }
+    });
+    let stop = WatchStream::new(shutdown)
+        .filter(|stopping| *stopping)
+        .map(|_| None);
+
+    let stream = events.merge(stop).map_while(std::convert::identity);
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL))
+}
@@ -0,0 +1,45 @@
+use crate::AppState;
+use axum::{
+    extract::State,
+    http::{StatusCode, header},
+    response::{IntoResponse, Response},
+};
+
+/// Prometheus text exposition. Gauges for state owned elsewhere are
+/// sampled at scrape time.
+pub async fn metrics(State(state): State<AppState>) -> Response {
+    let Some(handle) = &state.metrics else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let clicks = state.clicks.stats();
+    metrics::gauge!("click_pipeline_queue_depth").set(clicks.queue_depth as f64);
+    metrics::gauge!("click_pipeline_queue_capacity").set(clicks.queue_capacity as f64);
+    for (outcome, count) in [
+        ("enqueued", clicks.enqueued),
+        ("written", clicks.written),
+        ("dropped", clicks.dropped),
+        ("failed", clicks.failed),
+    ] {
+        metrics::counter!("click_pipeline_clicks_total", "outcome" => outcome).absolute(count);
+    }
+
+    let pool = state.db.pool_stats();
+    metrics::gauge!("db_pool_connections", "state" => "idle").set(pool.idle);
+    metrics::gauge!("db_pool_connections", "state" => "in_use")
+        .set(pool.size.saturating_sub(pool.idle));
+    metrics::gauge!("db_pool_max_connections").set(pool.max_connections);
+
+    let cache = state.url_cache.stats();
+    metrics::gauge!("url_cache_entries").set(cache.entries as f64);
+    metrics::counter!("url_cache_lookups_total", "result" => "hit").absolute(cache.hits);
+    metrics::counter!("url_cache_lookups_total", "result" => "miss").absolute(cache.misses);
+
+    metrics::gauge!("rate_limiter_tracked_ips").set(state.rate_limiter.tracked_ips() as f64);
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    )
+        .into_response()
+}
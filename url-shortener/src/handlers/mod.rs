@@ -1,4 +1,9 @@
+pub mod admin;
 pub mod analytics;
+pub mod health;
+pub mod live;
+pub mod metrics;
 pub mod redirect;
 pub mod shorten;
 pub mod web;
+pub mod webhooks;
@@ -1,41 +1,53 @@
 use crate::AppState;
-use crate::db::queries;
 use crate::error::{AppError, AppResult};
+use crate::extractors::ClientIp;
+use crate::models::NewClick;
+use crate::services::rate_limiter::RateLimitScope;
+use crate::services::referer;
+use crate::services::user_agent::classify_device;
 use axum::{
-    extract::{ConnectInfo, Path, State},
+    extract::{Path, State},
     http::{HeaderMap, header},
     response::{IntoResponse, Redirect},
 };
 use chrono::Utc;
-use std::net::SocketAddr;
 
 pub async fn redirect(
     State(state): State<AppState>,
     Path(short_code): Path<String>,
     headers: HeaderMap,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ClientIp(ip): ClientIp,
 ) -> AppResult<impl IntoResponse> {
     tracing::info!(
         "Redirect requested for code: {} from IP: {}",
         short_code,
-        addr.ip()
+        ip
     );
 
-    if !state.rate_limiter.check(addr.ip()) {
-        tracing::warn!("Rate limit exceeded for IP: {}", addr.ip().to_string());
-        return Err(AppError::RateLimitExceeded);
+    if let Err(e) = state.rate_limiter.check(RateLimitScope::Redirect, ip) {
+        tracing::warn!("Rate limit exceeded for IP: {}", ip.to_string());
+        return Err(e);
     }
 
-    let url = queries::get_url_by_code(&state.db, &short_code).await?;
+    let url = state
+        .url_cache
+        .get_url_by_code(state.db.as_ref(), &short_code)
+        .await?;
 
-    if let Some(expires_at) = url.expires_at {
-        if Utc::now() > expires_at {
-            tracing::warn!("Attempted to access expired URL: {}", short_code);
-            return Err(AppError::UrlExpired);
-        }
+    if let Some(expires_at) = url.expires_at
+        && Utc::now() > expires_at
+    {
+        tracing::warn!("Attempted to access expired URL: {}", short_code);
+        return Err(AppError::UrlExpired);
     }
 
-    let ip_address = Some(addr.ip().to_string());
+    let ip_address = match state.ip_anonymizer.anonymize(state.db.as_ref(), ip).await {
+        Ok(ip_address) => Some(ip_address),
+        Err(e) => {
+            tracing::error!("Failed to anonymize IP, storing none: {}", e);
+            None
+        }
+    };
     let user_agent = headers
         .get(header::USER_AGENT)
         .and_then(|v| v.to_str().ok())
@@ -45,12 +57,26 @@ pub async fn redirect(
         .and_then(|v| v.to_str().ok())
         .map(String::from);
 
-    let db = state.db.clone();
-    let url_id = url.id;
-    tokio::spawn(async move {
-        let _ = queries::record_click(&db, url_id.clone(), ip_address, user_agent, referer).await;
-        let _ = queries::increment_click(&db, url_id).await;
-    });
+    let device = classify_device(user_agent.as_deref());
+    let own_domain = referer::referer_domain(&state.config.load().server.base_url);
+    let referer_info = referer::classify(referer.as_deref(), own_domain.as_deref());
+    let click = NewClick {
+        url_id: url.id,
+        short_code,
+        clicked_at: Utc::now(),
+        ip_address,
+        user_agent,
+        referer,
+        referer_domain: referer_info.domain,
+        channel: referer_info.channel.as_str(),
+        device,
+    };
+    if !state.clicks.submit(click).await {
+        tracing::warn!(
+            "Click queue is full, dropping click for: {}",
+            url.short_code
+        );
+    }
 
     Ok(Redirect::to(&url.original_url))
 }
@@ -1,53 +1,183 @@
-use std::net::SocketAddr;
-
 use crate::AppState;
-use crate::db::queries;
 use crate::error::{AppError, AppResult};
-use crate::models::{CreateUrlRequest, CreateUrlResponse, Url};
-use crate::services::shorten::{generate_unique_code, validate_custom_code, validate_url};
-use axum::extract::ConnectInfo;
-use axum::{Json, extract::State};
+use crate::extractors::ClientIp;
+use crate::models::{
+    BulkCreateParams, BulkCreateResponse, BulkItemResult, BulkItemStatus, CreateUrlRequest,
+    CreateUrlResponse, Url,
+};
+use crate::services::rate_limiter::RateLimitScope;
+use crate::services::shorten::{create_link, create_links, prepare_link, prepare_links};
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
 
 pub async fn create_short_url(
     State(state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<CreateUrlRequest>,
 ) -> AppResult<Json<CreateUrlResponse>> {
-    let ip = addr.ip();
     tracing::info!("[CREATE_SHORT_URL] request from IP: {}", ip);
 
-    if !state.rate_limiter.check(ip) {
+    if let Err(e) = state.rate_limiter.check(RateLimitScope::Create, ip) {
         tracing::warn!("[CREATE_SHORT_URL] rate limit exceeded for IP: {}", ip);
-        return Err(AppError::RateLimitExceeded);
+        return Err(e);
+    }
+
+    // one snapshot for the whole request, a reload may land in between awaits
+    let config = state.config.load_full();
+
+    let new_url = prepare_link(state.db.as_ref(), payload, config.policy.short_code_length).await?;
+    let url = create_link(state.db.as_ref(), &new_url).await?;
+
+    Ok(Json(link_response(
+        &config.server.base_url,
+        url,
+        new_url.tags,
+    )))
+}
+
+/// Creates a batch of links in one transaction. Every item counts against
+/// the create quota. Answers 200 when all items were created, 207 when only
+/// some were and 400 when none were. Unless the batch is atomic, a code
+/// taken by another request since it was checked only fails its own item.
+pub async fn create_short_urls(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Query(params): Query<BulkCreateParams>,
+    Json(payload): Json<Vec<CreateUrlRequest>>,
+) -> AppResult<(StatusCode, Json<BulkCreateResponse>)> {
+    tracing::info!(
+        "[CREATE_SHORT_URLS] {} items from IP: {}",
+        payload.len(),
+        ip
+    );
+
+    let config = state.config.load_full();
+
+    if payload.is_empty() {
+        return Err(AppError::Validation(
+            "At least one link is required".to_string(),
+        ));
+    }
+    if payload.len() > config.policy.bulk_max_items {
+        return Err(AppError::Validation(format!(
+            "At most {} links can be created at once, got {}",
+            config.policy.bulk_max_items,
+            payload.len()
+        )));
     }
 
-    validate_url(&payload.url)?;
+    let items = u32::try_from(payload.len()).unwrap_or(u32::MAX);
+    if let Err(e) = state.rate_limiter.check_n(RateLimitScope::Bulk, ip, items) {
+        tracing::warn!("[CREATE_SHORT_URLS] rate limit exceeded for IP: {}", ip);
+        return Err(e);
+    }
+
+    let prepared = prepare_links(state.db.as_ref(), payload, config.policy.short_code_length).await;
 
-    let short_code = if let Some(custom_code) = payload.custom_code {
-        validate_custom_code(&custom_code)?;
+    let mut failed = prepared.iter().filter(|result| result.is_err()).count();
+    let rejected = params.atomic && failed > 0;
 
-        if queries::code_exists(&state.db, &custom_code).await? {
-            return Err(AppError::CodeAlreadyExists);
+    let mut results = Vec::with_capacity(prepared.len());
+    let mut new_urls = Vec::with_capacity(prepared.len() - failed);
+    for (index, result) in prepared.into_iter().enumerate() {
+        match result {
+            Ok(new_url) if !rejected => new_urls.push((index, new_url)),
+            Ok(_) => results.push(item_result(index, BulkItemStatus::Skipped)),
+            Err(e) => results.push(BulkItemResult {
+                error: Some(e.to_string()),
+                kind: Some(e.kind()),
+                ..item_result(index, BulkItemStatus::Failed)
+            }),
         }
+    }
 
-        custom_code
-    } else {
-        generate_unique_code(&state.db, state.config.short_code_length).await?
+    if !new_urls.is_empty() {
+        let (indexes, new_urls): (Vec<_>, Vec<_>) = new_urls.into_iter().unzip();
+        let created = match create_links(state.db.as_ref(), &new_urls).await {
+            // a code free when checked was taken since, only that item fails
+            Err(AppError::CodeAlreadyExists) if !params.atomic => {
+                tracing::warn!("[CREATE_SHORT_URLS] code conflict, creating items one by one");
+                let mut created = Vec::with_capacity(new_urls.len());
+                for new_url in &new_urls {
+                    created.push(create_link(state.db.as_ref(), new_url).await);
+                }
+                created
+            }
+            result => result?.into_iter().map(Ok).collect(),
+        };
+
+        for ((index, result), new_url) in indexes.into_iter().zip(created).zip(new_urls) {
+            results.push(match result {
+                Ok(url) => BulkItemResult {
+                    link: Some(link_response(&config.server.base_url, url, new_url.tags)),
+                    ..item_result(index, BulkItemStatus::Created)
+                },
+                Err(e) => {
+                    failed += 1;
+                    BulkItemResult {
+                        error: Some(e.to_string()),
+                        kind: Some(e.kind()),
+                        ..item_result(index, BulkItemStatus::Failed)
+                    }
+                }
+            });
+        }
+    }
+    results.sort_by_key(|result| result.index);
+
+    let created = results
+        .iter()
+        .filter(|result| result.status == BulkItemStatus::Created)
+        .count();
+    let status = match (created, failed) {
+        (_, 0) => StatusCode::OK,
+        (0, _) => StatusCode::BAD_REQUEST,
+        _ => StatusCode::MULTI_STATUS,
     };
 
-    let url = queries::create_url(&state.db, &payload.url, &short_code, payload.expires_at).await?;
+    tracing::info!(
+        "[CREATE_SHORT_URLS] created {}, failed {}{}",
+        created,
+        failed,
+        if rejected { ", batch rejected" } else { "" }
+    );
 
-    let short_url = format!("{}/{}", state.config.base_url, url.short_code);
+    Ok((
+        status,
+        Json(BulkCreateResponse {
+            created,
+            failed,
+            results,
+        }),
+    ))
+}
 
-    Ok(Json(CreateUrlResponse {
-        short_url,
+pub async fn list_urls(State(state): State<AppState>) -> AppResult<Json<Vec<Url>>> {
+    let urls = state.db.list_all_urls().await?;
+    Ok(Json(urls))
+}
+
+fn link_response(base_url: &str, url: Url, tags: Vec<String>) -> CreateUrlResponse {
+    CreateUrlResponse {
+        short_url: format!("{}/{}", base_url, url.short_code),
         short_code: url.short_code,
         original_url: url.original_url,
         expires_at: url.expires_at,
-    }))
+        tags,
+        campaign: url.campaign,
+        owner: url.owner,
+    }
 }
 
-pub async fn list_urls(State(state): State<AppState>) -> AppResult<Json<Vec<Url>>> {
-    let urls = queries::list_all_urls(&state.db).await?;
-    Ok(Json(urls))
+fn item_result(index: usize, status: BulkItemStatus) -> BulkItemResult {
+    BulkItemResult {
+        index,
+        status,
+        link: None,
+        error: None,
+        kind: None,
+    }
 }
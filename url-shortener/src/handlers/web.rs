@@ -1,15 +1,120 @@
 use crate::AppState;
-use crate::db::queries;
 use crate::error::AppResult;
-use crate::templates::{DashboardTemplate, IndexTemplate};
+use crate::extractors::ClientIp;
+use crate::models::DateRange;
+use crate::services::charts;
+use crate::services::rate_limiter::RateLimitScope;
+use crate::templates::{DashboardTemplate, IndexTemplate, StatsTemplate};
 use askama_axum::IntoResponse;
-use axum::extract::State;
+use axum::extract::{Path, Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::Response;
 
-pub async fn index() -> impl IntoResponse {
-    IndexTemplate {}
+// compiled in like the templates, so pages work whatever the working directory
+const ASSETS: [(&str, &str, &str); 3] = [
+    (
+        "css/styles.css",
+        "text/css; charset=utf-8",
+        include_str!("../../static/css/styles.css"),
+    ),
+    (
+        "js/app.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../../static/js/app.js"),
+    ),
+    (
+        "js/dashboard.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../../static/js/dashboard.js"),
+    ),
+];
+
+pub async fn index(State(state): State<AppState>) -> impl IntoResponse {
+    IndexTemplate {
+        base_path: state.config.load().base_path(),
+    }
 }
 
 pub async fn dashboard(State(state): State<AppState>) -> AppResult<impl IntoResponse> {
-    let urls = queries::list_all_urls(&state.db).await?;
-    Ok(DashboardTemplate { urls })
+    let urls = state.db.list_all_urls().await?;
+    Ok(DashboardTemplate {
+        base_path: state.config.load().base_path(),
+        urls,
+    })
+}
+
+pub async fn link_stats(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    Path(short_code): Path<String>,
+    Query(range): Query<DateRange>,
+) -> AppResult<impl IntoResponse> {
+    state.rate_limiter.check(RateLimitScope::Stats, ip)?;
+
+    range.validate()?;
+
+    let url = state
+        .url_cache
+        .get_url_by_code(state.db.as_ref(), &short_code)
+        .await?;
+    let tags = state.db.get_url_tags(&url.id).await?;
+    let stats = state.db.get_url_stats(url.id.clone(), &range).await?;
+
+    let clicks_chart = charts::clicks_over_time(&stats.clicks_by_date);
+    let country_chart = charts::breakdown(
+        "Countries",
+        stats
+            .top_countries
+            .iter()
+            .map(|c| (c.country.as_str(), c.count)),
+    );
+    let referer_chart = charts::breakdown(
+        "Referers",
+        stats
+            .top_referer_domains
+            .iter()
+            .map(|r| (r.domain.as_str(), r.count)),
+    );
+    let channel_chart = charts::breakdown(
+        "Channels",
+        stats.channels.iter().map(|c| (c.channel.as_str(), c.count)),
+    );
+    let device_chart = charts::breakdown(
+        "Devices",
+        stats
+            .top_devices
+            .iter()
+            .map(|d| (d.device.as_str(), d.count)),
+    );
+
+    let config = state.config.load();
+
+    Ok(StatsTemplate {
+        base_path: config.base_path(),
+        short_url: format!("{}/{}", config.server.base_url, url.short_code),
+        url,
+        tags,
+        range,
+        stats,
+        clicks_chart,
+        country_chart,
+        referer_chart,
+        channel_chart,
+        device_chart,
+    })
+}
+
+/// Stylesheets and scripts under `static/`.
+pub async fn asset(Path(path): Path<String>) -> Response {
+    match ASSETS.iter().find(|(name, _, _)| *name == path) {
+        Some((_, content_type, body)) => (
+            [
+                (header::CONTENT_TYPE, *content_type),
+                (header::CACHE_CONTROL, "public, max-age=3600"),
+            ],
+            *body,
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
 }
@@ -0,0 +1,179 @@
+use crate::AppState;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    CreateWebhookRequest, CreateWebhookResponse, DeliveryLogParams, Webhook, WebhookDelivery,
+    WebhookEvent, WebhookOwnerParams,
+};
+use crate::services::admin_auth::is_admin;
+use crate::services::shorten::validate_url;
+use crate::services::webhook_targets::check_target;
+use crate::services::webhooks::generate_secret;
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+};
+
+const DEFAULT_DELIVERY_LOG_LIMIT: u32 = 50;
+const MAX_DELIVERY_LOG_LIMIT: u32 = 500;
+
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateWebhookRequest>,
+) -> AppResult<(StatusCode, Json<CreateWebhookResponse>)> {
+    let config = state.config.load();
+
+    // an ownerless webhook receives events for every link
+    match payload.owner.as_deref().map(str::trim) {
+        Some("") => {
+            return Err(AppError::Validation(
+                "'owner' must not be empty".to_string(),
+            ));
+        }
+        Some(_) => {}
+        None => {
+            if !is_admin(&headers, config.server.admin_token.as_deref()) {
+                return Err(AppError::Validation(
+                    "'owner' is required, webhooks for every link need the admin token".to_string(),
+                ));
+            }
+        }
+    }
+
+    validate_url(&payload.url)?;
+    if !config.webhooks.allow_private_targets {
+        check_target(&payload.url).await?;
+    }
+
+    if payload.events.is_empty() {
+        return Err(AppError::Validation(
+            "At least one event is required".to_string(),
+        ));
+    }
+
+    let wants_threshold = payload.events.contains(&WebhookEvent::ClickThreshold);
+    match payload.click_threshold {
+        Some(threshold) if threshold < 1 => {
+            return Err(AppError::Validation(
+                "click_threshold must be at least 1".to_string(),
+            ));
+        }
+        None if wants_threshold => {
+            return Err(AppError::Validation(
+                "click_threshold is required for link.click_threshold events".to_string(),
+            ));
+        }
+        _ => {}
+    }
+
+    let secret = payload.secret.unwrap_or_else(generate_secret);
+    if secret.len() < 16 {
+        return Err(AppError::Validation(
+            "Webhook secret must be at least 16 characters".to_string(),
+        ));
+    }
+
+    let mut events: Vec<WebhookEvent> = Vec::with_capacity(payload.events.len());
+    for event in payload.events {
+        if !events.contains(&event) {
+            events.push(event);
+        }
+    }
+
+    let webhook = state
+        .db
+        .create_webhook(
+            payload.owner.as_deref(),
+            &payload.url,
+            &secret,
+            &events,
+            payload.click_threshold,
+        )
+        .await?;
+
+    tracing::info!("[WEBHOOKS] registered {} for {}", webhook.id, webhook.url);
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreateWebhookResponse { webhook, secret }),
+    ))
+}
+
+/// Webhooks of `?owner=`, or every webhook with the admin token.
+pub async fn list_webhooks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<WebhookOwnerParams>,
+) -> AppResult<Json<Vec<Webhook>>> {
+    let owner = params.owner.filter(|owner| !owner.is_empty());
+    if owner.is_none() && !is_admin(&headers, state.config.load().server.admin_token.as_deref()) {
+        return Err(AppError::Unauthorized);
+    }
+
+    let webhooks = state.db.list_webhooks(owner.as_deref()).await?;
+    Ok(Json(webhooks))
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<WebhookOwnerParams>,
+) -> AppResult<StatusCode> {
+    let webhook = state.db.get_webhook(&id).await?;
+    check_access(&state, &headers, &webhook, params.owner.as_deref())?;
+
+    state.db.delete_webhook(&webhook.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<DeliveryLogParams>,
+) -> AppResult<Json<Vec<WebhookDelivery>>> {
+    let webhook = state.db.get_webhook(&id).await?;
+    check_access(&state, &headers, &webhook, params.owner.as_deref())?;
+
+    if let Some(status) = params.status.as_deref()
+        && !matches!(status, "pending" | "sending" | "delivered" | "failed")
+    {
+        return Err(AppError::Validation(
+            "status must be pending, sending, delivered or failed".to_string(),
+        ));
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_DELIVERY_LOG_LIMIT)
+        .clamp(1, MAX_DELIVERY_LOG_LIMIT);
+
+    let deliveries = state
+        .db
+        .list_deliveries(&webhook.id, params.status.as_deref(), limit)
+        .await?;
+
+    Ok(Json(deliveries))
+}
+
+/// Lets the admin token act on any webhook and `?owner=` on that owner's
+/// webhooks. Someone else's webhook looks missing, an ownerless one needs
+/// the token.
+fn check_access(
+    state: &AppState,
+    headers: &HeaderMap,
+    webhook: &Webhook,
+    owner: Option<&str>,
+) -> AppResult<()> {
+    if is_admin(headers, state.config.load().server.admin_token.as_deref()) {
+        return Ok(());
+    }
+
+    match (webhook.owner.as_deref(), owner) {
+        (Some(webhook_owner), Some(owner)) if webhook_owner == owner => Ok(()),
+        (None, _) => Err(AppError::Unauthorized),
+        _ => Err(AppError::WebhookNotFound),
+    }
+}
@@ -0,0 +1,173 @@
+//! URL shortener with click analytics, run as the `url-shortener` binary or
+//! mounted inside another axum application.
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! use url_shortener::{Shortener, config::{Config, ConfigArgs}, db::MemoryStore};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let mut config = Config::default();
+//! config.server.base_url = "http://localhost:3000/links".to_string();
+//!
+//! let shortener = Shortener::start(Arc::new(config), ConfigArgs::default(), Arc::new(MemoryStore::new()))?;
+//! let app = axum::Router::new().nest("/links", shortener.router());
+//! # let _ = app;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use axum::{
+    Router, middleware,
+    routing::{delete, get, post},
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::sync::{broadcast, watch};
+use tower_http::{cors::CorsLayer, trace::TraceLayer};
+
+use crate::{
+    config::Config,
+    db::Storage,
+    models::ClickEvent,
+    services::{
+        click_pipeline::ClickPipeline, config_reload::ConfigReloader, privacy::IpAnonymizer,
+        rate_limiter::RateLimiter, scheduler::Scheduler, url_cache::UrlCache,
+    },
+};
+
+pub mod config;
+pub mod db;
+pub mod error;
+mod extractors;
+mod handlers;
+pub mod models;
+mod server;
+pub mod services;
+mod templates;
+
+pub use server::{Shortener, run_migrations, serve};
+
+/// Shared by every handler, built by [`Shortener::start`].
+#[derive(Clone)]
+pub struct AppState {
+    pub(crate) db: Arc<dyn Storage>,
+    /// Swapped on reload, handlers see the new settings on their next request.
+    pub(crate) config: Arc<ArcSwap<Config>>,
+    pub(crate) config_reloader: Arc<ConfigReloader>,
+    pub(crate) rate_limiter: Arc<RateLimiter>,
+    pub(crate) click_events: broadcast::Sender<ClickEvent>,
+    pub(crate) clicks: ClickPipeline,
+    pub(crate) url_cache: Arc<UrlCache>,
+    pub(crate) ip_anonymizer: Arc<IpAnonymizer>,
+    pub(crate) scheduler: Arc<Scheduler>,
+    /// `None` when the embedding application opted out of metrics.
+    pub(crate) metrics: Option<PrometheusHandle>,
+    /// Flips to true once shutdown starts, long-lived responses end on it.
+    pub(crate) shutdown: watch::Receiver<bool>,
+}
+
+impl AppState {
+    pub fn db(&self) -> &Arc<dyn Storage> {
+        &self.db
+    }
+
+    /// Settings in effect, including runtime reloads.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.load_full()
+    }
+}
+
+/// Every page and API route of the shortener. Paths are relative, so the
+/// router can be nested under a prefix as long as `server.base_url` ends
+/// with that same prefix. Client IPs come from `ConnectInfo<SocketAddr>`,
+/// serve it with `into_make_service_with_connect_info`.
+///
+/// The `/api/admin` routes are only mounted when `server.admin_token` is
+/// set, behind that token and without CORS. `/metrics` is left out when the
+/// shortener was started without a recorder.
+pub fn build_router(state: AppState) -> Router {
+    let mut router = Router::new()
+        .route("/", get(handlers::web::index))
+        .route("/dashboard", get(handlers::web::dashboard))
+        .route("/dashboard/:short_code", get(handlers::web::link_stats))
+        .route("/api/urls", get(handlers::shorten::list_urls))
+        .route(
+            "/api/webhooks",
+            get(handlers::webhooks::list_webhooks).post(handlers::webhooks::create_webhook),
+        )
+        .route(
+            "/api/webhooks/:id",
+            delete(handlers::webhooks::delete_webhook),
+        )
+        .route(
+            "/api/webhooks/:id/deliveries",
+            get(handlers::webhooks::list_deliveries),
+        )
+        .route("/healthz", get(handlers::health::healthz))
+        .route("/readyz", get(handlers::health::readyz))
+        .route("/api/live", get(handlers::live::all_clicks))
+        .route("/api/shorten", post(handlers::shorten::create_short_url))
+        .route(
+            "/api/shorten/bulk",
+            post(handlers::shorten::create_short_urls),
+        )
+        .route("/api/stats", get(handlers::analytics::get_aggregate_stats))
+        .route(
+            "/api/stats/compare",
+            get(handlers::analytics::compare_links),
+        )
+        .route(
+            "/api/urls/:short_code",
+            get(handlers::analytics::get_url_stats),
+        )
+        .route(
+            "/api/urls/:short_code/clicks",
+            get(handlers::analytics::export_clicks),
+        )
+        .route(
+            "/api/urls/:short_code/live",
+            get(handlers::live::url_clicks),
+        )
+        .route(
+            "/api/urls/:short_code/qr",
+            get(handlers::analytics::get_qr_code),
+        )
+        .route("/:short_code", get(handlers::redirect::redirect))
+        .route("/static/*path", get(handlers::web::asset))
+        .layer(CorsLayer::permissive());
+
+    if state.metrics.is_some() {
+        router = router.route("/metrics", get(handlers::metrics::metrics));
+    }
+
+    match state.config.load().server.admin_token.as_deref() {
+        Some(token) => router = router.nest("/api/admin", admin_router(Arc::from(token))),
+        None => tracing::info!("No admin token configured, the admin API is disabled"),
+    }
+
+    router
+        .layer(middleware::from_fn(services::metrics::track_requests))
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+fn admin_router(token: Arc<str>) -> Router<AppState> {
+    Router::new()
+        .route("/backup", post(handlers::admin::backup))
+        .route("/click-pipeline", get(handlers::admin::click_pipeline))
+        .route("/config", get(handlers::admin::config))
+        .route("/config/reload", post(handlers::admin::reload_config))
+        .route("/jobs", get(handlers::admin::jobs))
+        .route("/links/export", get(handlers::admin::export_links))
+        .route("/links/import", post(handlers::admin::import_links))
+        .route("/rollups/rebuild", post(handlers::admin::rebuild_rollups))
+        .route("/rollups/verify", get(handlers::admin::verify_rollups))
+        .route("/url-cache", get(handlers::admin::url_cache))
+        .layer(middleware::from_fn_with_state(
+            token,
+            services::admin_auth::require_admin_token,
+        ))
+}
@@ -1,12 +1,46 @@
 pub mod stats;
 pub mod url;
+pub mod webhook;
 
+pub use stats::AggregateStats;
+pub use stats::ChannelCount;
 pub use stats::Click;
+pub use stats::ClickCursor;
+pub use stats::ClickEvent;
+pub use stats::ClickExportParams;
 pub use stats::ClickStats;
+pub use stats::CompareParams;
 pub use stats::CountryCount;
 pub use stats::DateCount;
+pub use stats::DateRange;
+pub use stats::DeviceCount;
+pub use stats::LinkClickCount;
+pub use stats::LinkFilter;
+pub use stats::LinkSeries;
+pub use stats::NewClick;
 pub use stats::RefererCount;
+pub use stats::RefererDomainCount;
+pub use stats::RollupReport;
+pub use stats::StatsMetadata;
 
+pub use url::BulkCreateParams;
+pub use url::BulkCreateResponse;
+pub use url::BulkItemResult;
+pub use url::BulkItemStatus;
 pub use url::CreateUrlRequest;
 pub use url::CreateUrlResponse;
+pub use url::LinkExportParams;
+pub use url::LinkImportParams;
+pub use url::LinkRecord;
+pub use url::NewUrl;
 pub use url::Url;
+
+pub use webhook::CreateWebhookRequest;
+pub use webhook::CreateWebhookResponse;
+pub use webhook::DeliveryLogParams;
+pub use webhook::NewDelivery;
+pub use webhook::PendingDelivery;
+pub use webhook::Webhook;
+pub use webhook::WebhookDelivery;
+pub use webhook::WebhookEvent;
+pub use webhook::WebhookOwnerParams;
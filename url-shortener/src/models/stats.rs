@@ -1,6 +1,11 @@
+use std::{fmt, str::FromStr};
+
 use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::config::PrivacyMode;
+use crate::error::AppError;
+
 #[derive(Clone, Debug, Deserialize, Serialize, sqlx::FromRow)]
 pub struct Click {
     pub id: String,
@@ -11,6 +16,48 @@ pub struct Click {
     pub referer: Option<String>,
     pub country: Option<String>,
     pub city: Option<String>,
+    pub device: Option<String>,
+    pub referer_domain: Option<String>,
+    pub channel: Option<String>,
+}
+
+/// A click waiting to be written by the click pipeline.
+#[derive(Clone, Debug)]
+pub struct NewClick {
+    pub url_id: String,
+    pub short_code: String,
+    pub clicked_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    pub referer_domain: Option<String>,
+    pub channel: &'static str,
+    pub device: &'static str,
+}
+
+impl NewClick {
+    /// (dimension, value) pairs this click adds to the daily rollups.
+    pub fn dimensions(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("referer", self.referer.as_deref()),
+            ("referer_domain", self.referer_domain.as_deref()),
+            ("channel", Some(self.channel)),
+            ("device", Some(self.device)),
+            ("ip", self.ip_address.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(dimension, value)| value.map(|value| (dimension, value)))
+    }
+}
+
+/// Published to live subscribers whenever a click is recorded.
+#[derive(Clone, Debug, Serialize)]
+pub struct ClickEvent {
+    pub url_id: String,
+    pub short_code: String,
+    pub clicked_at: DateTime<Utc>,
+    pub referer: Option<String>,
+    pub country: Option<String>,
 }
 
 #[derive(Debug, Serialize, sqlx::FromRow)]
@@ -31,6 +78,25 @@ pub struct RefererCount {
     pub count: i64,
 }
 
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct RefererDomainCount {
+    #[sqlx(rename = "referer_domain")]
+    pub domain: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ChannelCount {
+    pub channel: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct DeviceCount {
+    pub device: String,
+    pub count: i64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ClickStats {
     pub total_clicks: i64,
@@ -38,4 +104,180 @@ pub struct ClickStats {
     pub clicks_by_date: Vec<DateCount>,
     pub top_countries: Vec<CountryCount>,
     pub top_referers: Vec<RefererCount>,
+    pub top_referer_domains: Vec<RefererDomainCount>,
+    pub channels: Vec<ChannelCount>,
+    pub top_devices: Vec<DeviceCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<StatsMetadata>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsMetadata {
+    pub ip_mode: PrivacyMode,
+    pub unique_ips: &'static str,
+}
+
+impl StatsMetadata {
+    pub fn new(ip_mode: PrivacyMode) -> Self {
+        let unique_ips = match ip_mode {
+            PrivacyMode::Full => "distinct visitor IP addresses",
+            PrivacyMode::Truncate => {
+                "distinct visitor networks (/24 for IPv4, /48 for IPv6), visitors sharing a network count once"
+            }
+            PrivacyMode::Hash => {
+                "distinct salted IP hashes, the salt rotates daily so a visitor returning on another day counts again"
+            }
+        };
+
+        Self {
+            ip_mode,
+            unique_ips,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let (Some(from), Some(to)) = (self.from, self.to)
+            && from > to
+        {
+            return Err(AppError::Validation(
+                "'from' must not be after 'to'".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Narrows aggregate stats to links of an owner, with a tag and/or in a
+/// campaign.
+#[derive(Debug, Default, Deserialize)]
+pub struct LinkFilter {
+    pub owner: Option<String>,
+    pub tag: Option<String>,
+    pub campaign: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CompareParams {
+    pub codes: String,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct LinkClickCount {
+    pub short_code: String,
+    pub original_url: String,
+    pub clicks: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AggregateStats {
+    pub total_links: i64,
+    pub top_links: Vec<LinkClickCount>,
+    #[serde(flatten)]
+    pub stats: ClickStats,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkSeries {
+    pub short_code: String,
+    pub total_clicks: i64,
+    pub clicks_by_date: Vec<DateCount>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClickExportParams {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+// position of the first click of a page, ordered by (clicked_at, id)
+#[derive(Clone, Debug)]
+pub struct ClickCursor {
+    pub clicked_at: DateTime<Utc>,
+    pub id: String,
+}
+
+impl fmt::Display for ClickCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.clicked_at.timestamp(), self.id)
+    }
+}
+
+impl FromStr for ClickCursor {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::Validation("Invalid cursor".to_string());
+
+        let (timestamp, id) = s.split_once('-').ok_or_else(invalid)?;
+        let clicked_at = timestamp
+            .parse::<i64>()
+            .ok()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .ok_or_else(invalid)?;
+
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            clicked_at,
+            id: id.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RollupReport {
+    pub consistent: bool,
+    pub days_checked: i64,
+    pub mismatched_totals: i64,
+    pub mismatched_rollups: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = ClickCursor {
+            clicked_at: DateTime::from_timestamp(1_768_130_000, 0).unwrap(),
+            id: "9f2c0a".to_string(),
+        };
+
+        let parsed: ClickCursor = cursor.to_string().parse().unwrap();
+        assert_eq!(parsed.clicked_at, cursor.clicked_at);
+        assert_eq!(parsed.id, cursor.id);
+        assert_eq!(cursor.to_string(), "1768130000-9f2c0a");
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in [
+            "",
+            "1768130000",
+            "1768130000-",
+            "-9f2c0a",
+            "now-9f2c0a",
+            "1768130000-9f2c0g",
+            "1768130000-9f2c' OR '1'='1",
+            "99999999999999999999-9f2c0a",
+            "9223372036854775807-9f2c0a",
+        ] {
+            assert!(
+                matches!(cursor.parse::<ClickCursor>(), Err(AppError::Validation(_))),
+                "accepted {:?}",
+                cursor
+            );
+        }
+    }
 }
@@ -1,6 +1,8 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::services::transfer::LinkFormat;
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Url {
     pub id: String,
@@ -9,6 +11,36 @@ pub struct Url {
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
     pub click_count: i32,
+    pub campaign: Option<String>,
+    pub owner: Option<String>,
+}
+
+/// A validated link ready to be inserted.
+#[derive(Debug, Clone)]
+pub struct NewUrl {
+    pub original_url: String,
+    pub short_code: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Kept from the source when importing, otherwise now.
+    pub created_at: Option<DateTime<Utc>>,
+    pub campaign: Option<String>,
+    pub owner: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// Portable form of a link used by export and import.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkRecord {
+    pub url: String,
+    pub code: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub campaign: Option<String>,
+    #[serde(default)]
+    pub owner: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -16,6 +48,10 @@ pub struct CreateUrlRequest {
     pub url: String,
     pub custom_code: Option<String>,
     pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub campaign: Option<String>,
+    pub owner: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -24,4 +60,56 @@ pub struct CreateUrlResponse {
     pub short_code: String,
     pub original_url: String,
     pub expires_at: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub campaign: Option<String>,
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LinkExportParams {
+    /// Taken from the `Accept` header when omitted, JSON unless it asks for CSV.
+    pub format: Option<LinkFormat>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct LinkImportParams {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct BulkCreateParams {
+    /// Create nothing unless every item is valid.
+    #[serde(default)]
+    pub atomic: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkCreateResponse {
+    pub created: usize,
+    pub failed: usize,
+    /// One per item, in request order.
+    pub results: Vec<BulkItemResult>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkItemResult {
+    pub index: usize,
+    pub status: BulkItemStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<CreateUrlResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// `AppError` kind of the error, e.g. `validation` or `code_already_exists`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<&'static str>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkItemStatus {
+    Created,
+    Failed,
+    /// Valid, but not created because another item of an atomic batch failed.
+    Skipped,
 }
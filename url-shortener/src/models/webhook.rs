@@ -0,0 +1,122 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "link.created")]
+    LinkCreated,
+    #[serde(rename = "link.clicked")]
+    LinkClicked,
+    #[serde(rename = "link.expired")]
+    LinkExpired,
+    #[serde(rename = "link.click_threshold")]
+    ClickThreshold,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::LinkCreated => "link.created",
+            WebhookEvent::LinkClicked => "link.clicked",
+            WebhookEvent::LinkExpired => "link.expired",
+            WebhookEvent::ClickThreshold => "link.click_threshold",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Webhook {
+    pub id: String,
+    pub owner: Option<String>,
+    pub url: String,
+    #[serde(serialize_with = "serialize_events")]
+    pub events: String,
+    pub click_threshold: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Webhook {
+    pub fn subscribes(&self, event: WebhookEvent) -> bool {
+        self.events.split(',').any(|name| name == event.as_str())
+    }
+}
+
+/// An outbox row to add, due right away.
+#[derive(Clone, Debug)]
+pub struct NewDelivery {
+    pub webhook_id: String,
+    pub event: WebhookEvent,
+    pub payload: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub owner: Option<String>,
+    pub events: Vec<WebhookEvent>,
+    pub click_threshold: Option<i64>,
+    pub secret: Option<String>,
+}
+
+/// Only returned on creation, the secret is not shown again.
+#[derive(Debug, Serialize)]
+pub struct CreateWebhookResponse {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+/// The owner a webhook request acts for. Without one, the admin token is
+/// required.
+#[derive(Debug, Deserialize)]
+pub struct WebhookOwnerParams {
+    pub owner: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub event: String,
+    #[serde(serialize_with = "serialize_json")]
+    pub payload: String,
+    pub status: String,
+    pub attempts: i64,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_status_code: Option<i64>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    /// End of the current send attempt's claim while `sending`.
+    pub locked_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveryLogParams {
+    pub owner: Option<String>,
+    pub status: Option<String>,
+    pub limit: Option<u32>,
+}
+
+/// A due outbox row joined with where and how to send it.
+#[derive(Debug, sqlx::FromRow)]
+pub struct PendingDelivery {
+    pub id: String,
+    pub event: String,
+    pub payload: String,
+    pub attempts: i64,
+    pub url: String,
+    pub secret: String,
+}
+
+fn serialize_events<S: Serializer>(events: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(events.split(',').filter(|event| !event.is_empty()))
+}
+
+// payloads are stored as JSON text, show them as objects rather than strings
+fn serialize_json<S: Serializer>(payload: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match serde_json::from_str::<serde_json::Value>(payload) {
+        Ok(value) => value.serialize(serializer),
+        Err(_) => serializer.serialize_str(payload),
+    }
+}
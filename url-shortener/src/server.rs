@@ -0,0 +1,317 @@
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use axum::Router;
+use metrics_exporter_prometheus::PrometheusHandle;
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
+
+use crate::{
+    AppState, build_router,
+    config::{Config, ConfigArgs},
+    db::{self, Storage},
+    services::{
+        self,
+        click_pipeline::{ClickPipeline, ClickWriterHandle},
+        config_reload::ConfigReloader,
+        privacy::IpAnonymizer,
+        rate_limiter::RateLimiter,
+        retention,
+        scheduler::{Scheduler, job},
+        url_cache::UrlCache,
+        webhooks::{self, WebhookDispatcher},
+    },
+};
+
+// slow live subscribers skip events once they fall this far behind
+const CLICK_EVENTS_CAPACITY: usize = 1024;
+
+/// Applies pending migrations and backfills data they introduce.
+pub async fn run_migrations(db: &dyn Storage, config: &Config) -> anyhow::Result<()> {
+    db.migrate().await?;
+    tracing::info!("Migrations completed successfully");
+
+    let own_domain = services::referer::referer_domain(&config.server.base_url);
+    let backfilled = services::referer::backfill(db, own_domain.as_deref()).await?;
+    if backfilled > 0 {
+        let report = db.rebuild_rollups().await?;
+        tracing::info!(
+            "Classified referers of {} older clicks, rollups consistent: {}",
+            backfilled,
+            report.consistent
+        );
+    }
+
+    Ok(())
+}
+
+/// A running shortener: the state its router serves and the background work
+/// spawned for it, which keeps running until [`Shortener::shutdown`].
+pub struct Shortener {
+    state: AppState,
+    click_writer: ClickWriterHandle,
+    shutdown: watch::Sender<bool>,
+}
+
+impl Shortener {
+    /// Starts the click writer, the rate limiter cleanup and the scheduled
+    /// jobs on a migrated `db`. Reloads read the file and environment
+    /// `config_args` points at, like the binary does. Installs the
+    /// process-wide Prometheus recorder, see [`Shortener::start_with_metrics`]
+    /// for applications that bring their own.
+    pub fn start(
+        config: Arc<Config>,
+        config_args: ConfigArgs,
+        db: Arc<dyn Storage>,
+    ) -> anyhow::Result<Self> {
+        let metrics = services::metrics::install_recorder()?;
+        Self::start_with_metrics(config, config_args, db, Some(metrics))
+    }
+
+    /// Like [`Shortener::start`], with `/metrics` rendered from `metrics`,
+    /// the handle of a recorder the application installed itself. `None`
+    /// leaves the route out; metrics are then recorded to whatever global
+    /// recorder is installed, if any.
+    pub fn start_with_metrics(
+        config: Arc<Config>,
+        config_args: ConfigArgs,
+        db: Arc<dyn Storage>,
+        metrics: Option<PrometheusHandle>,
+    ) -> anyhow::Result<Self> {
+        // Config::load validates, a config built in code hasn't been
+        let errors = config.validate();
+        if !errors.is_empty() {
+            anyhow::bail!("Invalid configuration:\n  - {}", errors.join("\n  - "));
+        }
+
+        let rate_limiter = Arc::new(RateLimiter::new(
+            config.rate_limits.redirect_requests_per_minute,
+            config.rate_limits.create_requests_per_minute,
+            config.rate_limits.bulk_items_per_minute,
+            config.rate_limits.stats_requests_per_minute,
+        ));
+        rate_limiter
+            .clone()
+            .spawn_cleanup(Duration::from_secs(config.rate_limits.cleanup_seconds));
+
+        let (click_events, _) = broadcast::channel(CLICK_EVENTS_CAPACITY);
+
+        let (clicks, click_writer) = ClickPipeline::spawn(
+            db.clone(),
+            click_events.clone(),
+            config.analytics.click_queue_capacity,
+            config.analytics.click_batch_size,
+        );
+
+        let url_cache = Arc::new(UrlCache::new(
+            config.cache.capacity,
+            Duration::from_secs(config.cache.ttl_seconds),
+        ));
+
+        let scheduler = Arc::new(Scheduler::new());
+
+        if let Some(days) = config.analytics.click_retention_days {
+            let db = db.clone();
+            scheduler.schedule(
+                "purge_old_clicks",
+                Duration::from_secs(config.analytics.click_purge_interval_seconds),
+                move || {
+                    let db = db.clone();
+                    job(async move { retention::purge_old_clicks(db.as_ref(), days).await })
+                },
+            );
+        }
+
+        if let Some(days) = config.policy.expired_link_retention_days {
+            let db = db.clone();
+            let url_cache = url_cache.clone();
+            scheduler.schedule(
+                "purge_expired_links",
+                Duration::from_secs(config.policy.expired_link_purge_interval_seconds),
+                move || {
+                    let db = db.clone();
+                    let url_cache = url_cache.clone();
+                    job(async move {
+                        retention::purge_expired_links(db.as_ref(), &url_cache, days).await
+                    })
+                },
+            );
+        }
+
+        if let Some(seconds) = config.database.vacuum_interval_seconds {
+            let db = db.clone();
+            scheduler.schedule("vacuum", Duration::from_secs(seconds), move || {
+                let db = db.clone();
+                job(async move { retention::vacuum(db.as_ref()).await })
+            });
+        }
+
+        let dispatcher = Arc::new(WebhookDispatcher::new(
+            db.clone(),
+            Duration::from_secs(config.webhooks.timeout_seconds),
+            config.webhooks.max_attempts,
+            config.webhooks.allow_private_targets,
+        )?);
+        scheduler.schedule(
+            "deliver_webhooks",
+            Duration::from_secs(config.webhooks.delivery_interval_seconds),
+            move || {
+                let dispatcher = dispatcher.clone();
+                job(async move { dispatcher.deliver_due().await })
+            },
+        );
+
+        {
+            let db = db.clone();
+            scheduler.schedule(
+                "notify_expired_links",
+                Duration::from_secs(config.policy.link_expiry_check_interval_seconds),
+                move || {
+                    let db = db.clone();
+                    job(async move { webhooks::notify_expired_links(db.as_ref()).await })
+                },
+            );
+        }
+
+        let shared_config = Arc::new(ArcSwap::new(config.clone()));
+        let config_reloader = Arc::new(ConfigReloader::new(
+            config_args,
+            shared_config.clone(),
+            rate_limiter.clone(),
+        ));
+
+        let (shutdown, shutdown_rx) = watch::channel(false);
+
+        let state = AppState {
+            db,
+            config: shared_config,
+            config_reloader,
+            rate_limiter,
+            click_events,
+            clicks,
+            url_cache,
+            ip_anonymizer: Arc::new(IpAnonymizer::new(config.analytics.privacy_mode)),
+            scheduler,
+            metrics,
+            shutdown: shutdown_rx,
+        };
+
+        Ok(Self {
+            state,
+            click_writer,
+            shutdown,
+        })
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    /// See [`build_router`].
+    pub fn router(&self) -> Router {
+        build_router(self.state.clone())
+    }
+
+    /// Ends the live click streams, which would otherwise hold up a graceful
+    /// server shutdown forever.
+    pub fn close_streams(&self) {
+        let _ = self.shutdown.send(true);
+    }
+
+    /// Closes the streams and writes the queued clicks, giving up on those
+    /// still queued at `deadline`.
+    pub async fn shutdown(self, deadline: Instant) {
+        self.close_streams();
+
+        let click_queue = self.state.clicks.clone();
+        if tokio::time::timeout_at(deadline, self.click_writer.shutdown())
+            .await
+            .is_err()
+        {
+            tracing::error!(
+                "Click queue not flushed in time, {} clicks lost",
+                click_queue.stats().queue_depth
+            );
+        }
+    }
+}
+
+/// Connects, migrates and serves until Ctrl+C or SIGTERM, then drains
+/// in-flight requests and queued clicks.
+pub async fn serve(config: Arc<Config>, config_args: ConfigArgs) -> anyhow::Result<()> {
+    let db = db::connect(&config.database).await?;
+    run_migrations(db.as_ref(), &config).await?;
+
+    let shortener = Shortener::start(config.clone(), config_args, db)?;
+    shortener.state.config_reloader.clone().spawn_on_sighup()?;
+
+    let app = shortener.router();
+
+    let address = format!("{}:{}", config.server.host, config.server.port);
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+
+    tracing::info!("Server running on http://{}", address);
+
+    let mut stopping = shortener.shutdown.subscribe();
+    let mut server = tokio::spawn(
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(async move {
+            let _ = stopping.wait_for(|stopping| *stopping).await;
+        })
+        .into_future(),
+    );
+
+    shutdown_signal().await;
+    shortener.close_streams();
+
+    // in-flight requests and the click flush share one drain budget
+    let drain = Duration::from_secs(config.server.shutdown_drain_seconds);
+    let deadline = Instant::now() + drain;
+
+    match tokio::time::timeout_at(deadline, &mut server).await {
+        Ok(result) => result??,
+        Err(_) => {
+            tracing::warn!(
+                "Connections still open after {}s, closing them",
+                drain.as_secs()
+            );
+            server.abort();
+        }
+    }
+
+    tracing::info!("Server stopped, flushing queued clicks");
+    shortener.shutdown(deadline).await;
+
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("Shutdown signal received");
+}
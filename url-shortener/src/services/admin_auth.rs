@@ -0,0 +1,49 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::error::AppError;
+
+/// Lets a request through only with `Authorization: Bearer <token>`
+/// matching the configured admin token.
+pub async fn require_admin_token(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if has_admin_token(request.headers(), &token) {
+        return next.run(request).await;
+    }
+
+    tracing::warn!(
+        "[ADMIN] rejected {} {} without a valid token",
+        request.method(),
+        request.uri().path()
+    );
+    AppError::Unauthorized.into_response()
+}
+
+/// Whether an admin token is configured and the request carries it.
+pub fn is_admin(headers: &HeaderMap, admin_token: Option<&str>) -> bool {
+    admin_token.is_some_and(|token| has_admin_token(headers, token))
+}
+
+/// Whether the request carries `Authorization: Bearer <token>`.
+pub fn has_admin_token(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()))
+}
+
+// compares every byte so the time taken doesn't reveal how much matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,66 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::db::Storage;
+use crate::error::{AppError, AppResult};
+
+#[derive(Debug, Serialize)]
+pub struct BackupReport {
+    pub path: String,
+    pub size_bytes: u64,
+    pub created_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+/// `url_shortener-<timestamp>.db` in `dir`, so backups sort by age.
+pub fn default_path(dir: &Path, at: DateTime<Utc>) -> PathBuf {
+    dir.join(format!("url_shortener-{}.db", at.format("%Y%m%dT%H%M%SZ")))
+}
+
+/// Writes a snapshot of the database to `destination`, creating its
+/// directory. Existing files are never overwritten.
+pub async fn backup(db: &dyn Storage, destination: &Path) -> AppResult<BackupReport> {
+    if destination.exists() {
+        return Err(AppError::Validation(format!(
+            "{} already exists",
+            destination.display()
+        )));
+    }
+    if let Some(dir) = destination.parent()
+        && !dir.as_os_str().is_empty()
+    {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let created_at = Utc::now();
+    let started = Instant::now();
+    db.backup(destination).await?;
+
+    let size_bytes = tokio::fs::metadata(destination)
+        .await
+        .with_context(|| format!("Failed to read {}", destination.display()))?
+        .len();
+
+    let report = BackupReport {
+        path: destination.display().to_string(),
+        size_bytes,
+        created_at,
+        duration_ms: started.elapsed().as_millis() as u64,
+    };
+    tracing::info!(
+        "[BACKUP] wrote {} ({} bytes) in {}ms",
+        report.path,
+        report.size_bytes,
+        report.duration_ms
+    );
+
+    Ok(report)
+}
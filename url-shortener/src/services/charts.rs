@@ -0,0 +1,179 @@
+use crate::models::DateCount;
+use chrono::NaiveDate;
+use std::fmt::Write;
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 240.0;
+const PADDING: f64 = 32.0;
+const BAR_HEIGHT: f64 = 22.0;
+const BAR_GAP: f64 = 8.0;
+const LABEL_WIDTH: f64 = 220.0;
+const ACCENT: &str = "#667eea";
+
+/// Line chart of clicks per day, oldest day first. Days without clicks
+/// between the first and last one are drawn as zero.
+pub fn clicks_over_time(clicks_by_date: &[DateCount]) -> String {
+    let Some(first) = clicks_by_date.iter().map(|c| c.date).min() else {
+        return empty_chart();
+    };
+    let last = clicks_by_date.iter().map(|c| c.date).max().unwrap_or(first);
+
+    let days: Vec<(NaiveDate, i64)> = first
+        .iter_days()
+        .take_while(|day| *day <= last)
+        .map(|day| {
+            let count = clicks_by_date
+                .iter()
+                .find(|c| c.date == day)
+                .map_or(0, |c| c.count);
+            (day, count)
+        })
+        .collect();
+
+    let max = days
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let plot_width = WIDTH - 2.0 * PADDING;
+    let plot_height = HEIGHT - 2.0 * PADDING;
+    let step = if days.len() > 1 {
+        plot_width / (days.len() - 1) as f64
+    } else {
+        0.0
+    };
+
+    let points: Vec<(f64, f64)> = days
+        .iter()
+        .enumerate()
+        .map(|(i, (_, count))| {
+            let x = PADDING + step * i as f64;
+            let y = HEIGHT - PADDING - plot_height * (*count as f64 / max);
+            (x, y)
+        })
+        .collect();
+
+    let mut svg = open_svg(WIDTH, HEIGHT, "Clicks over time");
+
+    let _ = write!(
+        svg,
+        r##"<line x1="{PADDING}" y1="{y}" x2="{x}" y2="{y}" stroke="#e0e0e0"/>"##,
+        x = WIDTH - PADDING,
+        y = HEIGHT - PADDING,
+    );
+    let _ = write!(
+        svg,
+        r##"<text x="{PADDING}" y="{y}" font-size="12" fill="#888">{max}</text>"##,
+        y = PADDING - 8.0,
+    );
+
+    let polyline: Vec<String> = points
+        .iter()
+        .map(|(x, y)| format!("{x:.1},{y:.1}"))
+        .collect();
+    let _ = write!(
+        svg,
+        r#"<polyline points="{}" fill="none" stroke="{ACCENT}" stroke-width="2"/>"#,
+        polyline.join(" ")
+    );
+
+    for ((x, y), (day, count)) in points.iter().zip(&days) {
+        let _ = write!(
+            svg,
+            r#"<circle cx="{x:.1}" cy="{y:.1}" r="3" fill="{ACCENT}"><title>{day}: {count}</title></circle>"#
+        );
+    }
+
+    let _ = write!(
+        svg,
+        r##"<text x="{PADDING}" y="{y}" font-size="12" fill="#888">{first}</text>"##,
+        y = HEIGHT - 10.0,
+    );
+    if last != first {
+        let _ = write!(
+            svg,
+            r##"<text x="{x}" y="{y}" font-size="12" fill="#888" text-anchor="end">{last}</text>"##,
+            x = WIDTH - PADDING,
+            y = HEIGHT - 10.0,
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// Horizontal bar chart of labelled counts, in the given order.
+pub fn breakdown<'a>(title: &str, rows: impl IntoIterator<Item = (&'a str, i64)>) -> String {
+    let rows: Vec<(&str, i64)> = rows.into_iter().collect();
+    if rows.is_empty() {
+        return empty_chart();
+    }
+
+    let max = rows
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let height = rows.len() as f64 * (BAR_HEIGHT + BAR_GAP);
+    let bar_space = WIDTH - LABEL_WIDTH - 60.0;
+
+    let mut svg = open_svg(WIDTH, height, title);
+
+    for (i, (label, count)) in rows.iter().enumerate() {
+        let y = i as f64 * (BAR_HEIGHT + BAR_GAP);
+        let text_y = y + BAR_HEIGHT * 0.7;
+        let width = (bar_space * (*count as f64 / max)).max(1.0);
+
+        let _ = write!(
+            svg,
+            r##"<text x="{x}" y="{text_y}" font-size="13" fill="#555" text-anchor="end">{}<title>{}</title></text>"##,
+            escape(&truncate(label, 32)),
+            escape(label),
+            x = LABEL_WIDTH - 8.0,
+        );
+        let _ = write!(
+            svg,
+            r#"<rect x="{LABEL_WIDTH}" y="{y}" width="{width:.1}" height="{BAR_HEIGHT}" rx="3" fill="{ACCENT}"/>"#
+        );
+        let _ = write!(
+            svg,
+            r##"<text x="{x:.1}" y="{text_y}" font-size="13" fill="#333">{count}</text>"##,
+            x = LABEL_WIDTH + width + 6.0,
+        );
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+fn open_svg(width: f64, height: f64, title: &str) -> String {
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {width} {height}" role="img" aria-label="{title}" class="chart">"#,
+        title = escape(title),
+    )
+}
+
+fn empty_chart() -> String {
+    r#"<p class="empty-state">No clicks in this period.</p>"#.to_string()
+}
+
+fn truncate(label: &str, max_chars: usize) -> String {
+    if label.chars().count() <= max_chars {
+        return label.to_string();
+    }
+
+    let mut truncated: String = label.chars().take(max_chars - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+// labels are user controlled (referers), the svg is rendered unescaped
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,232 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::db::Storage;
+use crate::error::AppError;
+use crate::models::{Click, ClickEvent, NewClick};
+use crate::services::webhooks::ClickOutbox;
+
+// how long a redirect waits for room in a full queue before the click is dropped
+const ENQUEUE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct PipelineCounters {
+    enqueued: AtomicU64,
+    written: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClickPipelineStats {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub enqueued: u64,
+    pub written: u64,
+    pub dropped: u64,
+    pub failed: u64,
+}
+
+/// Producer side of the click queue, cheap to clone into handlers.
+#[derive(Clone)]
+pub struct ClickPipeline {
+    sender: mpsc::Sender<NewClick>,
+    counters: Arc<PipelineCounters>,
+}
+
+/// Owned by `main` to flush the queue on shutdown.
+pub struct ClickWriterHandle {
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ClickPipeline {
+    pub fn spawn(
+        db: Arc<dyn Storage>,
+        click_events: broadcast::Sender<ClickEvent>,
+        capacity: usize,
+        batch_size: usize,
+    ) -> (Self, ClickWriterHandle) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let counters = Arc::new(PipelineCounters::default());
+
+        let writer = ClickWriter {
+            db,
+            click_events,
+            counters: counters.clone(),
+            batch_size,
+        };
+        let task = tokio::spawn(writer.run(receiver, shutdown_rx));
+
+        (
+            Self { sender, counters },
+            ClickWriterHandle { shutdown, task },
+        )
+    }
+
+    /// Queues a click, waiting briefly when the queue is full. Returns false
+    /// if the click had to be dropped.
+    pub async fn submit(&self, click: NewClick) -> bool {
+        match self.sender.send_timeout(click, ENQUEUE_TIMEOUT).await {
+            Ok(()) => {
+                self.counters.enqueued.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ClickPipelineStats {
+        let queue_capacity = self.sender.max_capacity();
+
+        ClickPipelineStats {
+            queue_depth: queue_capacity - self.sender.capacity(),
+            queue_capacity,
+            enqueued: self.counters.enqueued.load(Ordering::Relaxed),
+            written: self.counters.written.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+            failed: self.counters.failed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ClickWriterHandle {
+    /// Stops accepting clicks and waits until everything queued is written.
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        if let Err(e) = self.task.await {
+            tracing::error!("[CLICK_PIPELINE] writer task failed: {}", e);
+        }
+    }
+}
+
+struct ClickWriter {
+    db: Arc<dyn Storage>,
+    click_events: broadcast::Sender<ClickEvent>,
+    counters: Arc<PipelineCounters>,
+    batch_size: usize,
+}
+
+impl ClickWriter {
+    async fn run(
+        self,
+        mut receiver: mpsc::Receiver<NewClick>,
+        mut shutdown: oneshot::Receiver<()>,
+    ) {
+        let mut batch = Vec::with_capacity(self.batch_size);
+
+        loop {
+            tokio::select! {
+                received = receiver.recv_many(&mut batch, self.batch_size) => {
+                    if received == 0 {
+                        break;
+                    }
+                    self.write(&mut batch).await;
+                }
+                _ = &mut shutdown => {
+                    // keep what is already queued, refuse anything new
+                    receiver.close();
+                    while receiver.recv_many(&mut batch, self.batch_size).await > 0 {
+                        self.write(&mut batch).await;
+                    }
+                    break;
+                }
+            }
+        }
+
+        tracing::info!("[CLICK_PIPELINE] writer stopped, queue flushed");
+    }
+
+    async fn write(&self, batch: &mut Vec<NewClick>) {
+        let count = batch.len();
+        let outbox = self.outbox(batch).await;
+
+        match self.db.record_clicks(batch, &outbox).await {
+            Ok(clicks) => self.publish(batch, clicks),
+            // one bad row, e.g. a click on a link deleted while it was still
+            // cached, rolls back the batch, write the others one by one
+            Err(e) if count > 1 => {
+                tracing::warn!(
+                    "[CLICK_PIPELINE] failed to write {} clicks, retrying one by one: {}",
+                    count,
+                    e
+                );
+                for click in batch.drain(..) {
+                    let mut click = vec![click];
+                    let outbox = self.outbox(&click).await;
+                    match self.db.record_clicks(&click, &outbox).await {
+                        Ok(recorded) => self.publish(&mut click, recorded),
+                        Err(e) => self.failed(&click, e),
+                    }
+                }
+            }
+            Err(e) => {
+                self.failed(batch, e);
+                batch.clear();
+            }
+        }
+    }
+
+    /// Webhook deliveries for the batch, none when the subscriptions can't be
+    /// read so the clicks are still written.
+    async fn outbox(&self, batch: &[NewClick]) -> ClickOutbox {
+        ClickOutbox::for_clicks(self.db.as_ref(), batch)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("[CLICK_PIPELINE] failed to look up webhooks: {}", e);
+                ClickOutbox::default()
+            })
+    }
+
+    /// Announces written clicks, leaving `new_clicks` empty.
+    fn publish(&self, new_clicks: &mut Vec<NewClick>, clicks: Vec<Click>) {
+        self.counters
+            .written
+            .fetch_add(new_clicks.len() as u64, Ordering::Relaxed);
+
+        for (new_click, click) in new_clicks.drain(..).zip(clicks) {
+            // no receivers just means nobody is watching
+            let _ = self.click_events.send(ClickEvent {
+                url_id: click.url_id,
+                short_code: new_click.short_code,
+                clicked_at: click.clicked_at,
+                referer: click.referer,
+                country: click.country,
+            });
+        }
+    }
+
+    fn failed(&self, clicks: &[NewClick], error: AppError) {
+        self.counters
+            .failed
+            .fetch_add(clicks.len() as u64, Ordering::Relaxed);
+
+        match clicks {
+            [click] => tracing::error!(
+                "[CLICK_PIPELINE] dropped a click on {}: {}",
+                click.short_code,
+                error
+            ),
+            _ => tracing::error!(
+                "[CLICK_PIPELINE] failed to write {} clicks: {}",
+                clicks.len(),
+                error
+            ),
+        }
+    }
+}
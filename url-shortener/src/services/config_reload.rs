@@ -0,0 +1,183 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::config::{Config, ConfigArgs};
+use crate::services::rate_limiter::RateLimiter;
+
+#[derive(Debug, Serialize)]
+pub struct ConfigChange {
+    pub key: String,
+    pub old: String,
+    pub new: String,
+    /// False for settings only read at startup, they need a restart.
+    pub applied: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReloadReport {
+    pub file: Option<String>,
+    pub changes: Vec<ConfigChange>,
+}
+
+/// Re-reads the configuration the server was started with and applies the
+/// settings that can change at runtime: the base URL, trusted proxies, short
+/// code length and rate limit quotas.
+pub struct ConfigReloader {
+    args: ConfigArgs,
+    config: Arc<ArcSwap<Config>>,
+    rate_limiter: Arc<RateLimiter>,
+    // SIGHUP and the admin endpoint may race, reloads run one at a time
+    reloading: Mutex<()>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        args: ConfigArgs,
+        config: Arc<ArcSwap<Config>>,
+        rate_limiter: Arc<RateLimiter>,
+    ) -> Self {
+        Self {
+            args,
+            config,
+            rate_limiter,
+            reloading: Mutex::new(()),
+        }
+    }
+
+    /// Loads and validates the config file again, environment variables and
+    /// flags still apply on top. The running settings are untouched when the
+    /// new configuration is invalid.
+    pub async fn reload(&self) -> anyhow::Result<ReloadReport> {
+        let _reloading = self.reloading.lock().await;
+
+        let (loaded, path) = Config::load(&self.args)?;
+        let current = self.config.load_full();
+
+        let mut next = (*current).clone();
+        next.server.base_url = loaded.server.base_url.clone();
+        next.server.trusted_proxies = loaded.server.trusted_proxies.clone();
+        next.policy.short_code_length = loaded.policy.short_code_length;
+        next.policy.bulk_max_items = loaded.policy.bulk_max_items;
+        next.rate_limits.redirect_requests_per_minute =
+            loaded.rate_limits.redirect_requests_per_minute;
+        next.rate_limits.create_requests_per_minute = loaded.rate_limits.create_requests_per_minute;
+        next.rate_limits.bulk_items_per_minute = loaded.rate_limits.bulk_items_per_minute;
+        next.rate_limits.stats_requests_per_minute = loaded.rate_limits.stats_requests_per_minute;
+
+        let applied = diff(&current, &next)?;
+        let changes: Vec<ConfigChange> = diff(&current, &loaded)?
+            .into_iter()
+            .map(|(key, old, new)| ConfigChange {
+                applied: applied
+                    .iter()
+                    .any(|(applied_key, _, _)| *applied_key == key),
+                key,
+                old,
+                new,
+            })
+            .collect();
+
+        for (scope, previous, current) in self.rate_limiter.set_quotas(
+            next.rate_limits.redirect_requests_per_minute,
+            next.rate_limits.create_requests_per_minute,
+            next.rate_limits.bulk_items_per_minute,
+            next.rate_limits.stats_requests_per_minute,
+        ) {
+            tracing::info!(
+                "[CONFIG] {} quota {} -> {} per minute, its per-IP state was reset",
+                scope.as_str(),
+                previous,
+                current
+            );
+        }
+
+        self.config.store(Arc::new(next));
+
+        if changes.is_empty() {
+            tracing::info!("[CONFIG] reloaded, nothing changed");
+        }
+        for change in &changes {
+            if change.applied {
+                tracing::info!("[CONFIG] {}: {} -> {}", change.key, change.old, change.new);
+            } else {
+                tracing::warn!(
+                    "[CONFIG] {}: {} -> {} is only read at startup, restart to apply it",
+                    change.key,
+                    change.old,
+                    change.new
+                );
+            }
+        }
+
+        Ok(ReloadReport {
+            file: path.map(|path| path.display().to_string()),
+            changes,
+        })
+    }
+
+    /// Reloads whenever the process receives SIGHUP.
+    #[cfg(unix)]
+    pub fn spawn_on_sighup(self: Arc<Self>) -> anyhow::Result<()> {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                tracing::info!("[CONFIG] SIGHUP received, reloading");
+                if let Err(e) = self.reload().await {
+                    tracing::error!("[CONFIG] reload failed, keeping current settings: {:#}", e);
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn spawn_on_sighup(self: Arc<Self>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// `(section.key, old, new)` for every setting that differs. Unset optional
+/// settings are absent from the TOML form and shown as `unset`.
+fn diff(old: &Config, new: &Config) -> anyhow::Result<Vec<(String, String, String)>> {
+    let old = flatten(old)?;
+    let new = flatten(new)?;
+
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    let unset = "unset".to_string();
+
+    Ok(keys
+        .into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|key| {
+            (
+                key.clone(),
+                old.get(key).unwrap_or(&unset).clone(),
+                new.get(key).unwrap_or(&unset).clone(),
+            )
+        })
+        .collect())
+}
+
+fn flatten(config: &Config) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut settings = BTreeMap::new();
+
+    for (section, values) in toml::Table::try_from(config.redacted())? {
+        let Some(values) = values.as_table() else {
+            continue;
+        };
+        for (key, value) in values {
+            settings.insert(format!("{}.{}", section, key), value.to_string());
+        }
+    }
+
+    Ok(settings)
+}
@@ -0,0 +1,148 @@
+use crate::error::{AppError, AppResult};
+use axum::http::{HeaderMap, header};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl ExportFormat {
+    /// Picks the format from the `Accept` header, falling back to NDJSON.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        if accept.contains("text/csv") {
+            ExportFormat::Csv
+        } else {
+            ExportFormat::Ndjson
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// Encodes records one at a time so they can be written to a streaming body.
+pub struct ExportEncoder {
+    format: ExportFormat,
+    header_written: bool,
+}
+
+impl ExportEncoder {
+    pub fn new(format: ExportFormat) -> Self {
+        Self {
+            format,
+            header_written: false,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&mut self, record: &T) -> AppResult<Vec<u8>> {
+        match self.format {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(!self.header_written)
+                    .from_writer(Vec::new());
+
+                writer.serialize(record).map_err(|e| {
+                    AppError::Internal(anyhow::anyhow!("Failed to encode CSV row: {}", e))
+                })?;
+                self.header_written = true;
+
+                writer.into_inner().map_err(|e| {
+                    AppError::Internal(anyhow::anyhow!("Failed to flush CSV row: {}", e))
+                })
+            }
+            ExportFormat::Ndjson => {
+                let mut line = serde_json::to_vec(record).map_err(|e| {
+                    AppError::Internal(anyhow::anyhow!("Failed to encode JSON line: {}", e))
+                })?;
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Row<'a> {
+        id: u32,
+        user_agent: Option<&'a str>,
+        referer: Option<&'a str>,
+    }
+
+    fn encode_all(format: ExportFormat, rows: &[Row]) -> String {
+        let mut encoder = ExportEncoder::new(format);
+        let bytes: Vec<u8> = rows
+            .iter()
+            .flat_map(|row| encoder.encode(row).unwrap())
+            .collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn csv_quotes_commas_and_quotes() {
+        let rows = [
+            Row {
+                id: 1,
+                user_agent: Some("Mozilla/5.0 (X11; Linux x86_64) Gecko, like \"Chrome\""),
+                referer: Some("https://example.com/?q=a,b"),
+            },
+            Row {
+                id: 2,
+                user_agent: None,
+                referer: Some("line\nbreak"),
+            },
+        ];
+
+        let csv = encode_all(ExportFormat::Csv, &rows);
+        assert_eq!(
+            csv,
+            "id,user_agent,referer\n\
+             1,\"Mozilla/5.0 (X11; Linux x86_64) Gecko, like \"\"Chrome\"\"\",\"https://example.com/?q=a,b\"\n\
+             2,,\"line\nbreak\"\n"
+        );
+
+        // and reads back to the same values
+        let mut reader = csv::Reader::from_reader(csv.as_bytes());
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(&records[0][1], rows[0].user_agent.unwrap());
+        assert_eq!(&records[0][2], rows[0].referer.unwrap());
+        assert_eq!(&records[1][2], "line\nbreak");
+    }
+
+    #[test]
+    fn ndjson_writes_one_line_per_record() {
+        let rows = [
+            Row {
+                id: 1,
+                user_agent: Some("a \"quoted\"\nagent"),
+                referer: None,
+            },
+            Row {
+                id: 2,
+                user_agent: None,
+                referer: None,
+            },
+        ];
+
+        let ndjson = encode_all(ExportFormat::Ndjson, &rows);
+        let lines: Vec<&str> = ndjson.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["user_agent"], "a \"quoted\"\nagent");
+        assert!(first["referer"].is_null());
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+const REQUEST_DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+// histograms are drained into their buckets on upkeep, not on every record
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+// the recorder is process wide, every shortener started in the process shares it
+static RECORDER: Mutex<Option<PrometheusHandle>> = Mutex::new(None);
+
+/// Installs the global Prometheus recorder on first use and returns the
+/// handle `/metrics` renders from.
+pub fn install_recorder() -> anyhow::Result<PrometheusHandle> {
+    let mut recorder = RECORDER.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(handle) = recorder.as_ref() {
+        return Ok(handle.clone());
+    }
+
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Full("http_request_duration_seconds".to_string()),
+            &REQUEST_DURATION_BUCKETS,
+        )?
+        .install_recorder()?;
+
+    describe();
+
+    let upkeep = handle.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            upkeep.run_upkeep();
+        }
+    });
+
+    *recorder = Some(handle.clone());
+
+    Ok(handle)
+}
+
+/// Counts and times every request, labelled by method, matched route and
+/// status. Runs inside the router so the route template is known.
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    // label by template, not raw path, so short codes don't explode cardinality
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let response = next.run(request).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    metrics::counter!("http_requests_total", &labels).increment(1);
+    metrics::histogram!("http_request_duration_seconds", &labels)
+        .record(started.elapsed().as_secs_f64());
+
+    response
+}
+
+fn describe() {
+    metrics::describe_counter!(
+        "http_requests_total",
+        "HTTP requests by method, route and status"
+    );
+    metrics::describe_histogram!(
+        "http_request_duration_seconds",
+        metrics::Unit::Seconds,
+        "HTTP request latency by method, route and status"
+    );
+    metrics::describe_counter!("app_errors_total", "Error responses by AppError variant");
+    metrics::describe_counter!(
+        "rate_limit_rejections_total",
+        "Requests rejected by the rate limiter, by scope"
+    );
+    metrics::describe_gauge!(
+        "rate_limiter_tracked_ips",
+        "IPs with rate limiter state across all scopes"
+    );
+    metrics::describe_gauge!("click_pipeline_queue_depth", "Clicks waiting to be written");
+    metrics::describe_gauge!(
+        "click_pipeline_queue_capacity",
+        "Clicks the queue holds before redirects drop them"
+    );
+    metrics::describe_counter!(
+        "click_pipeline_clicks_total",
+        "Clicks by pipeline outcome: enqueued, written, dropped or failed"
+    );
+    metrics::describe_gauge!(
+        "db_pool_connections",
+        "Database pool connections by state: idle or in_use"
+    );
+    metrics::describe_gauge!(
+        "db_pool_max_connections",
+        "Maximum size of the database pool"
+    );
+    metrics::describe_counter!(
+        "url_cache_lookups_total",
+        "Short code cache lookups by result: hit or miss"
+    );
+    metrics::describe_gauge!("url_cache_entries", "Short codes in the lookup cache");
+}
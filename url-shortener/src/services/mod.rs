@@ -1,3 +1,19 @@
+pub(crate) mod admin_auth;
+pub mod backup;
+pub(crate) mod charts;
+pub(crate) mod click_pipeline;
+pub(crate) mod config_reload;
+pub(crate) mod export;
+pub(crate) mod metrics;
+pub(crate) mod privacy;
 pub mod qr_code;
-pub mod rate_limiter;
+pub(crate) mod rate_limiter;
+pub mod referer;
+pub(crate) mod retention;
+pub(crate) mod scheduler;
 pub mod shorten;
+pub mod transfer;
+pub(crate) mod url_cache;
+pub(crate) mod user_agent;
+pub(crate) mod webhook_targets;
+pub mod webhooks;
@@ -0,0 +1,166 @@
+use std::net::IpAddr;
+
+use chrono::{NaiveDate, Utc};
+use ipnet::{Ipv4Net, Ipv6Net};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::config::PrivacyMode;
+use crate::db::ClickStore;
+use crate::error::AppResult;
+
+const IPV4_PREFIX: u8 = 24;
+const IPV6_PREFIX: u8 = 48;
+
+/// Turns visitor IPs into what gets stored in `clicks.ip_address`.
+pub struct IpAnonymizer {
+    mode: PrivacyMode,
+    daily_salt: Mutex<Option<(NaiveDate, String)>>,
+}
+
+impl IpAnonymizer {
+    pub fn new(mode: PrivacyMode) -> Self {
+        Self {
+            mode,
+            daily_salt: Mutex::new(None),
+        }
+    }
+
+    pub fn mode(&self) -> PrivacyMode {
+        self.mode
+    }
+
+    pub async fn anonymize(&self, db: &dyn ClickStore, ip: IpAddr) -> AppResult<String> {
+        match self.mode {
+            PrivacyMode::Full => Ok(ip.to_string()),
+            PrivacyMode::Truncate => Ok(truncate_ip(ip).to_string()),
+            PrivacyMode::Hash => {
+                let salt = self.salt_for_today(db).await?;
+                Ok(hash_ip(&salt, ip))
+            }
+        }
+    }
+
+    async fn salt_for_today(&self, db: &dyn ClickStore) -> AppResult<String> {
+        let today = Utc::now().date_naive();
+        let mut daily_salt = self.daily_salt.lock().await;
+
+        if let Some((day, salt)) = daily_salt.as_ref()
+            && *day == today
+        {
+            return Ok(salt.clone());
+        }
+
+        // the stored salt wins so every process hashes the same way today
+        let mut candidate = [0u8; 32];
+        rand::rng().fill_bytes(&mut candidate);
+        let salt = db
+            .get_or_create_ip_salt(today, &hex::encode(candidate))
+            .await?;
+        db.delete_ip_salts_before(today).await?;
+
+        *daily_salt = Some((today, salt.clone()));
+        Ok(salt)
+    }
+}
+
+pub fn truncate_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => IpAddr::V4(
+            Ipv4Net::new(v4, IPV4_PREFIX)
+                .expect("valid IPv4 prefix")
+                .network(),
+        ),
+        IpAddr::V6(v6) => IpAddr::V6(
+            Ipv6Net::new(v6, IPV6_PREFIX)
+                .expect("valid IPv6 prefix")
+                .network(),
+        ),
+    }
+}
+
+fn hash_ip(salt: &str, ip: IpAddr) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(ip.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::MemoryStore;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_keeps_the_24_bit_network() {
+        assert_eq!(truncate_ip(ip("192.0.2.187")), ip("192.0.2.0"));
+        assert_eq!(truncate_ip(ip("192.0.2.0")), ip("192.0.2.0"));
+        assert_ne!(truncate_ip(ip("192.0.3.1")), truncate_ip(ip("192.0.2.1")));
+    }
+
+    #[test]
+    fn ipv6_keeps_the_48_bit_network() {
+        assert_eq!(
+            truncate_ip(ip("2001:db8:85a3:8d3:1319:8a2e:370:7348")),
+            ip("2001:db8:85a3::")
+        );
+        assert_ne!(
+            truncate_ip(ip("2001:db8:85a4::1")),
+            truncate_ip(ip("2001:db8:85a3::1"))
+        );
+    }
+
+    #[test]
+    fn hashes_depend_on_the_salt_and_the_ip() {
+        let hash = hash_ip("salt-a", ip("192.0.2.1"));
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_ip("salt-a", ip("192.0.2.1")));
+        assert_ne!(hash, hash_ip("salt-b", ip("192.0.2.1")));
+        assert_ne!(hash, hash_ip("salt-a", ip("192.0.2.2")));
+        assert!(!hash.contains("192.0.2.1"));
+    }
+
+    #[tokio::test]
+    async fn hashes_are_stable_within_a_day() {
+        let db = MemoryStore::new();
+        let anonymizer = IpAnonymizer::new(PrivacyMode::Hash);
+
+        let first = anonymizer.anonymize(&db, ip("192.0.2.1")).await.unwrap();
+        assert_eq!(
+            anonymizer.anonymize(&db, ip("192.0.2.1")).await.unwrap(),
+            first
+        );
+        assert_ne!(
+            anonymizer.anonymize(&db, ip("192.0.2.2")).await.unwrap(),
+            first
+        );
+
+        // another process shares today's salt through the database
+        let other = IpAnonymizer::new(PrivacyMode::Hash);
+        assert_eq!(other.anonymize(&db, ip("192.0.2.1")).await.unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn each_mode_stores_its_own_form() {
+        let db = MemoryStore::new();
+        let visitor = ip("192.0.2.187");
+
+        let stored = |mode| {
+            let db = &db;
+            async move {
+                IpAnonymizer::new(mode)
+                    .anonymize(db, visitor)
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(stored(PrivacyMode::Full).await, "192.0.2.187");
+        assert_eq!(stored(PrivacyMode::Truncate).await, "192.0.2.0");
+        assert_ne!(stored(PrivacyMode::Hash).await, "192.0.2.187");
+    }
+}
@@ -1,40 +1,186 @@
+use arc_swap::ArcSwap;
 use governor::{
     Quota, RateLimiter as GovRateLimiter,
-    clock::DefaultClock,
-    state::{InMemoryState, NotKeyed},
+    clock::{Clock, DefaultClock},
+    state::keyed::DefaultKeyedStateStore,
 };
-use std::{collections::HashMap, net::IpAddr, num::NonZeroU32, sync::Mutex};
+use std::{net::IpAddr, num::NonZeroU32, sync::Arc, time::Duration};
 
+use crate::error::{AppError, AppResult};
+
+const MIN_CLEANUP_INTERVAL: Duration = Duration::from_secs(1);
+
+type KeyedLimiter = GovRateLimiter<IpAddr, DefaultKeyedStateStore<IpAddr>, DefaultClock>;
+
+#[derive(Clone, Copy, Debug)]
+pub enum RateLimitScope {
+    Redirect,
+    Create,
+    /// Links created through bulk requests, charged per item.
+    Bulk,
+    Stats,
+}
+
+impl RateLimitScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RateLimitScope::Redirect => "redirect",
+            RateLimitScope::Create => "create",
+            RateLimitScope::Bulk => "bulk",
+            RateLimitScope::Stats => "stats",
+        }
+    }
+}
+
+struct ScopedLimiter {
+    requests_per_minute: u32,
+    limiter: KeyedLimiter,
+}
+
+impl ScopedLimiter {
+    /// A quota of 0 would reject every request forever, it is raised to 1.
+    fn new(requests_per_minute: u32) -> Self {
+        let requests_per_minute = requests_per_minute.max(1);
+        let quota =
+            Quota::per_minute(NonZeroU32::new(requests_per_minute).unwrap_or(NonZeroU32::MIN));
+        Self {
+            requests_per_minute,
+            limiter: GovRateLimiter::keyed(quota),
+        }
+    }
+}
+
+/// Per-IP limiter with a separate quota for each `RateLimitScope`. State is
+/// kept in governor's sharded keyed store and pruned by `spawn_cleanup`.
+/// Each scope sits behind an `ArcSwap` so quotas can change at runtime.
 pub struct RateLimiter {
-    quota: Quota,
-    limiters: Mutex<HashMap<IpAddr, GovRateLimiter<NotKeyed, InMemoryState, DefaultClock>>>,
+    redirect: ArcSwap<ScopedLimiter>,
+    create: ArcSwap<ScopedLimiter>,
+    bulk: ArcSwap<ScopedLimiter>,
+    stats: ArcSwap<ScopedLimiter>,
 }
 
 impl RateLimiter {
-    pub fn new(requests_per_minute: u32) -> Self {
-        let quota = Quota::per_minute(NonZeroU32::new(requests_per_minute).unwrap());
+    pub fn new(
+        redirect_per_minute: u32,
+        create_per_minute: u32,
+        bulk_items_per_minute: u32,
+        stats_per_minute: u32,
+    ) -> Self {
         Self {
-            limiters: Mutex::new(HashMap::new()),
-            quota,
+            redirect: ArcSwap::from_pointee(ScopedLimiter::new(redirect_per_minute)),
+            create: ArcSwap::from_pointee(ScopedLimiter::new(create_per_minute)),
+            bulk: ArcSwap::from_pointee(ScopedLimiter::new(bulk_items_per_minute)),
+            stats: ArcSwap::from_pointee(ScopedLimiter::new(stats_per_minute)),
         }
     }
 
-    pub fn check(&self, ip: IpAddr) -> bool {
-        let mut limiters = self.limiters.lock().unwrap();
-        let limiter = limiters
-            .entry(ip)
-            .or_insert_with(|| GovRateLimiter::direct(self.quota));
-        limiter.check().is_ok()
+    /// Applies new quotas. Governor ties per-IP state to its quota, so only
+    /// scopes whose quota changed get a fresh limiter, the others keep their
+    /// state. Returns the scopes that changed with their old and new quota.
+    pub fn set_quotas(
+        &self,
+        redirect_per_minute: u32,
+        create_per_minute: u32,
+        bulk_items_per_minute: u32,
+        stats_per_minute: u32,
+    ) -> Vec<(RateLimitScope, u32, u32)> {
+        let mut changed = Vec::new();
+
+        for (scope, requests_per_minute) in [
+            (RateLimitScope::Redirect, redirect_per_minute),
+            (RateLimitScope::Create, create_per_minute),
+            (RateLimitScope::Bulk, bulk_items_per_minute),
+            (RateLimitScope::Stats, stats_per_minute),
+        ] {
+            let slot = self.scoped(scope);
+            let previous = slot.load().requests_per_minute;
+
+            if previous != requests_per_minute {
+                slot.store(Arc::new(ScopedLimiter::new(requests_per_minute)));
+                changed.push((scope, previous, requests_per_minute));
+            }
+        }
+
+        changed
+    }
+
+    pub fn check(&self, scope: RateLimitScope, ip: IpAddr) -> AppResult<()> {
+        self.check_n(scope, ip, 1)
+    }
+
+    /// Charges `n` requests at once, e.g. one per item of a batch. A batch
+    /// larger than the whole quota could never pass and is rejected as invalid.
+    pub fn check_n(&self, scope: RateLimitScope, ip: IpAddr, n: u32) -> AppResult<()> {
+        let Some(n) = NonZeroU32::new(n) else {
+            return Ok(());
+        };
+        let scoped = self.scoped(scope).load();
+
+        let Ok(allowed) = scoped.limiter.check_key_n(&ip, n) else {
+            return Err(AppError::Validation(format!(
+                "{} items exceed the {} limit of {} per minute",
+                n,
+                scope.as_str(),
+                scoped.requests_per_minute
+            )));
+        };
+
+        allowed.map_err(|not_until| {
+            metrics::counter!("rate_limit_rejections_total", "scope" => scope.as_str())
+                .increment(1);
+
+            let retry_after = not_until.wait_time_from(scoped.limiter.clock().now());
+            AppError::RateLimitExceeded {
+                limit: scoped.requests_per_minute,
+                retry_after,
+            }
+        })
     }
 
-    #[allow(dead_code)]
     pub fn tracked_ips(&self) -> usize {
-        self.limiters.lock().unwrap().len()
+        self.limiters()
+            .map(|scoped| scoped.load().limiter.len())
+            .sum()
     }
 
-    #[allow(dead_code)]
+    /// Forgets IPs whose quota has fully replenished, they are
+    /// indistinguishable from IPs that were never seen.
     pub fn cleanup_stale_limiters(&self) {
-        let mut limiters = self.limiters.lock().unwrap();
-        limiters.retain(|_, limiter| limiter.check().is_ok());
+        for scoped in self.limiters() {
+            let scoped = scoped.load();
+            scoped.limiter.retain_recent();
+            scoped.limiter.shrink_to_fit();
+        }
+    }
+
+    /// Prunes every `every`, at least once a second.
+    pub fn spawn_cleanup(self: Arc<Self>, every: Duration) {
+        // a zero period panics in tokio
+        let every = every.max(MIN_CLEANUP_INTERVAL);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            loop {
+                interval.tick().await;
+                self.cleanup_stale_limiters();
+                tracing::debug!(
+                    "[RATE_LIMITER] cleanup done, tracking {} IPs",
+                    self.tracked_ips()
+                );
+            }
+        });
+    }
+
+    fn scoped(&self, scope: RateLimitScope) -> &ArcSwap<ScopedLimiter> {
+        match scope {
+            RateLimitScope::Redirect => &self.redirect,
+            RateLimitScope::Create => &self.create,
+            RateLimitScope::Bulk => &self.bulk,
+            RateLimitScope::Stats => &self.stats,
+        }
+    }
+
+    fn limiters(&self) -> impl Iterator<Item = &ArcSwap<ScopedLimiter>> {
+        [&self.redirect, &self.create, &self.bulk, &self.stats].into_iter()
     }
 }
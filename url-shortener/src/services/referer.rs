@@ -0,0 +1,278 @@
+use crate::db::ClickStore;
+use crate::error::AppResult;
+
+const SOCIAL_DOMAINS: &[&str] = &[
+    "facebook.com",
+    "fb.me",
+    "t.co",
+    "twitter.com",
+    "x.com",
+    "linkedin.com",
+    "lnkd.in",
+    "instagram.com",
+    "reddit.com",
+    "youtube.com",
+    "youtu.be",
+    "tiktok.com",
+    "pinterest.com",
+    "threads.net",
+    "bsky.app",
+    "mastodon.social",
+    "news.ycombinator.com",
+];
+
+const SEARCH_DOMAINS: &[&str] = &[
+    "bing.com",
+    "duckduckgo.com",
+    "search.yahoo.com",
+    "baidu.com",
+    "ecosia.org",
+    "search.brave.com",
+    "startpage.com",
+];
+
+// subdomains of google.<tld> that are search, after www. was stripped
+const GOOGLE_SEARCH_SUBDOMAINS: &[&str] = &["images", "news", "scholar", "books"];
+
+// checked before search so mail.google.com is not counted as google search
+const EMAIL_DOMAINS: &[&str] = &[
+    "mail.google.com",
+    "outlook.live.com",
+    "outlook.office.com",
+    "outlook.office365.com",
+    "mail.yahoo.com",
+    "mail.proton.me",
+    "com.google.android.gm",
+];
+
+const BACKFILL_BATCH_SIZE: i64 = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Direct,
+    Internal,
+    Social,
+    Search,
+    Email,
+    Referral,
+}
+
+impl Channel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Channel::Direct => "direct",
+            Channel::Internal => "internal",
+            Channel::Social => "social",
+            Channel::Search => "search",
+            Channel::Email => "email",
+            Channel::Referral => "referral",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RefererInfo {
+    pub domain: Option<String>,
+    pub channel: Channel,
+}
+
+/// Extracts the normalized referer domain and its channel. `own_domain` is
+/// the host of `BASE_URL`, links between our own pages count as internal.
+pub fn classify(referer: Option<&str>, own_domain: Option<&str>) -> RefererInfo {
+    let Some(referer) = referer.map(str::trim).filter(|r| !r.is_empty()) else {
+        return RefererInfo {
+            domain: None,
+            channel: Channel::Direct,
+        };
+    };
+
+    let Some(domain) = referer_domain(referer) else {
+        return RefererInfo {
+            domain: None,
+            channel: Channel::Referral,
+        };
+    };
+
+    let channel = if own_domain.is_some_and(|own| own == domain) {
+        Channel::Internal
+    } else if matches_any(&domain, EMAIL_DOMAINS) {
+        Channel::Email
+    } else if matches_any(&domain, SEARCH_DOMAINS) || is_google_search(&domain) {
+        Channel::Search
+    } else if matches_any(&domain, SOCIAL_DOMAINS) {
+        Channel::Social
+    } else {
+        Channel::Referral
+    };
+
+    RefererInfo {
+        domain: Some(domain),
+        channel,
+    }
+}
+
+/// Lowercased host of a url with `www.`, `m.` and `mobile.` prefixes removed.
+pub fn referer_domain(referer: &str) -> Option<String> {
+    let parsed = url::Url::parse(referer).ok()?;
+    let host = parsed.host_str()?.to_ascii_lowercase();
+
+    let domain = ["www.", "m.", "mobile."]
+        .iter()
+        .find_map(|prefix| host.strip_prefix(prefix))
+        .unwrap_or(&host);
+
+    Some(domain.trim_end_matches('.').to_string())
+}
+
+/// Fills `referer_domain` and `channel` on clicks recorded before those
+/// fields existed. Returns how many clicks were updated.
+pub async fn backfill(db: &dyn ClickStore, own_domain: Option<&str>) -> AppResult<u64> {
+    let mut updated = 0;
+
+    loop {
+        let pending = db.clicks_missing_channel(BACKFILL_BATCH_SIZE).await?;
+        if pending.is_empty() {
+            break;
+        }
+
+        let classified: Vec<_> = pending
+            .into_iter()
+            .map(|(id, referer)| (id, classify(referer.as_deref(), own_domain)))
+            .collect();
+        db.set_click_channels(&classified).await?;
+        updated += classified.len() as u64;
+    }
+
+    Ok(updated)
+}
+
+fn matches_any(domain: &str, candidates: &[&str]) -> bool {
+    candidates.iter().any(|candidate| {
+        domain == *candidate
+            || domain
+                .strip_suffix(candidate)
+                .is_some_and(|rest| rest.ends_with('.'))
+    })
+}
+
+/// google.com, google.de, google.co.uk or google.com.au, bare or on one of
+/// the search subdomains. Other Google products such as docs.google.com are
+/// referrals, and so is any host that merely contains `google`.
+fn is_google_search(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let Some(google) = labels.iter().position(|label| *label == "google") else {
+        return false;
+    };
+
+    let country = |label: &str| label.len() == 2 && label.bytes().all(|b| b.is_ascii_lowercase());
+    let suffix = match &labels[google + 1..] {
+        [tld] => tld.len() >= 2 && tld.bytes().all(|b| b.is_ascii_lowercase()),
+        ["co" | "com", cc] => country(cc),
+        _ => false,
+    };
+
+    let subdomain = match &labels[..google] {
+        [] => true,
+        [subdomain] => GOOGLE_SEARCH_SUBDOMAINS.contains(subdomain),
+        _ => false,
+    };
+
+    suffix && subdomain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel(referer: &str) -> Channel {
+        classify(Some(referer), Some("sho.rt")).channel
+    }
+
+    #[test]
+    fn each_channel() {
+        assert_eq!(classify(None, Some("sho.rt")).channel, Channel::Direct);
+        assert_eq!(channel("  "), Channel::Direct);
+        assert_eq!(channel("https://sho.rt/dashboard"), Channel::Internal);
+        assert_eq!(channel("https://t.co/abc"), Channel::Social);
+        assert_eq!(
+            channel("https://news.ycombinator.com/item?id=1"),
+            Channel::Social
+        );
+        assert_eq!(channel("https://duckduckgo.com/?q=links"), Channel::Search);
+        assert_eq!(channel("https://outlook.office.com/mail/"), Channel::Email);
+        assert_eq!(
+            channel("android-app://com.google.android.gm/"),
+            Channel::Email
+        );
+        assert_eq!(channel("https://blog.example.com/post"), Channel::Referral);
+        // no host to classify
+        assert_eq!(channel("not a url"), Channel::Referral);
+        assert_eq!(classify(Some("not a url"), None).domain, None);
+    }
+
+    #[test]
+    fn subdomains_of_listed_domains_match_but_lookalikes_do_not() {
+        assert_eq!(channel("https://old.reddit.com/r/rust"), Channel::Social);
+        assert_eq!(channel("https://notreddit.com/"), Channel::Referral);
+        assert_eq!(
+            channel("https://reddit.com.example.org/"),
+            Channel::Referral
+        );
+    }
+
+    #[test]
+    fn www_and_mobile_prefixes_are_stripped() {
+        for (referer, domain) in [
+            ("https://www.Facebook.com/groups/1", "facebook.com"),
+            ("https://m.facebook.com/", "facebook.com"),
+            ("https://mobile.twitter.com/", "twitter.com"),
+            ("https://www.example.com./page", "example.com"),
+            ("https://web.example.com/", "web.example.com"),
+        ] {
+            assert_eq!(
+                referer_domain(referer).as_deref(),
+                Some(domain),
+                "{}",
+                referer
+            );
+        }
+
+        let info = classify(Some("https://m.youtube.com/watch"), None);
+        assert_eq!(info.domain.as_deref(), Some("youtube.com"));
+        assert_eq!(info.channel, Channel::Social);
+    }
+
+    #[test]
+    fn google_search_across_country_domains() {
+        for domain in [
+            "google.com",
+            "google.de",
+            "google.co.uk",
+            "google.com.au",
+            "images.google.com",
+            "news.google.co.jp",
+        ] {
+            assert!(is_google_search(domain), "{}", domain);
+        }
+        assert_eq!(channel("https://www.google.co.uk/"), Channel::Search);
+    }
+
+    #[test]
+    fn other_google_products_are_not_search() {
+        for domain in [
+            "docs.google.com",
+            "mail.google.com",
+            "googleblog.com",
+            "google.example.com",
+            "a.b.google.com",
+            "google.co.example",
+        ] {
+            assert!(!is_google_search(domain), "{}", domain);
+        }
+        assert_eq!(
+            channel("https://docs.google.com/document/d/1"),
+            Channel::Referral
+        );
+        assert_eq!(channel("https://mail.google.com/mail/u/0/"), Channel::Email);
+    }
+}
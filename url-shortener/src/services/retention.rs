@@ -0,0 +1,46 @@
+use chrono::{Days, Utc};
+
+use crate::db::{ClickStore, LinkStore, Storage};
+use crate::error::{AppError, AppResult};
+use crate::services::url_cache::UrlCache;
+
+/// Deletes clicks older than `days` days, their days live on in the daily
+/// rollups. Whole days are purged at once.
+pub async fn purge_old_clicks(db: &dyn ClickStore, days: u32) -> AppResult<String> {
+    let cutoff = Utc::now()
+        .date_naive()
+        .checked_sub_days(Days::new(days.into()))
+        .ok_or_else(|| AppError::Validation("Click retention is too long".to_string()))?;
+
+    let purged = db.purge_clicks_before(cutoff).await?;
+
+    Ok(format!("purged {} clicks from before {}", purged, cutoff))
+}
+
+/// Deletes links that expired more than `days` days ago, along with their
+/// clicks and aggregates.
+pub async fn purge_expired_links(
+    db: &dyn LinkStore,
+    url_cache: &UrlCache,
+    days: u32,
+) -> AppResult<String> {
+    let cutoff = Utc::now()
+        .checked_sub_days(Days::new(days.into()))
+        .ok_or_else(|| AppError::Validation("Link retention is too long".to_string()))?;
+
+    let deleted = db.delete_urls_expired_before(cutoff).await?;
+    for short_code in &deleted {
+        url_cache.invalidate(short_code);
+    }
+
+    Ok(format!(
+        "deleted {} links expired before {}",
+        deleted.len(),
+        cutoff
+    ))
+}
+
+pub async fn vacuum(db: &dyn Storage) -> AppResult<String> {
+    db.vacuum().await?;
+    Ok("database vacuumed".to_string())
+}
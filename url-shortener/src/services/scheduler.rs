@@ -0,0 +1,114 @@
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::Mutex;
+
+use crate::error::AppResult;
+
+pub type JobFuture = Pin<Box<dyn Future<Output = AppResult<String>> + Send>>;
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobOutcome {
+    Ok,
+    Failed,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct JobStatus {
+    pub name: &'static str,
+    pub interval_seconds: u64,
+    pub runs: u64,
+    pub failures: u64,
+    pub last_started_at: Option<DateTime<Utc>>,
+    pub last_finished_at: Option<DateTime<Utc>>,
+    pub last_outcome: Option<JobOutcome>,
+    pub last_message: Option<String>,
+}
+
+/// Runs periodic maintenance jobs on tokio intervals and remembers how each
+/// job's last run went.
+#[derive(Default)]
+pub struct Scheduler {
+    statuses: Mutex<Vec<JobStatus>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns `job` to run every `every`, starting right away.
+    pub fn schedule<F>(self: &Arc<Self>, name: &'static str, every: Duration, job: F)
+    where
+        F: Fn() -> JobFuture + Send + Sync + 'static,
+    {
+        let index = {
+            let mut statuses = self.statuses.lock().unwrap();
+            statuses.push(JobStatus {
+                name,
+                interval_seconds: every.as_secs(),
+                runs: 0,
+                failures: 0,
+                last_started_at: None,
+                last_finished_at: None,
+                last_outcome: None,
+                last_message: None,
+            });
+            statuses.len() - 1
+        };
+
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                scheduler.update(index, |status| {
+                    status.last_started_at = Some(Utc::now());
+                });
+
+                let result = job().await;
+
+                scheduler.update(index, |status| {
+                    status.runs += 1;
+                    status.last_finished_at = Some(Utc::now());
+                    match &result {
+                        Ok(message) => {
+                            tracing::info!("[SCHEDULER] {}: {}", name, message);
+                            status.last_outcome = Some(JobOutcome::Ok);
+                            status.last_message = Some(message.clone());
+                        }
+                        Err(e) => {
+                            tracing::error!("[SCHEDULER] {} failed: {}", name, e);
+                            status.failures += 1;
+                            status.last_outcome = Some(JobOutcome::Failed);
+                            status.last_message = Some(e.to_string());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    pub fn statuses(&self) -> Vec<JobStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    fn update(&self, index: usize, f: impl FnOnce(&mut JobStatus)) {
+        if let Some(status) = self.statuses.lock().unwrap().get_mut(index) {
+            f(status);
+        }
+    }
+}
+
+/// Boxes an async job body so it can be handed to `Scheduler::schedule`.
+pub fn job<Fut>(future: Fut) -> JobFuture
+where
+    Fut: Future<Output = AppResult<String>> + Send + 'static,
+{
+    Box::pin(future)
+}
@@ -1,7 +1,10 @@
-use crate::db::queries;
+use std::collections::HashSet;
+
+use crate::db::{LinkStore, Storage};
 use crate::error::{AppError, AppResult};
+use crate::models::{CreateUrlRequest, LinkRecord, NewUrl, Url};
+use crate::services::webhooks;
 use nanoid::nanoid;
-use sqlx::SqlitePool;
 
 const ALPHABET: [char; 62] = [
     '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i',
@@ -14,12 +17,12 @@ pub fn generate_short_code(length: usize) -> String {
     nanoid!(length, &ALPHABET)
 }
 
-pub async fn generate_unique_code(pool: &SqlitePool, length: usize) -> AppResult<String> {
+pub async fn generate_unique_code(db: &dyn LinkStore, length: usize) -> AppResult<String> {
     const MAX_RETRIES: u8 = 10;
 
     for _ in 0..MAX_RETRIES {
         let code = generate_short_code(length);
-        if !queries::code_exists(pool, &code).await? {
+        if !db.code_exists(&code).await? {
             return Ok(code);
         }
     }
@@ -52,6 +55,57 @@ pub fn validate_custom_code(code: &str) -> AppResult<()> {
     Ok(())
 }
 
+/// Lowercases and deduplicates tags, rejecting empty or oversized ones.
+pub fn normalize_tags(tags: &[String]) -> AppResult<Vec<String>> {
+    const MAX_TAGS: usize = 10;
+    const MAX_TAG_LENGTH: usize = 32;
+
+    if tags.len() > MAX_TAGS {
+        return Err(AppError::Validation(format!(
+            "At most {} tags are allowed",
+            MAX_TAGS
+        )));
+    }
+
+    let mut normalized: Vec<String> = Vec::with_capacity(tags.len());
+
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+
+        if tag.is_empty() || tag.len() > MAX_TAG_LENGTH {
+            return Err(AppError::Validation(format!(
+                "Tags must be between 1 and {} characters",
+                MAX_TAG_LENGTH
+            )));
+        }
+
+        if !tag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(AppError::Validation(
+                "Tags can only contain letters, digits, '-' and '_'".to_string(),
+            ));
+        }
+
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+
+    Ok(normalized)
+}
+
+pub fn validate_campaign(campaign: &str) -> AppResult<()> {
+    if campaign.trim().is_empty() || campaign.len() > 64 {
+        return Err(AppError::Validation(
+            "Campaign must be between 1 and 64 characters".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
 pub fn validate_url(url: &str) -> AppResult<()> {
     if url.is_empty() {
         return Err(AppError::InvalidUrl);
@@ -65,3 +119,129 @@ pub fn validate_url(url: &str) -> AppResult<()> {
 
     Ok(())
 }
+
+/// Validates a create request and picks its short code, generating one when
+/// no custom code is given.
+pub async fn prepare_link(
+    db: &dyn LinkStore,
+    request: CreateUrlRequest,
+    code_length: usize,
+) -> AppResult<NewUrl> {
+    validate_url(&request.url)?;
+
+    let tags = normalize_tags(&request.tags)?;
+    if let Some(campaign) = &request.campaign {
+        validate_campaign(campaign)?;
+    }
+
+    let short_code = if let Some(custom_code) = request.custom_code {
+        validate_custom_code(&custom_code)?;
+
+        if db.code_exists(&custom_code).await? {
+            return Err(AppError::CodeAlreadyExists);
+        }
+
+        custom_code
+    } else {
+        generate_unique_code(db, code_length).await?
+    };
+
+    Ok(NewUrl {
+        original_url: request.url,
+        short_code,
+        expires_at: request.expires_at,
+        created_at: None,
+        campaign: request.campaign,
+        owner: request.owner,
+        tags,
+    })
+}
+
+/// Prepares each request of a batch like [`prepare_link`]. A code used by an
+/// earlier item of the batch is rejected, or picked again when generated.
+pub async fn prepare_links(
+    db: &dyn LinkStore,
+    requests: Vec<CreateUrlRequest>,
+    code_length: usize,
+) -> Vec<AppResult<NewUrl>> {
+    let mut codes = HashSet::new();
+    let mut prepared = Vec::with_capacity(requests.len());
+
+    for request in requests {
+        let generated = request.custom_code.is_none();
+
+        let mut result = prepare_link(db, request, code_length).await;
+        if let Ok(new_url) = &mut result {
+            while generated && codes.contains(&new_url.short_code) {
+                match generate_unique_code(db, code_length).await {
+                    Ok(code) => new_url.short_code = code,
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let result = result.and_then(|new_url| {
+            if codes.insert(new_url.short_code.clone()) {
+                Ok(new_url)
+            } else {
+                Err(AppError::Validation(format!(
+                    "Short code '{}' is used more than once in the batch",
+                    new_url.short_code
+                )))
+            }
+        });
+        prepared.push(result);
+    }
+
+    prepared
+}
+
+/// Validates an exported record for import. The code is kept as is, so
+/// existing short links keep working after a move.
+pub fn prepare_import(record: LinkRecord) -> AppResult<NewUrl> {
+    validate_url(&record.url)?;
+    validate_custom_code(&record.code)?;
+
+    let tags = normalize_tags(&record.tags)?;
+    if let Some(campaign) = &record.campaign {
+        validate_campaign(campaign)?;
+    }
+
+    Ok(NewUrl {
+        original_url: record.url,
+        short_code: record.code,
+        expires_at: record.expires_at,
+        created_at: record.created_at,
+        campaign: record.campaign,
+        owner: record.owner,
+        tags,
+    })
+}
+
+/// Stores a prepared link and notifies webhooks subscribed to new links.
+pub async fn create_link(db: &dyn Storage, new_url: &NewUrl) -> AppResult<Url> {
+    let url = db.create_url(new_url).await?;
+
+    // the link exists either way, a missed event is logged rather than failing the request
+    if let Err(e) = webhooks::link_created(db, &url, &new_url.tags).await {
+        tracing::error!("[CREATE_LINK] failed to enqueue webhooks: {}", e);
+    }
+
+    Ok(url)
+}
+
+/// Stores prepared links in one transaction, then notifies webhooks of each.
+pub async fn create_links(db: &dyn Storage, new_urls: &[NewUrl]) -> AppResult<Vec<Url>> {
+    let urls = db.create_urls(new_urls).await?;
+
+    for (url, new_url) in urls.iter().zip(new_urls) {
+        if let Err(e) = webhooks::link_created(db, url, &new_url.tags).await {
+            tracing::error!("[CREATE_LINKS] failed to enqueue webhooks: {}", e);
+        }
+    }
+
+    Ok(urls)
+}
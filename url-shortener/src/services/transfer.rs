@@ -0,0 +1,312 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::db::Storage;
+use crate::error::{AppError, AppResult};
+use crate::models::{LinkRecord, NewUrl};
+use crate::services::shorten::prepare_import;
+
+// links created per transaction on import
+const IMPORT_CHUNK_SIZE: usize = 500;
+
+/// File format of a link export or import.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl LinkFormat {
+    /// Guesses the format from a file extension, `.csv` or `.json`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(LinkFormat::Csv),
+            "json" => Some(LinkFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            LinkFormat::Json => "application/json",
+            LinkFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// A link as a CSV row. Tags share one field, separated by commas, and
+/// timestamps are RFC 3339. Only `url` and `code` are required on import.
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    url: String,
+    code: String,
+    #[serde(default)]
+    expires_at: String,
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    created_at: String,
+    #[serde(default)]
+    campaign: String,
+    #[serde(default)]
+    owner: String,
+}
+
+impl From<&LinkRecord> for CsvRow {
+    fn from(record: &LinkRecord) -> Self {
+        Self {
+            url: record.url.clone(),
+            code: record.code.clone(),
+            expires_at: record
+                .expires_at
+                .map(|expires_at| expires_at.to_rfc3339())
+                .unwrap_or_default(),
+            tags: record.tags.join(","),
+            created_at: record
+                .created_at
+                .map(|created_at| created_at.to_rfc3339())
+                .unwrap_or_default(),
+            campaign: record.campaign.clone().unwrap_or_default(),
+            owner: record.owner.clone().unwrap_or_default(),
+        }
+    }
+}
+
+impl CsvRow {
+    fn into_record(self) -> AppResult<LinkRecord> {
+        Ok(LinkRecord {
+            expires_at: parse_time("expires_at", &self.expires_at)?,
+            created_at: parse_time("created_at", &self.created_at)?,
+            tags: self
+                .tags
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect(),
+            campaign: Some(self.campaign).filter(|campaign| !campaign.is_empty()),
+            owner: Some(self.owner).filter(|owner| !owner.is_empty()),
+            url: self.url,
+            code: self.code,
+        })
+    }
+}
+
+/// Empty means unset. Timestamps without an offset, as other tools often
+/// write them, are taken as UTC, a bare date as its midnight.
+fn parse_time(column: &str, value: &str) -> AppResult<Option<DateTime<Utc>>> {
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(value, format) {
+            return Ok(Some(time.and_utc()));
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(Some(date.and_time(NaiveTime::MIN).and_utc()));
+    }
+
+    Err(AppError::Validation(format!(
+        "{} '{}' is not an RFC 3339 timestamp",
+        column, value
+    )))
+}
+
+pub fn write_links<W: Write>(
+    format: LinkFormat,
+    records: &[LinkRecord],
+    writer: W,
+) -> anyhow::Result<()> {
+    match format {
+        LinkFormat::Json => serde_json::to_writer_pretty(writer, records)?,
+        LinkFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for record in records {
+                writer.serialize(CsvRow::from(record))?;
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A row read for import, with the code it claims even when it is invalid.
+#[derive(Debug)]
+pub struct ImportRow {
+    pub code: String,
+    pub record: AppResult<LinkRecord>,
+}
+
+/// Parses an import. A malformed JSON file fails as a whole, CSV rows fail
+/// one by one so the rest can still be imported.
+pub fn read_links<R: Read>(format: LinkFormat, reader: R) -> anyhow::Result<Vec<ImportRow>> {
+    match format {
+        LinkFormat::Json => {
+            let records: Vec<LinkRecord> = serde_json::from_reader(reader)?;
+            Ok(records
+                .into_iter()
+                .map(|record| ImportRow {
+                    code: record.code.clone(),
+                    record: Ok(record),
+                })
+                .collect())
+        }
+        LinkFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(reader);
+
+            let headers = reader.headers()?.clone();
+            let column = |name: &str| headers.iter().position(|header| header == name);
+            if column("url").is_none() {
+                anyhow::bail!("CSV header has no 'url' column");
+            }
+            let Some(code_column) = column("code") else {
+                anyhow::bail!("CSV header has no 'code' column");
+            };
+
+            let malformed = |e: csv::Error| AppError::Validation(format!("Malformed row: {}", e));
+
+            Ok(reader
+                .records()
+                .map(|row| match row {
+                    Ok(row) => ImportRow {
+                        code: row.get(code_column).unwrap_or_default().to_string(),
+                        record: row
+                            .deserialize::<CsvRow>(Some(&headers))
+                            .map_err(malformed)
+                            .and_then(CsvRow::into_record),
+                    },
+                    Err(e) => ImportRow {
+                        code: String::new(),
+                        record: Err(malformed(e)),
+                    },
+                })
+                .collect())
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total: usize,
+    /// Links created, or that would be without the dry run.
+    pub imported: usize,
+    pub skipped: Vec<SkippedRow>,
+}
+
+/// A row left out of an import. Rows are numbered from 1, not counting the
+/// CSV header.
+#[derive(Debug, Serialize)]
+pub struct SkippedRow {
+    pub row: usize,
+    pub code: String,
+    pub error: String,
+    /// `code_already_exists` for conflicts, `validation` for invalid rows,
+    /// `database` when the insert itself failed.
+    pub kind: &'static str,
+}
+
+/// Creates the links of an import, keeping their codes. Rows that are
+/// invalid, repeat an earlier code or conflict with an existing link are
+/// skipped and reported. A dry run checks all of that and creates nothing.
+/// A chunk that fails to insert is retried row by row, so only the rows at
+/// fault are skipped.
+pub async fn import_links(
+    db: &dyn Storage,
+    rows: Vec<ImportRow>,
+    dry_run: bool,
+) -> AppResult<ImportReport> {
+    let total = rows.len();
+    let mut skipped = Vec::new();
+    let mut first_rows: HashMap<String, usize> = HashMap::new();
+    let mut new_urls: Vec<NewUrl> = Vec::with_capacity(total);
+    let mut numbers: Vec<usize> = Vec::with_capacity(total);
+
+    for (index, row) in rows.into_iter().enumerate() {
+        let number = index + 1;
+        let mut skip = |error: AppError| {
+            skipped.push(SkippedRow {
+                row: number,
+                code: row.code.clone(),
+                error: error.to_string(),
+                kind: error.kind(),
+            })
+        };
+
+        let new_url = match row.record.and_then(prepare_import) {
+            Ok(new_url) => new_url,
+            Err(e) => {
+                skip(e);
+                continue;
+            }
+        };
+
+        if let Some(first) = first_rows.get(&new_url.short_code) {
+            skip(AppError::Validation(format!(
+                "Short code is already used by row {}",
+                first
+            )));
+            continue;
+        }
+        first_rows.insert(new_url.short_code.clone(), number);
+
+        if db.code_exists(&new_url.short_code).await? {
+            skip(AppError::CodeAlreadyExists);
+            continue;
+        }
+
+        new_urls.push(new_url);
+        numbers.push(number);
+    }
+
+    let mut imported = new_urls.len();
+
+    if !dry_run {
+        let chunks = new_urls
+            .chunks(IMPORT_CHUNK_SIZE)
+            .zip(numbers.chunks(IMPORT_CHUNK_SIZE));
+        for (chunk, numbers) in chunks {
+            let Err(e) = db.create_urls(chunk).await else {
+                continue;
+            };
+
+            // e.g. a code created since it was checked, find the rows at fault
+            tracing::warn!("[IMPORT] chunk failed, creating its rows one by one: {}", e);
+            for (new_url, number) in chunk.iter().zip(numbers) {
+                if let Err(e) = db.create_url(new_url).await {
+                    imported -= 1;
+                    skipped.push(SkippedRow {
+                        row: *number,
+                        code: new_url.short_code.clone(),
+                        error: e.to_string(),
+                        kind: e.kind(),
+                    });
+                }
+            }
+        }
+        skipped.sort_by_key(|row| row.row);
+    }
+
+    Ok(ImportReport {
+        dry_run,
+        total,
+        imported,
+        skipped,
+    })
+}
@@ -0,0 +1,122 @@
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use lru::LruCache;
+use serde::Serialize;
+
+use crate::db::LinkStore;
+use crate::error::AppResult;
+use crate::models::Url;
+
+struct CachedUrl {
+    url: Url,
+    valid_until: Instant,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UrlCacheStats {
+    pub enabled: bool,
+    pub capacity: usize,
+    pub entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// LRU cache of `Url` rows keyed by short code. Entries live for at most
+/// `ttl`, and never past the link's own expiry. Links removed in-process are
+/// invalidated, changes made by another process, such as the CLI, show up
+/// once the entry's TTL runs out.
+pub struct UrlCache {
+    entries: Option<Mutex<LruCache<String, CachedUrl>>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl UrlCache {
+    /// A capacity of 0 disables caching.
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: NonZeroUsize::new(capacity).map(|cap| Mutex::new(LruCache::new(cap))),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub async fn get_url_by_code(&self, db: &dyn LinkStore, short_code: &str) -> AppResult<Url> {
+        if let Some(url) = self.get(short_code) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(url);
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let url = db.get_url_by_code(short_code).await?;
+        self.insert(url.clone());
+
+        Ok(url)
+    }
+
+    pub fn invalidate(&self, short_code: &str) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().pop(short_code);
+        }
+    }
+
+    pub fn stats(&self) -> UrlCacheStats {
+        let (capacity, entries) = match &self.entries {
+            Some(entries) => {
+                let entries = entries.lock().unwrap();
+                (entries.cap().get(), entries.len())
+            }
+            None => (0, 0),
+        };
+
+        UrlCacheStats {
+            enabled: self.entries.is_some(),
+            capacity,
+            entries,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    fn get(&self, short_code: &str) -> Option<Url> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+
+        match entries.get(short_code) {
+            Some(cached) if cached.valid_until > Instant::now() => Some(cached.url.clone()),
+            Some(_) => {
+                entries.pop(short_code);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, url: Url) {
+        let Some(entries) = &self.entries else {
+            return;
+        };
+
+        let mut valid_until = Instant::now() + self.ttl;
+
+        // drop the entry when the link expires so the next lookup sees fresh data
+        if let Some(expires_at) = url.expires_at {
+            let remaining = (expires_at - Utc::now()).to_std().unwrap_or_default();
+            valid_until = valid_until.min(Instant::now() + remaining);
+        }
+
+        entries
+            .lock()
+            .unwrap()
+            .put(url.short_code.clone(), CachedUrl { url, valid_until });
+    }
+}
@@ -0,0 +1,18 @@
+/// Buckets a user agent into bot, tablet, mobile, desktop or unknown.
+/// The click rollup migration backfills with the same rules in SQL.
+pub fn classify_device(user_agent: Option<&str>) -> &'static str {
+    let Some(user_agent) = user_agent.filter(|ua| !ua.is_empty()) else {
+        return "unknown";
+    };
+    let ua = user_agent.to_ascii_lowercase();
+
+    if ["bot", "crawl", "spider"].iter().any(|s| ua.contains(s)) {
+        "bot"
+    } else if ["ipad", "tablet"].iter().any(|s| ua.contains(s)) {
+        "tablet"
+    } else if ["mobi", "iphone", "android"].iter().any(|s| ua.contains(s)) {
+        "mobile"
+    } else {
+        "desktop"
+    }
+}
@@ -0,0 +1,103 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use url::{Host, Url};
+
+use crate::error::{AppError, AppResult};
+
+/// Rejects webhook URLs whose host is, or resolves to, an address that is
+/// not publicly routable, so webhooks can't be pointed at loopback, the
+/// private network or cloud metadata endpoints.
+pub async fn check_target(url: &str) -> AppResult<()> {
+    let url =
+        Url::parse(url).map_err(|e| AppError::Validation(format!("Invalid webhook URL: {}", e)))?;
+
+    let addresses: Vec<IpAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => {
+            let port = url.port_or_known_default().unwrap_or(443);
+            tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|e| AppError::Validation(format!("Failed to resolve {}: {}", domain, e)))?
+                .map(|address| address.ip())
+                .collect()
+        }
+        None => {
+            return Err(AppError::Validation(
+                "Webhook URL must have a host".to_string(),
+            ));
+        }
+    };
+
+    if addresses.is_empty() || !addresses.iter().copied().all(is_public) {
+        return Err(AppError::Validation(
+            "Webhook URL must point to a public address".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// DNS resolver for the delivery client that drops non-public addresses, so
+/// a name re-pointed after it was checked still can't reach them.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            // the connector fills in the port
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+
+            if addresses.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "this network" and the reserved 240.0.0.0/4
+        || a == 0
+        || a >= 240
+        // carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (b & 0b1100_0000) == 64))
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // unique local, fc00::/7
+        || (first & 0xfe00) == 0xfc00
+        // link-local, fe80::/10
+        || (first & 0xffc0) == 0xfe80
+        // documentation, 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0db8))
+}
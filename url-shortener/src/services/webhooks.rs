@@ -0,0 +1,400 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{SubsecRound, Utc};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use tokio::task::JoinSet;
+
+use crate::db::Storage;
+use crate::error::{AppError, AppResult};
+use crate::models::{NewClick, NewDelivery, PendingDelivery, Url, Webhook, WebhookEvent};
+use crate::services::webhook_targets::{PublicResolver, check_target};
+
+// due deliveries sent per dispatcher run
+const DELIVERY_BATCH_SIZE: i64 = 50;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(10);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(3600);
+// claims outlast the request timeout by this much, to record the outcome
+const LEASE_MARGIN: Duration = Duration::from_secs(30);
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+pub const EVENT_HEADER: &str = "x-webhook-event";
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    id: &'a str,
+    event: WebhookEvent,
+    created_at: String,
+    data: serde_json::Value,
+}
+
+/// Random hex secret for webhooks registered without one.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 32];
+    rand::rng().fill_bytes(&mut secret);
+    hex::encode(secret)
+}
+
+/// `sha256=<hex>` HMAC of `"{timestamp}.{body}"` keyed with the webhook secret.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+pub async fn link_created(db: &dyn Storage, url: &Url, tags: &[String]) -> AppResult<()> {
+    let data = json!({
+        "short_code": url.short_code,
+        "original_url": url.original_url,
+        "owner": url.owner,
+        "campaign": url.campaign,
+        "tags": tags,
+        "expires_at": url.expires_at,
+    });
+
+    enqueue(db, &url.id, WebhookEvent::LinkCreated, data).await
+}
+
+/// Webhook deliveries a batch of clicks produces. The store writes them in
+/// the transaction that records the clicks, so a click and its events are
+/// committed or rolled back together.
+#[derive(Debug, Default)]
+pub struct ClickOutbox {
+    deliveries: Vec<NewDelivery>,
+    thresholds: Vec<ThresholdWatch>,
+}
+
+#[derive(Debug)]
+struct ThresholdWatch {
+    webhook_id: String,
+    url_id: String,
+    short_code: String,
+    threshold: i64,
+}
+
+impl ClickOutbox {
+    /// Looks up the subscriptions of each link in the batch once and builds
+    /// the `link.clicked` deliveries.
+    pub async fn for_clicks(db: &dyn Storage, batch: &[NewClick]) -> AppResult<Self> {
+        let mut outbox = Self::default();
+        if batch.is_empty() || !db.any_webhooks().await? {
+            return Ok(outbox);
+        }
+
+        let mut subscribed: HashMap<&str, Vec<Webhook>> = HashMap::new();
+
+        for click in batch {
+            let webhooks = match subscribed.entry(&click.url_id) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let webhooks = db
+                        .subscribed_webhooks(
+                            &click.url_id,
+                            &[WebhookEvent::LinkClicked, WebhookEvent::ClickThreshold],
+                        )
+                        .await?;
+                    for webhook in &webhooks {
+                        if let Some(threshold) = webhook.click_threshold
+                            && webhook.subscribes(WebhookEvent::ClickThreshold)
+                        {
+                            outbox.thresholds.push(ThresholdWatch {
+                                webhook_id: webhook.id.clone(),
+                                url_id: click.url_id.clone(),
+                                short_code: click.short_code.clone(),
+                                threshold,
+                            });
+                        }
+                    }
+                    entry.insert(webhooks)
+                }
+            };
+
+            if !webhooks
+                .iter()
+                .any(|webhook| webhook.subscribes(WebhookEvent::LinkClicked))
+            {
+                continue;
+            }
+
+            let data = json!({
+                "short_code": click.short_code,
+                // stored to the second
+                "clicked_at": click.clicked_at.trunc_subsecs(0),
+                "referer_domain": click.referer_domain,
+                "channel": click.channel,
+                "device": click.device,
+                // resolved after the click is recorded, if ever
+                "country": serde_json::Value::Null,
+            });
+            let payload = envelope(&event_id(), WebhookEvent::LinkClicked, data)?;
+
+            for webhook in webhooks
+                .iter()
+                .filter(|webhook| webhook.subscribes(WebhookEvent::LinkClicked))
+            {
+                outbox.deliveries.push(NewDelivery {
+                    webhook_id: webhook.id.clone(),
+                    event: WebhookEvent::LinkClicked,
+                    payload: payload.clone(),
+                });
+            }
+        }
+
+        Ok(outbox)
+    }
+
+    /// Every delivery of the batch given the click counters it produced, as
+    /// `(url id, clicks in the batch, counter after the batch)`. Thresholds
+    /// fire for links the batch took from below a threshold to at least it.
+    pub fn deliveries(&self, counters: &[(String, i64, i64)]) -> AppResult<Vec<NewDelivery>> {
+        let mut deliveries = self.deliveries.clone();
+        // one event per link, shared by every webhook it is delivered to
+        let mut event_ids: HashMap<&str, String> = HashMap::new();
+
+        for watch in &self.thresholds {
+            let Some((_, batch_clicks, click_count)) = counters
+                .iter()
+                .find(|(url_id, _, _)| *url_id == watch.url_id)
+            else {
+                continue;
+            };
+            let previous = click_count - batch_clicks;
+            if previous >= watch.threshold || *click_count < watch.threshold {
+                continue;
+            }
+
+            let event_id = event_ids.entry(&watch.url_id).or_insert_with(event_id);
+            let data = json!({
+                "short_code": watch.short_code,
+                "threshold": watch.threshold,
+                "click_count": click_count,
+            });
+            deliveries.push(NewDelivery {
+                webhook_id: watch.webhook_id.clone(),
+                event: WebhookEvent::ClickThreshold,
+                payload: envelope(event_id, WebhookEvent::ClickThreshold, data)?,
+            });
+        }
+
+        Ok(deliveries)
+    }
+}
+
+/// Scheduled job announcing links whose expiry date has passed.
+pub async fn notify_expired_links(db: &dyn Storage) -> AppResult<String> {
+    let expired = db.take_newly_expired_urls(Utc::now()).await?;
+
+    for url in &expired {
+        let data = json!({
+            "short_code": url.short_code,
+            "original_url": url.original_url,
+            "owner": url.owner,
+            "expires_at": url.expires_at,
+        });
+        enqueue(db, &url.id, WebhookEvent::LinkExpired, data).await?;
+    }
+
+    Ok(format!("announced {} expired links", expired.len()))
+}
+
+/// Sends due outbox rows, rescheduling failures with exponential backoff.
+pub struct WebhookDispatcher {
+    db: Arc<dyn Storage>,
+    client: reqwest::Client,
+    max_attempts: u32,
+    allow_private_targets: bool,
+    lease: Duration,
+}
+
+impl WebhookDispatcher {
+    pub fn new(
+        db: Arc<dyn Storage>,
+        timeout: Duration,
+        max_attempts: u32,
+        allow_private_targets: bool,
+    ) -> AppResult<Self> {
+        // a redirect could lead anywhere, receivers have to answer directly
+        let mut client = reqwest::Client::builder()
+            .timeout(timeout)
+            .redirect(reqwest::redirect::Policy::none());
+        if !allow_private_targets {
+            client = client.dns_resolver(Arc::new(PublicResolver));
+        }
+        let client = client.build().map_err(|e| {
+            AppError::Internal(anyhow::anyhow!("Failed to build HTTP client: {}", e))
+        })?;
+
+        Ok(Self {
+            db,
+            client,
+            max_attempts,
+            allow_private_targets,
+            lease: timeout + LEASE_MARGIN,
+        })
+    }
+
+    /// Scheduled job body, sends one batch of due deliveries concurrently.
+    pub async fn deliver_due(&self) -> AppResult<String> {
+        let due = self
+            .db
+            .due_deliveries(DELIVERY_BATCH_SIZE, self.lease)
+            .await?;
+
+        let mut sends = JoinSet::new();
+        for delivery in due {
+            let client = self.client.clone();
+            let allow_private_targets = self.allow_private_targets;
+            sends.spawn(async move {
+                let result = send(&client, &delivery, allow_private_targets).await;
+                (delivery, result)
+            });
+        }
+
+        let (mut delivered, mut retried, mut failed) = (0, 0, 0);
+
+        while let Some(joined) = sends.join_next().await {
+            let (delivery, result) = joined
+                .map_err(|e| AppError::Internal(anyhow::anyhow!("Delivery task failed: {}", e)))?;
+
+            match result {
+                Ok(status_code) => {
+                    self.db.mark_delivered(&delivery.id, status_code).await?;
+                    delivered += 1;
+                }
+                Err((status_code, error)) => {
+                    let attempts = delivery.attempts as u32 + 1;
+                    let retry_at =
+                        (attempts < self.max_attempts).then(|| Utc::now() + retry_delay(attempts));
+
+                    tracing::warn!(
+                        "[WEBHOOKS] delivery {} of {} to {} failed (attempt {}): {}",
+                        delivery.id,
+                        delivery.event,
+                        delivery.url,
+                        attempts,
+                        error
+                    );
+
+                    self.db
+                        .mark_attempt_failed(&delivery.id, status_code, &error, retry_at)
+                        .await?;
+
+                    if retry_at.is_some() {
+                        retried += 1;
+                    } else {
+                        failed += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(format!(
+            "delivered {}, retrying {}, gave up on {}",
+            delivered, retried, failed
+        ))
+    }
+}
+
+async fn send(
+    client: &reqwest::Client,
+    delivery: &PendingDelivery,
+    allow_private_targets: bool,
+) -> Result<u16, (Option<u16>, String)> {
+    // the resolver filters names, this also covers IP literals and webhooks
+    // registered before the check existed
+    if !allow_private_targets {
+        check_target(&delivery.url)
+            .await
+            .map_err(|e| (None, e.to_string()))?;
+    }
+
+    let timestamp = Utc::now().timestamp();
+
+    let response = client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &delivery.event)
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(
+            SIGNATURE_HEADER,
+            sign(&delivery.secret, timestamp, &delivery.payload),
+        )
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| (None, e.to_string()))?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(status.as_u16())
+    } else {
+        Err((
+            Some(status.as_u16()),
+            format!("endpoint responded {}", status),
+        ))
+    }
+}
+
+// 10s, 20s, 40s, ... capped at an hour
+fn retry_delay(attempts: u32) -> chrono::Duration {
+    let delay = BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY);
+
+    chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::hours(1))
+}
+
+async fn enqueue(
+    db: &dyn Storage,
+    url_id: &str,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) -> AppResult<()> {
+    let subscribed = db.subscribed_webhooks(url_id, &[event]).await?;
+    if subscribed.is_empty() {
+        return Ok(());
+    }
+
+    let payload = envelope(&event_id(), event, data)?;
+    let deliveries: Vec<NewDelivery> = subscribed
+        .into_iter()
+        .map(|webhook| NewDelivery {
+            webhook_id: webhook.id,
+            event,
+            payload: payload.clone(),
+        })
+        .collect();
+
+    db.enqueue_deliveries(&deliveries).await
+}
+
+fn envelope(id: &str, event: WebhookEvent, data: serde_json::Value) -> AppResult<String> {
+    let envelope = Envelope {
+        id,
+        event,
+        created_at: Utc::now().to_rfc3339(),
+        data,
+    };
+
+    serde_json::to_string(&envelope)
+        .map_err(|e| AppError::Internal(anyhow::anyhow!("Failed to encode webhook payload: {}", e)))
+}
+
+// shared by every delivery of one event so receivers can deduplicate
+fn event_id() -> String {
+    let mut id = [0u8; 16];
+    rand::rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
@@ -1,12 +1,31 @@
-use crate::models::Url;
+use crate::models::{ClickStats, DateRange, Url};
 use askama::Template;
 
 #[derive(Template)]
 #[template(path = "index.html")]
-pub struct IndexTemplate {}
+pub struct IndexTemplate {
+    pub base_path: String,
+}
 
 #[derive(Template)]
 #[template(path = "dashboard.html")]
 pub struct DashboardTemplate {
+    pub base_path: String,
     pub urls: Vec<Url>,
 }
+
+#[derive(Template)]
+#[template(path = "stats.html")]
+pub struct StatsTemplate {
+    pub base_path: String,
+    pub url: Url,
+    pub short_url: String,
+    pub tags: Vec<String>,
+    pub range: DateRange,
+    pub stats: ClickStats,
+    pub clicks_chart: String,
+    pub country_chart: String,
+    pub referer_chart: String,
+    pub channel_chart: String,
+    pub device_chart: String,
+}
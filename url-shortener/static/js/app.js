@@ -1,3 +1,4 @@
+const basePath = document.body.dataset.basePath;
 const form = document.getElementById('shortenForm');
 const resultDiv = document.getElementById('result');
 const errorDiv = document.getElementById('error');
@@ -21,7 +22,7 @@ form.addEventListener('submit', async (e) => {
     };
 
     try {
-        const response = await fetch("/api/shorten", {
+        const response = await fetch(`${basePath}/api/shorten`, {
             method: "POST",
             headers: {
                 'Content-Type': "application/json",
@@ -36,7 +37,7 @@ form.addEventListener('submit', async (e) => {
         }
 
         shortUrlInput.value = data.short_url;
-        viewStatsLink.href = `/api/urls/${data.short_code}`;
+        viewStatsLink.href = `${basePath}/dashboard/${data.short_code}`;
         resultDiv.classList.remove("hidden");
 
         form.reset();
@@ -0,0 +1,15 @@
+const liveCount = document.getElementById('liveCount');
+
+const events = new EventSource(`${document.body.dataset.basePath}/api/live`);
+
+events.addEventListener('click', (e) => {
+    const click = JSON.parse(e.data);
+
+    liveCount.textContent = Number(liveCount.textContent) + 1;
+
+    document
+        .querySelectorAll(`.click-count[data-short-code="${click.short_code}"]`)
+        .forEach((counter) => {
+            counter.textContent = Number(counter.textContent) + 1;
+        });
+});
@@ -0,0 +1,627 @@
+//! The HTTP API end to end, through `build_router`, mostly on a `MemoryStore`.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{
+    Router,
+    body::Body,
+    extract::ConnectInfo,
+    http::{Request, StatusCode, header},
+};
+use serde_json::{Value, json};
+use tempfile::TempDir;
+use tower::ServiceExt;
+use url_shortener::{
+    Shortener,
+    config::{Config, ConfigArgs, DatabaseConfig, PrivacyMode},
+    db::{MemoryStore, SqliteStore, Storage},
+};
+
+const BASE_URL: &str = "http://localhost:3000";
+const ADMIN_TOKEN: &str = "router-test-admin-token";
+
+fn start(admin_token: Option<&str>) -> Shortener {
+    Shortener::start(
+        Arc::new(config(admin_token)),
+        ConfigArgs::default(),
+        Arc::new(MemoryStore::new()),
+    )
+    .unwrap()
+}
+
+fn config(admin_token: Option<&str>) -> Config {
+    let mut config = Config::default();
+    config.server.base_url = BASE_URL.to_string();
+    config.server.admin_token = admin_token.map(str::to_string);
+    config.rate_limits.create_requests_per_minute = 1000;
+    config.rate_limits.redirect_requests_per_minute = 1000;
+    config.rate_limits.stats_requests_per_minute = 1000;
+    config
+}
+
+/// Like [`start`] on a SQLite file in `dir`. The memory store checks codes
+/// and inserts under one lock, SQLite lets concurrent requests race.
+async fn start_sqlite(dir: &TempDir, admin_token: Option<&str>) -> Shortener {
+    let database = DatabaseConfig {
+        url: format!("sqlite:{}", dir.path().join("router.db").display()),
+        ..DatabaseConfig::default()
+    };
+    let db = SqliteStore::connect(&database).await.unwrap();
+    db.migrate().await.unwrap();
+
+    Shortener::start(
+        Arc::new(config(admin_token)),
+        ConfigArgs::default(),
+        Arc::new(db),
+    )
+    .unwrap()
+}
+
+/// Sends `request` from 192.0.2.1 and returns the status, the `Location`
+/// header and the JSON body, `Null` when there is none.
+async fn send(app: &Router, request: Request<Body>) -> (StatusCode, Option<String>, Value) {
+    send_from(app, request, [192, 0, 2, 1]).await
+}
+
+async fn send_from(
+    app: &Router,
+    mut request: Request<Body>,
+    ip: [u8; 4],
+) -> (StatusCode, Option<String>, Value) {
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from((ip, 40000))));
+
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let location = response
+        .headers()
+        .get(header::LOCATION)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+
+    (status, location, body)
+}
+
+fn get(uri: &str) -> Request<Body> {
+    Request::get(uri).body(Body::empty()).unwrap()
+}
+
+fn post_json(uri: &str, body: Value) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn with_admin_token(mut request: Request<Body>) -> Request<Body> {
+    request.headers_mut().insert(
+        header::AUTHORIZATION,
+        format!("Bearer {}", ADMIN_TOKEN).parse().unwrap(),
+    );
+    request
+}
+
+#[tokio::test]
+async fn shorten_then_redirect() {
+    let app = start(None).router();
+
+    let (status, _, body) = send(
+        &app,
+        post_json(
+            "/api/shorten",
+            json!({"url": "https://example.com/docs", "custom_code": "docs", "tags": ["Guide"]}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["short_code"], "docs");
+    assert_eq!(body["short_url"], format!("{}/docs", BASE_URL));
+    assert_eq!(body["tags"], json!(["guide"]));
+
+    let (status, location, _) = send(&app, get("/docs")).await;
+    assert!(status.is_redirection(), "got {}", status);
+    assert_eq!(location.as_deref(), Some("https://example.com/docs"));
+
+    let (status, _, body) = send(
+        &app,
+        post_json(
+            "/api/shorten",
+            json!({"url": "https://example.com/other", "custom_code": "docs"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert!(body["error"].is_string());
+
+    let (status, _, _) = send(
+        &app,
+        post_json("/api/shorten", json!({"url": "ftp://example.com"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _, _) = send(&app, get("/missing")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn stats_count_redirects() {
+    let app = start(None).router();
+
+    let (status, _, _) = send(
+        &app,
+        post_json(
+            "/api/shorten",
+            json!({"url": "https://example.com/stats", "custom_code": "stats", "owner": "alice"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    for ip in [[192, 0, 2, 1], [192, 0, 2, 1], [192, 0, 2, 2]] {
+        let (status, _, _) = send_from(&app, get("/stats"), ip).await;
+        assert!(status.is_redirection());
+    }
+
+    // clicks are written in the background
+    let mut stats = Value::Null;
+    for _ in 0..50 {
+        let (status, _, body) = send(&app, get("/api/urls/stats")).await;
+        assert_eq!(status, StatusCode::OK);
+        stats = body;
+        if stats["total_clicks"] == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(stats["total_clicks"], 3);
+    assert_eq!(stats["unique_ips"], 2);
+
+    let (status, _, body) = send(&app, get("/api/stats?owner=alice")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total_links"], 1);
+    assert_eq!(body["total_clicks"], 3);
+
+    let (status, _, body) = send(&app, get("/api/stats?owner=bob")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total_links"], 0);
+
+    let (status, _, _) = send(&app, get("/api/urls/missing")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn unique_ips_follow_the_privacy_mode() {
+    // two visitors sharing a /24, one of them twice, and one elsewhere
+    let visitors = [
+        [192, 0, 2, 1],
+        [192, 0, 2, 1],
+        [192, 0, 2, 2],
+        [198, 51, 100, 1],
+    ];
+
+    for (mode, unique_ips) in [
+        (PrivacyMode::Full, 3),
+        (PrivacyMode::Truncate, 2),
+        (PrivacyMode::Hash, 3),
+    ] {
+        let mut config = config(None);
+        config.analytics.privacy_mode = mode;
+        let app = Shortener::start(
+            Arc::new(config),
+            ConfigArgs::default(),
+            Arc::new(MemoryStore::new()),
+        )
+        .unwrap()
+        .router();
+
+        let (status, _, _) = send(
+            &app,
+            post_json(
+                "/api/shorten",
+                json!({"url": "https://example.com/private", "custom_code": "private"}),
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        for ip in visitors {
+            send_from(&app, get("/private"), ip).await;
+        }
+
+        let mut stats = Value::Null;
+        for _ in 0..50 {
+            (_, _, stats) = send(&app, get("/api/urls/private")).await;
+            if stats["total_clicks"] == visitors.len() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(stats["total_clicks"], visitors.len(), "{:?}", mode);
+        assert_eq!(stats["unique_ips"], unique_ips, "{:?}", mode);
+        assert_eq!(stats["metadata"]["ip_mode"], json!(mode), "{:?}", mode);
+    }
+}
+
+#[tokio::test]
+async fn bulk_reports_each_item() {
+    let app = start(None).router();
+
+    let items = json!([
+        {"url": "https://example.com/a", "custom_code": "bulka"},
+        {"url": "not a url"},
+        {"url": "https://example.com/b"},
+    ]);
+
+    let (status, _, body) = send(
+        &app,
+        post_json("/api/shorten/bulk?atomic=true", items.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["created"], 0);
+    let statuses: Vec<&str> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["skipped", "failed", "skipped"]);
+
+    let (status, _, body) = send(&app, post_json("/api/shorten/bulk", items)).await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(body["created"], 2);
+    assert_eq!(body["failed"], 1);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["link"]["short_code"], "bulka");
+    assert_eq!(results[1]["status"], "failed");
+    assert_eq!(results[1]["kind"], "validation");
+    assert_eq!(results[2]["status"], "created");
+
+    let (status, location, _) = send(&app, get("/bulka")).await;
+    assert!(status.is_redirection());
+    assert_eq!(location.as_deref(), Some("https://example.com/a"));
+
+    // an existing code fails that item only
+    let (status, _, body) = send(
+        &app,
+        post_json(
+            "/api/shorten/bulk",
+            json!([
+                {"url": "https://example.com/c", "custom_code": "bulka"},
+                {"url": "https://example.com/d", "custom_code": "bulkd"},
+            ]),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::MULTI_STATUS);
+    assert_eq!(body["results"][0]["kind"], "code_already_exists");
+    assert_eq!(body["results"][1]["status"], "created");
+
+    let (status, _, _) = send(&app, post_json("/api/shorten/bulk", json!([]))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn bulk_fits_the_default_quotas() {
+    let mut config = Config::default();
+    config.server.base_url = BASE_URL.to_string();
+    let app = Shortener::start(
+        Arc::new(config.clone()),
+        ConfigArgs::default(),
+        Arc::new(MemoryStore::new()),
+    )
+    .unwrap()
+    .router();
+
+    let batch = |prefix: &str, size: usize| {
+        let items: Vec<Value> = (0..size)
+            .map(|i| json!({"url": format!("https://example.com/{}/{}", prefix, i)}))
+            .collect();
+        post_json("/api/shorten/bulk", Value::Array(items))
+    };
+
+    // far more items than create_requests_per_minute
+    let size = config.policy.bulk_max_items;
+    assert!(size > config.rate_limits.create_requests_per_minute as usize);
+    let (status, _, body) = send(&app, batch("a", size)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["created"], size);
+
+    // the bulk quota is spent, single creations have their own
+    let (status, _, _) = send(&app, batch("b", 1)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _, _) = send(
+        &app,
+        post_json("/api/shorten", json!({"url": "https://example.com/single"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn import_needs_the_admin_token() {
+    let app = start(None).router();
+    let (status, _, _) = send(&app, post_json("/api/admin/links/import", json!([]))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let app = start(Some(ADMIN_TOKEN)).router();
+    let (status, _, _) = send(&app, post_json("/api/admin/links/import", json!([]))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn import_skips_conflicts() {
+    let app = start(Some(ADMIN_TOKEN)).router();
+
+    let (status, _, _) = send(
+        &app,
+        post_json(
+            "/api/shorten",
+            json!({"url": "https://example.com/taken", "custom_code": "taken"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let rows = json!([
+        {"url": "https://example.com/one", "code": "imported1", "tags": ["docs"]},
+        {"url": "https://example.com/two", "code": "taken"},
+        {"url": "https://example.com/three", "code": "imported3", "owner": "alice"},
+    ]);
+
+    let (status, _, report) = send(
+        &app,
+        with_admin_token(post_json(
+            "/api/admin/links/import?dry_run=true",
+            rows.clone(),
+        )),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["imported"], 2);
+    let (status, _, _) = send(&app, get("/imported1")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, _, report) = send(
+        &app,
+        with_admin_token(post_json("/api/admin/links/import", rows)),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["total"], 3);
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["skipped"][0]["row"], 2);
+    assert_eq!(report["skipped"][0]["code"], "taken");
+    assert_eq!(report["skipped"][0]["kind"], "code_already_exists");
+
+    let (status, location, _) = send(&app, get("/imported3")).await;
+    assert!(status.is_redirection());
+    assert_eq!(location.as_deref(), Some("https://example.com/three"));
+    let (_, location, _) = send(&app, get("/taken")).await;
+    assert_eq!(location.as_deref(), Some("https://example.com/taken"));
+}
+
+#[tokio::test]
+async fn start_validates_the_config() {
+    let mut config = config(None);
+    config.server.base_url = "localhost:3000".to_string();
+    config.server.admin_token = Some("short".to_string());
+
+    let error = Shortener::start(
+        Arc::new(config),
+        ConfigArgs::default(),
+        Arc::new(MemoryStore::new()),
+    )
+    .err()
+    .unwrap()
+    .to_string();
+    assert!(error.contains("server.base_url"), "{}", error);
+    assert!(error.contains("server.admin_token"), "{}", error);
+}
+
+#[tokio::test]
+async fn metrics_can_be_left_out() {
+    let (status, _, _) = send(&start(None).router(), get("/metrics")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let shortener = Shortener::start_with_metrics(
+        Arc::new(config(None)),
+        ConfigArgs::default(),
+        Arc::new(MemoryStore::new()),
+        None,
+    )
+    .unwrap();
+    let (status, _, _) = send(&shortener.router(), get("/metrics")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn assets_are_served_from_the_binary() {
+    let app = start(None).router();
+
+    let mut request = get("/static/css/styles.css");
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([192, 0, 2, 1], 40000))));
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/css; charset=utf-8"
+    );
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    assert_eq!(body, include_str!("../static/css/styles.css"));
+
+    let (status, _, _) = send(&app, get("/static/js/dashboard.js")).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send(&app, get("/static/missing.css")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn bulk_race_fails_only_the_taken_code() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = start_sqlite(&dir, None).await.router();
+
+    let mut requests = tokio::task::JoinSet::new();
+    for i in 0..8 {
+        let app = app.clone();
+        requests.spawn(async move {
+            let items = json!([
+                {"url": "https://example.com/own", "custom_code": format!("own{}", i)},
+                {"url": "https://example.com/race", "custom_code": "race"},
+            ]);
+            send(&app, post_json("/api/shorten/bulk", items)).await
+        });
+    }
+
+    let mut winners = 0;
+    while let Some(response) = requests.join_next().await {
+        let (status, _, body) = response.unwrap();
+        assert_eq!(body["results"][0]["status"], "created", "{}", body);
+        match body["results"][1]["status"].as_str() {
+            Some("created") => {
+                assert_eq!(status, StatusCode::OK);
+                winners += 1;
+            }
+            _ => {
+                assert_eq!(status, StatusCode::MULTI_STATUS);
+                assert_eq!(body["results"][1]["kind"], "code_already_exists");
+            }
+        }
+    }
+    assert_eq!(winners, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_imports_skip_rows_taken_meanwhile() {
+    let dir = tempfile::tempdir().unwrap();
+    let app = start_sqlite(&dir, Some(ADMIN_TOKEN)).await.router();
+
+    let rows: Vec<Value> = (0..20)
+        .map(|i| json!({"url": "https://example.com/import", "code": format!("race{}", i)}))
+        .collect();
+
+    let mut requests = tokio::task::JoinSet::new();
+    for _ in 0..4 {
+        let app = app.clone();
+        let rows = Value::from(rows.clone());
+        requests.spawn(async move {
+            send(
+                &app,
+                with_admin_token(post_json("/api/admin/links/import", rows)),
+            )
+            .await
+        });
+    }
+
+    let mut imported = 0;
+    while let Some(response) = requests.join_next().await {
+        let (status, _, report) = response.unwrap();
+        assert_eq!(status, StatusCode::OK, "{}", report);
+
+        let skipped = report["skipped"].as_array().unwrap();
+        assert_eq!(
+            report["imported"].as_u64().unwrap() as usize + skipped.len(),
+            20
+        );
+        assert!(
+            skipped
+                .iter()
+                .all(|row| row["kind"] == "code_already_exists"),
+            "{}",
+            report
+        );
+        imported += report["imported"].as_u64().unwrap();
+    }
+    assert_eq!(imported, 20);
+
+    for i in [0, 19] {
+        let (status, _, _) = send(&app, get(&format!("/race{}", i))).await;
+        assert!(status.is_redirection());
+    }
+}
+
+#[tokio::test]
+async fn webhooks_need_their_owner_or_the_admin_token() {
+    let app = start(Some(ADMIN_TOKEN)).router();
+    let hook = |owner: Option<&str>| json!({"url": "http://93.184.216.34/hook", "events": ["link.created"], "owner": owner});
+
+    let (status, _, owned) = send(&app, post_json("/api/webhooks", hook(Some("alice")))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let owned = owned["id"].as_str().unwrap().to_string();
+
+    let (status, _, _) = send(&app, post_json("/api/webhooks", hook(None))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _, global) = send(
+        &app,
+        with_admin_token(post_json("/api/webhooks", hook(None))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let global = global["id"].as_str().unwrap().to_string();
+
+    let (status, _, _) = send(&app, get("/api/webhooks")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (_, _, listed) = send(&app, get("/api/webhooks?owner=alice")).await;
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    let (_, _, listed) = send(&app, with_admin_token(get("/api/webhooks"))).await;
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+
+    let deliveries = |id: &str, owner: &str| format!("/api/webhooks/{}/deliveries{}", id, owner);
+    let (status, _, _) = send(&app, get(&deliveries(&owned, ""))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = send(&app, get(&deliveries(&owned, "?owner=bob"))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = send(&app, get(&deliveries(&owned, "?owner=alice"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = send(&app, get(&deliveries(&global, "?owner=alice"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let delete = |uri: String| Request::delete(uri).body(Body::empty()).unwrap();
+    let (status, _, _) = send(&app, delete(format!("/api/webhooks/{}?owner=bob", owned))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = send(&app, delete(format!("/api/webhooks/{}", global))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = send(&app, delete(format!("/api/webhooks/{}?owner=alice", owned))).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _) = send(
+        &app,
+        with_admin_token(delete(format!("/api/webhooks/{}", global))),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn click_export_needs_the_admin_token() {
+    let app = start(Some(ADMIN_TOKEN)).router();
+    let (status, _, _) = send(
+        &app,
+        post_json(
+            "/api/shorten",
+            json!({"url": "https://example.com/export", "custom_code": "export"}),
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _, _) = send(&app, get("/api/urls/export/clicks")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = send(&app, with_admin_token(get("/api/urls/export/clicks"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // disabled without a token
+    let app = start(None).router();
+    let (status, _, _) = send(&app, with_admin_token(get("/api/urls/export/clicks"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,140 @@
+//! Hammers a fresh SQLite file with concurrent writes and reads. None of
+//! them may fail, e.g. with "database is locked", and every click batch has
+//! to land.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio::sync::Mutex;
+use url_shortener::{
+    config::{DatabaseConfig, JournalMode},
+    db::{SqliteStore, Storage},
+    error::AppResult,
+    models::{CreateUrlRequest, DateRange, NewClick},
+    services::{shorten, webhooks::ClickOutbox},
+};
+
+const LINKS: usize = 20;
+const CLICK_BATCH: usize = 10;
+const TASKS: usize = 16;
+const ITERATIONS: usize = 60;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn wal_journal() {
+    hammer(JournalMode::Wal).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn delete_journal() {
+    hammer(JournalMode::Delete).await;
+}
+
+async fn hammer(journal_mode: JournalMode) {
+    let dir = tempfile::tempdir().unwrap();
+    let config = DatabaseConfig {
+        url: format!("sqlite:{}", dir.path().join("concurrency.db").display()),
+        sqlite_journal_mode: journal_mode,
+        ..DatabaseConfig::default()
+    };
+    let db: Arc<dyn Storage> = Arc::new(SqliteStore::connect(&config).await.unwrap());
+    db.migrate().await.unwrap();
+
+    let mut links = Vec::with_capacity(LINKS);
+    for i in 0..LINKS {
+        links.push(create(db.as_ref(), format!("stress{}", i)).await.unwrap());
+    }
+    let links = Arc::new(links);
+
+    let errors = Arc::new(Mutex::new(BTreeMap::<String, u64>::new()));
+
+    let mut handles = Vec::with_capacity(TASKS);
+    for task in 0..TASKS {
+        let db = db.clone();
+        let links = links.clone();
+        let errors = errors.clone();
+
+        handles.push(tokio::spawn(async move {
+            for i in 0..ITERATIONS {
+                let (short_code, url_id) = &links[(task + i) % links.len()];
+
+                // a quarter of the tasks write, the others read like dashboards do
+                let result = if task % 4 == 0 {
+                    if i % 20 == 0 {
+                        create(db.as_ref(), format!("t{}i{}", task, i))
+                            .await
+                            .map(|_| ())
+                    } else {
+                        let clicks = (0..CLICK_BATCH)
+                            .map(|n| click(short_code, url_id, n))
+                            .collect::<Vec<_>>();
+                        db.record_clicks(&clicks, &ClickOutbox::default())
+                            .await
+                            .map(|_| ())
+                    }
+                } else {
+                    match i % 3 {
+                        0 => db.get_url_by_code(short_code).await.map(|_| ()),
+                        1 => db
+                            .get_url_stats(url_id.clone(), &DateRange::default())
+                            .await
+                            .map(|_| ()),
+                        _ => db.click_counts(&[url_id.as_str()]).await.map(|_| ()),
+                    }
+                };
+
+                if let Err(error) = result {
+                    *errors.lock().await.entry(error.to_string()).or_default() += 1;
+                }
+            }
+        }));
+    }
+
+    for handle in handles {
+        handle.await.unwrap();
+    }
+
+    let errors = errors.lock().await;
+    assert!(errors.is_empty(), "operations failed: {:?}", *errors);
+
+    let writer_tasks = TASKS.div_ceil(4);
+    let click_batches = writer_tasks * (ITERATIONS - ITERATIONS.div_ceil(20));
+    let url_ids = links.iter().map(|(_, id)| id.as_str()).collect::<Vec<_>>();
+    let recorded: i64 = db
+        .click_counts(&url_ids)
+        .await
+        .unwrap()
+        .iter()
+        .map(|(_, count)| count)
+        .sum();
+    assert_eq!(recorded, (click_batches * CLICK_BATCH) as i64);
+}
+
+async fn create(db: &dyn Storage, custom_code: String) -> AppResult<(String, String)> {
+    let request = CreateUrlRequest {
+        url: "https://example.com".to_string(),
+        custom_code: Some(custom_code),
+        expires_at: None,
+        tags: vec!["stress".to_string()],
+        campaign: None,
+        owner: None,
+    };
+    let new_url = shorten::prepare_link(db, request, 6).await?;
+    let url = shorten::create_link(db, &new_url).await?;
+
+    Ok((url.short_code, url.id))
+}
+
+fn click(short_code: &str, url_id: &str, n: usize) -> NewClick {
+    NewClick {
+        url_id: url_id.to_string(),
+        short_code: short_code.to_string(),
+        clicked_at: Utc::now(),
+        ip_address: Some(format!("10.0.0.{}", n)),
+        user_agent: Some("sqlite_concurrency".to_string()),
+        referer: None,
+        referer_domain: None,
+        channel: "direct",
+        device: "desktop",
+    }
+}
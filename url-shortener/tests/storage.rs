@@ -0,0 +1,553 @@
+//! Behaviour every storage backend has to share. The suite runs against
+//! `MemoryStore`, SQLite on a temporary file, and Postgres when
+//! `TEST_DATABASE_URL` points at a database it may write to. Every run uses fresh codes and
+//! owners, so a Postgres database can be reused between runs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use rand::{Rng, distr::Alphanumeric};
+use url_shortener::config::DatabaseConfig;
+use url_shortener::db::{MemoryStore, PostgresStore, SqliteStore, Storage};
+use url_shortener::error::AppError;
+use url_shortener::models::{
+    ClickStats, DateRange, LinkFilter, NewClick, NewUrl, PendingDelivery, WebhookDelivery,
+    WebhookEvent,
+};
+use url_shortener::services::webhooks::ClickOutbox;
+
+const LEASE: Duration = Duration::from_secs(60);
+
+#[tokio::test]
+async fn memory_store() {
+    suite(Arc::new(MemoryStore::new())).await;
+}
+
+#[tokio::test]
+async fn sqlite_store() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = DatabaseConfig {
+        url: format!("sqlite:{}", dir.path().join("storage.db").display()),
+        ..DatabaseConfig::default()
+    };
+
+    let store = SqliteStore::connect(&config).await.unwrap();
+    store.migrate().await.unwrap();
+
+    suite(Arc::new(store)).await;
+}
+
+#[tokio::test]
+async fn postgres_store() {
+    let Ok(url) = std::env::var("TEST_DATABASE_URL") else {
+        eprintln!("TEST_DATABASE_URL is not set, skipping the Postgres suite");
+        return;
+    };
+    let config = DatabaseConfig {
+        url,
+        ..DatabaseConfig::default()
+    };
+
+    let store = PostgresStore::connect(&config).await.unwrap();
+    store.migrate().await.unwrap();
+
+    suite(Arc::new(store)).await;
+}
+
+async fn suite(db: Arc<dyn Storage>) {
+    links(db.as_ref()).await;
+    expiry(db.as_ref()).await;
+    clicks(db.as_ref()).await;
+    aggregate_stats(db.as_ref()).await;
+    click_webhooks(db.as_ref()).await;
+    delivery_claims(db.as_ref()).await;
+    rollups(db.as_ref()).await;
+}
+
+async fn links(db: &dyn Storage) {
+    let code = unique("l");
+    let created = db
+        .create_url(&NewUrl {
+            tags: vec!["b".to_string(), "a".to_string()],
+            ..new_url(&code, None)
+        })
+        .await
+        .unwrap();
+
+    let found = db.get_url_by_code(&code).await.unwrap();
+    assert_eq!(found.id, created.id);
+    assert_eq!(found.original_url, "https://example.com/storage");
+    assert_eq!(found.click_count, 0);
+    assert!(db.code_exists(&code).await.unwrap());
+
+    let mut tags = db.get_url_tags(&created.id).await.unwrap();
+    tags.sort();
+    assert_eq!(tags, ["a", "b"]);
+
+    assert!(matches!(
+        db.create_url(&new_url(&code, None)).await,
+        Err(AppError::CodeAlreadyExists)
+    ));
+
+    // one conflict rolls back the whole batch
+    let fresh = unique("l");
+    let batch = [new_url(&fresh, None), new_url(&code, None)];
+    assert!(matches!(
+        db.create_urls(&batch).await,
+        Err(AppError::CodeAlreadyExists)
+    ));
+    assert!(!db.code_exists(&fresh).await.unwrap());
+
+    db.delete_url_by_code(&code).await.unwrap();
+    assert!(matches!(
+        db.get_url_by_code(&code).await,
+        Err(AppError::UrLNotFound)
+    ));
+    assert!(matches!(
+        db.delete_url_by_code(&code).await,
+        Err(AppError::UrLNotFound)
+    ));
+}
+
+async fn expiry(db: &dyn Storage) {
+    let expired = db
+        .create_url(&NewUrl {
+            expires_at: Some(Utc::now() - chrono::Duration::hours(1)),
+            ..new_url(&unique("e"), None)
+        })
+        .await
+        .unwrap();
+    let live = db
+        .create_url(&NewUrl {
+            expires_at: Some(Utc::now() + chrono::Duration::hours(1)),
+            ..new_url(&unique("e"), None)
+        })
+        .await
+        .unwrap();
+
+    let taken = db.take_newly_expired_urls(Utc::now()).await.unwrap();
+    assert!(taken.iter().any(|url| url.id == expired.id));
+    assert!(!taken.iter().any(|url| url.id == live.id));
+
+    // announced once
+    let taken = db.take_newly_expired_urls(Utc::now()).await.unwrap();
+    assert!(!taken.iter().any(|url| url.id == expired.id));
+}
+
+async fn clicks(db: &dyn Storage) {
+    let code = unique("c");
+    let url = db.create_url(&new_url(&code, None)).await.unwrap();
+
+    let batch = [
+        click(&url.id, &code, "192.0.2.1"),
+        click(&url.id, &code, "192.0.2.1"),
+        click(&url.id, &code, "192.0.2.2"),
+    ];
+    let recorded = db
+        .record_clicks(&batch, &ClickOutbox::default())
+        .await
+        .unwrap();
+    assert_eq!(recorded.len(), 3);
+    assert_eq!(db.get_url_by_code(&code).await.unwrap().click_count, 3);
+
+    let stats = db
+        .get_url_stats(url.id.clone(), &DateRange::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.total_clicks, 3);
+    assert_eq!(stats.unique_ips, 2);
+    assert_eq!(
+        stats
+            .clicks_by_date
+            .iter()
+            .map(|day| day.count)
+            .sum::<i64>(),
+        3
+    );
+
+    // a click on a missing link fails the batch it is in
+    let batch = [
+        click(&url.id, &code, "192.0.2.3"),
+        click("missing", "missing", "192.0.2.3"),
+    ];
+    assert!(
+        db.record_clicks(&batch, &ClickOutbox::default())
+            .await
+            .is_err()
+    );
+    assert_eq!(db.get_url_by_code(&code).await.unwrap().click_count, 3);
+}
+
+async fn aggregate_stats(db: &dyn Storage) {
+    let owner = unique("o");
+    for clicks in [1, 2] {
+        let code = unique("a");
+        let url = db.create_url(&new_url(&code, Some(&owner))).await.unwrap();
+        let batch: Vec<NewClick> = (0..clicks)
+            .map(|_| click(&url.id, &code, "192.0.2.10"))
+            .collect();
+        db.record_clicks(&batch, &ClickOutbox::default())
+            .await
+            .unwrap();
+    }
+    db.create_url(&new_url(&unique("a"), Some(&unique("o"))))
+        .await
+        .unwrap();
+
+    let filter = LinkFilter {
+        owner: Some(owner),
+        ..LinkFilter::default()
+    };
+    let stats = db
+        .get_aggregate_stats(&filter, &DateRange::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.total_links, 2);
+    assert_eq!(stats.stats.total_clicks, 3);
+    assert_eq!(stats.top_links.len(), 2);
+}
+
+async fn click_webhooks(db: &dyn Storage) {
+    let owner = unique("o");
+    let code = unique("w");
+    let url = db.create_url(&new_url(&code, Some(&owner))).await.unwrap();
+
+    let webhook = db
+        .create_webhook(
+            Some(&owner),
+            "https://example.com/hook",
+            "0123456789abcdef",
+            &[WebhookEvent::LinkClicked, WebhookEvent::ClickThreshold],
+            Some(2),
+        )
+        .await
+        .unwrap();
+    let other = db
+        .create_webhook(
+            Some(&unique("o")),
+            "https://example.com/hook",
+            "0123456789abcdef",
+            &[WebhookEvent::LinkClicked],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let subscribed = db
+        .subscribed_webhooks(&url.id, &[WebhookEvent::LinkClicked])
+        .await
+        .unwrap();
+    assert!(subscribed.iter().any(|hook| hook.id == webhook.id));
+    assert!(!subscribed.iter().any(|hook| hook.id == other.id));
+    assert!(
+        db.subscribed_webhooks(&url.id, &[WebhookEvent::LinkExpired])
+            .await
+            .unwrap()
+            .iter()
+            .all(|hook| hook.id != webhook.id)
+    );
+
+    // two clicks take the counter from 0 to the threshold of 2
+    let batch = [
+        click(&url.id, &code, "192.0.2.20"),
+        click(&url.id, &code, "192.0.2.21"),
+    ];
+    let outbox = ClickOutbox::for_clicks(db, &batch).await.unwrap();
+    db.record_clicks(&batch, &outbox).await.unwrap();
+
+    let deliveries = db.list_deliveries(&webhook.id, None, 50).await.unwrap();
+    let count = |event: WebhookEvent| {
+        deliveries
+            .iter()
+            .filter(|delivery| delivery.event == event.as_str())
+            .count()
+    };
+    assert_eq!(count(WebhookEvent::LinkClicked), 2);
+    assert_eq!(count(WebhookEvent::ClickThreshold), 1);
+    assert!(
+        db.list_deliveries(&other.id, None, 50)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    // past the threshold, only clicks are delivered
+    let batch = [click(&url.id, &code, "192.0.2.22")];
+    let outbox = ClickOutbox::for_clicks(db, &batch).await.unwrap();
+    db.record_clicks(&batch, &outbox).await.unwrap();
+    let deliveries = db.list_deliveries(&webhook.id, None, 50).await.unwrap();
+    assert_eq!(deliveries.len(), 4);
+}
+
+async fn delivery_claims(db: &dyn Storage) {
+    let owner = unique("o");
+    let code = unique("d");
+    let url = db.create_url(&new_url(&code, Some(&owner))).await.unwrap();
+    let webhook = db
+        .create_webhook(
+            Some(&owner),
+            "https://example.com/hook",
+            "0123456789abcdef",
+            &[WebhookEvent::LinkClicked],
+            None,
+        )
+        .await
+        .unwrap();
+
+    let batch: Vec<NewClick> = (0..3)
+        .map(|_| click(&url.id, &code, "192.0.2.30"))
+        .collect();
+    let outbox = ClickOutbox::for_clicks(db, &batch).await.unwrap();
+    db.record_clicks(&batch, &outbox).await.unwrap();
+
+    let ours: Vec<String> = db
+        .list_deliveries(&webhook.id, None, 50)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|delivery| delivery.id)
+        .collect();
+    assert_eq!(ours.len(), 3);
+    let claimed = |claim: &[PendingDelivery]| -> Vec<String> {
+        let mut ids: Vec<String> = claim
+            .iter()
+            .filter(|delivery| ours.contains(&delivery.id))
+            .map(|delivery| delivery.id.clone())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    let first = db.due_deliveries(1000, LEASE).await.unwrap();
+    assert_eq!(claimed(&first).len(), 3);
+    let first = first
+        .into_iter()
+        .find(|delivery| ours.contains(&delivery.id))
+        .unwrap();
+    assert_eq!(first.url, "https://example.com/hook");
+    assert_eq!(first.secret, "0123456789abcdef");
+
+    // claimed rows are not handed out twice
+    assert!(
+        claimed(&db.due_deliveries(1000, LEASE).await.unwrap()).is_empty(),
+        "a claimed delivery was claimed again"
+    );
+    assert_eq!(
+        db.list_deliveries(&webhook.id, Some("sending"), 50)
+            .await
+            .unwrap()
+            .len(),
+        3
+    );
+
+    let (delivered, retried, failed) = (&ours[0], &ours[1], &ours[2]);
+    db.mark_delivered(delivered, 200).await.unwrap();
+    db.mark_attempt_failed(
+        retried,
+        Some(500),
+        "endpoint responded 500",
+        Some(Utc::now() - chrono::Duration::seconds(1)),
+    )
+    .await
+    .unwrap();
+    db.mark_attempt_failed(failed, None, "connection refused", None)
+        .await
+        .unwrap();
+
+    // only the retry is due, a claim whose lease ran out is due again
+    let again = db.due_deliveries(1000, Duration::ZERO).await.unwrap();
+    assert_eq!(claimed(&again), [retried.as_str()]);
+    assert_eq!(
+        again
+            .iter()
+            .find(|delivery| delivery.id == *retried)
+            .unwrap()
+            .attempts,
+        1
+    );
+    let expired = db.due_deliveries(1000, LEASE).await.unwrap();
+    assert_eq!(claimed(&expired), [retried.as_str()]);
+
+    let row = delivery(db, &webhook.id, delivered).await;
+    assert_eq!(row.status, "delivered");
+    assert_eq!(row.last_status_code, Some(200));
+    assert!(row.delivered_at.is_some());
+    assert!(row.locked_until.is_none());
+    let row = delivery(db, &webhook.id, failed).await;
+    assert_eq!(row.status, "failed");
+    assert_eq!(row.last_error.as_deref(), Some("connection refused"));
+    assert_eq!(delivery(db, &webhook.id, retried).await.status, "sending");
+}
+
+async fn delivery(db: &dyn Storage, webhook_id: &str, id: &str) -> WebhookDelivery {
+    db.list_deliveries(webhook_id, None, 50)
+        .await
+        .unwrap()
+        .into_iter()
+        .find(|delivery| delivery.id == id)
+        .unwrap()
+}
+
+/// Stats of past days come from the daily rollups and have to match what
+/// the raw clicks add up to, before and after those clicks are purged.
+async fn rollups(db: &dyn Storage) {
+    let code = unique("r");
+    let url = db.create_url(&new_url(&code, None)).await.unwrap();
+
+    // (days ago, ip, channel, device, referer domain)
+    let seeds = [
+        (6, "192.0.2.1", "direct", "desktop", None),
+        (6, "192.0.2.1", "search", "mobile", Some("google.com")),
+        (6, "192.0.2.2", "search", "mobile", Some("google.com")),
+        (4, "192.0.2.1", "social", "tablet", Some("twitter.com")),
+        (2, "192.0.2.3", "referral", "desktop", Some("example.org")),
+        (2, "192.0.2.3", "direct", "desktop", None),
+        (1, "192.0.2.4", "email", "mobile", None),
+        (0, "192.0.2.1", "direct", "desktop", None),
+        (0, "192.0.2.5", "search", "desktop", Some("google.com")),
+    ];
+    let now = Utc::now();
+    let batch: Vec<NewClick> = seeds
+        .iter()
+        .map(|&(days_ago, ip, channel, device, domain)| NewClick {
+            clicked_at: now - chrono::Duration::days(days_ago),
+            channel,
+            device,
+            referer_domain: domain.map(str::to_string),
+            referer: domain.map(|domain| format!("https://{}/", domain)),
+            ..click(&url.id, &code, ip)
+        })
+        .collect();
+    db.record_clicks(&batch, &ClickOutbox::default())
+        .await
+        .unwrap();
+
+    // what the raw clicks add up to
+    let mut by_date: BTreeMap<NaiveDate, i64> = BTreeMap::new();
+    let mut ips_by_date: BTreeMap<NaiveDate, BTreeSet<&str>> = BTreeMap::new();
+    let mut channels: BTreeMap<String, i64> = BTreeMap::new();
+    let mut devices: BTreeMap<String, i64> = BTreeMap::new();
+    let mut domains: BTreeMap<String, i64> = BTreeMap::new();
+    for (click, &(_, ip, ..)) in batch.iter().zip(&seeds) {
+        let day = click.clicked_at.date_naive();
+        *by_date.entry(day).or_default() += 1;
+        ips_by_date.entry(day).or_default().insert(ip);
+        *channels.entry(click.channel.to_string()).or_default() += 1;
+        *devices.entry(click.device.to_string()).or_default() += 1;
+        if let Some(domain) = &click.referer_domain {
+            *domains.entry(domain.clone()).or_default() += 1;
+        }
+    }
+    // visitors are distinct across the days that still have their ips, a
+    // purged day only keeps its own unique count
+    let cutoff = (now - chrono::Duration::days(3)).date_naive();
+    let unique_ips = |purged_before: NaiveDate| -> i64 {
+        let kept: BTreeSet<&str> = ips_by_date
+            .range(purged_before..)
+            .flat_map(|(_, ips)| ips.iter().copied())
+            .collect();
+        let purged: usize = ips_by_date
+            .range(..purged_before)
+            .map(|(_, ips)| ips.len())
+            .sum();
+        (kept.len() + purged) as i64
+    };
+
+    let check = |stats: ClickStats, unique_ips: i64, when: &str| {
+        assert_eq!(stats.total_clicks, seeds.len() as i64, "{}", when);
+        assert_eq!(stats.unique_ips, unique_ips, "{}", when);
+        let stats_by_date: BTreeMap<NaiveDate, i64> = stats
+            .clicks_by_date
+            .iter()
+            .map(|day| (day.date, day.count))
+            .collect();
+        assert_eq!(stats_by_date, by_date, "{}", when);
+        let stats_channels: BTreeMap<String, i64> = stats
+            .channels
+            .into_iter()
+            .map(|count| (count.channel, count.count))
+            .collect();
+        assert_eq!(stats_channels, channels, "{}", when);
+        let stats_devices: BTreeMap<String, i64> = stats
+            .top_devices
+            .into_iter()
+            .map(|count| (count.device, count.count))
+            .collect();
+        assert_eq!(stats_devices, devices, "{}", when);
+        let stats_domains: BTreeMap<String, i64> = stats
+            .top_referer_domains
+            .into_iter()
+            .map(|count| (count.domain, count.count))
+            .collect();
+        assert_eq!(stats_domains, domains, "{}", when);
+    };
+
+    let stats = db
+        .get_url_stats(url.id.clone(), &DateRange::default())
+        .await
+        .unwrap();
+    check(stats, unique_ips(NaiveDate::MIN), "before the purge");
+    assert!(db.verify_rollups().await.unwrap().consistent);
+
+    // the raw clicks of the two oldest days go
+    let purged = db.purge_clicks_before(cutoff).await.unwrap();
+    assert!(purged >= 4, "purged {}", purged);
+
+    let stats = db
+        .get_url_stats(url.id.clone(), &DateRange::default())
+        .await
+        .unwrap();
+    check(stats, unique_ips(cutoff), "after the purge");
+
+    // a range inside the purged days is served from the rollups alone
+    let day = (now - chrono::Duration::days(6)).date_naive();
+    let stats = db
+        .get_url_stats(
+            url.id.clone(),
+            &DateRange {
+                from: Some(day),
+                to: Some(day),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(stats.total_clicks, 3);
+    assert_eq!(stats.unique_ips, 2);
+}
+
+fn new_url(code: &str, owner: Option<&str>) -> NewUrl {
+    NewUrl {
+        original_url: "https://example.com/storage".to_string(),
+        short_code: code.to_string(),
+        expires_at: None,
+        created_at: None,
+        campaign: None,
+        owner: owner.map(str::to_string),
+        tags: Vec::new(),
+    }
+}
+
+fn click(url_id: &str, code: &str, ip: &str) -> NewClick {
+    NewClick {
+        url_id: url_id.to_string(),
+        short_code: code.to_string(),
+        clicked_at: Utc::now(),
+        ip_address: Some(ip.to_string()),
+        user_agent: Some("storage-test".to_string()),
+        referer: None,
+        referer_domain: None,
+        channel: "direct",
+        device: "desktop",
+    }
+}
+
+fn unique(prefix: &str) -> String {
+    let suffix: String = rand::rng()
+        .sample_iter(Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect();
+    format!("{}{}", prefix, suffix)
+}
@@ -0,0 +1,281 @@
+//! Webhook deliveries to a real receiver listening on 127.0.0.1: signed
+//! requests, backoff after failures and the final delivered state.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+};
+use chrono::Utc;
+use sqlx::SqlitePool;
+use tempfile::TempDir;
+use url_shortener::{
+    config::DatabaseConfig,
+    db::{SqliteStore, Storage},
+    models::{NewUrl, Url, WebhookDelivery, WebhookEvent},
+    services::webhooks::{
+        self, EVENT_HEADER, SIGNATURE_HEADER, TIMESTAMP_HEADER, WebhookDispatcher,
+    },
+};
+
+const SECRET: &str = "receiver-test-secret";
+
+/// Answers with the queued statuses in order, 200 once they run out, and
+/// keeps every request it got.
+#[derive(Default)]
+struct Receiver {
+    statuses: Mutex<VecDeque<StatusCode>>,
+    received: Mutex<Vec<(HeaderMap, String)>>,
+}
+
+async fn receive(
+    State(receiver): State<Arc<Receiver>>,
+    headers: HeaderMap,
+    body: String,
+) -> StatusCode {
+    receiver.received.lock().unwrap().push((headers, body));
+    receiver
+        .statuses
+        .lock()
+        .unwrap()
+        .pop_front()
+        .unwrap_or(StatusCode::OK)
+}
+
+/// Starts a receiver on an ephemeral port and returns its hook URL.
+async fn start_receiver(statuses: &[StatusCode]) -> (Arc<Receiver>, String) {
+    let receiver = Arc::new(Receiver::default());
+    receiver
+        .statuses
+        .lock()
+        .unwrap()
+        .extend(statuses.iter().copied());
+
+    let app = Router::new()
+        .route("/hook", post(receive))
+        .with_state(receiver.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    (receiver, format!("http://{}/hook", address))
+}
+
+struct Setup {
+    db: Arc<dyn Storage>,
+    // to move due dates instead of waiting for them
+    pool: SqlitePool,
+    url: Url,
+    webhook_id: String,
+    _dir: TempDir,
+}
+
+/// A SQLite store with one link, a webhook for its `link.created` events
+/// pointing at `hook_url`, and that event queued.
+async fn setup(hook_url: &str) -> Setup {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("webhooks.db");
+    let config = DatabaseConfig {
+        url: format!("sqlite:{}", path.display()),
+        ..DatabaseConfig::default()
+    };
+    let db: Arc<dyn Storage> = Arc::new(SqliteStore::connect(&config).await.unwrap());
+    db.migrate().await.unwrap();
+    let pool = SqlitePool::connect(&format!("sqlite:{}", path.display()))
+        .await
+        .unwrap();
+
+    let webhook = db
+        .create_webhook(
+            Some("alice"),
+            hook_url,
+            SECRET,
+            &[WebhookEvent::LinkCreated],
+            None,
+        )
+        .await
+        .unwrap();
+    let url = db
+        .create_url(&NewUrl {
+            original_url: "https://example.com/hooked".to_string(),
+            short_code: "hooked".to_string(),
+            expires_at: None,
+            created_at: None,
+            campaign: None,
+            owner: Some("alice".to_string()),
+            tags: vec!["docs".to_string()],
+        })
+        .await
+        .unwrap();
+    webhooks::link_created(db.as_ref(), &url, &["docs".to_string()])
+        .await
+        .unwrap();
+
+    Setup {
+        db,
+        pool,
+        url,
+        webhook_id: webhook.id,
+        _dir: dir,
+    }
+}
+
+impl Setup {
+    fn dispatcher(&self, max_attempts: u32, allow_private_targets: bool) -> WebhookDispatcher {
+        WebhookDispatcher::new(
+            self.db.clone(),
+            Duration::from_secs(5),
+            max_attempts,
+            allow_private_targets,
+        )
+        .unwrap()
+    }
+
+    async fn delivery(&self) -> WebhookDelivery {
+        let mut deliveries = self
+            .db
+            .list_deliveries(&self.webhook_id, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(deliveries.len(), 1);
+        deliveries.remove(0)
+    }
+
+    /// Makes a scheduled retry due now.
+    async fn rewind(&self) {
+        sqlx::query("UPDATE webhook_deliveries SET next_attempt_at = datetime('now')")
+            .execute(&self.pool)
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn signed_delivery_verifies() {
+    let (receiver, hook_url) = start_receiver(&[]).await;
+    let setup = setup(&hook_url).await;
+
+    setup.dispatcher(3, true).deliver_due().await.unwrap();
+
+    let received = receiver.received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let (headers, body) = &received[0];
+
+    let header = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+    assert_eq!(header(EVENT_HEADER), "link.created");
+    let timestamp: i64 = header(TIMESTAMP_HEADER).parse().unwrap();
+    assert!((Utc::now().timestamp() - timestamp).abs() < 60);
+    assert_eq!(
+        header(SIGNATURE_HEADER),
+        webhooks::sign(SECRET, timestamp, body)
+    );
+    assert_ne!(
+        header(SIGNATURE_HEADER),
+        webhooks::sign("another-secret-entirely", timestamp, body)
+    );
+
+    let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(payload["event"], "link.created");
+    assert!(payload["id"].is_string());
+    assert_eq!(payload["data"]["short_code"], setup.url.short_code);
+    assert_eq!(payload["data"]["tags"], serde_json::json!(["docs"]));
+}
+
+#[tokio::test]
+async fn failures_back_off_until_delivered() {
+    let (receiver, hook_url) = start_receiver(&[
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::INTERNAL_SERVER_ERROR,
+    ])
+    .await;
+    let setup = setup(&hook_url).await;
+    let dispatcher = setup.dispatcher(5, true);
+
+    // 500, retried 10 seconds later
+    dispatcher.deliver_due().await.unwrap();
+    let delivery = setup.delivery().await;
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.attempts, 1);
+    assert_eq!(delivery.last_status_code, Some(500));
+    assert!(delivery.locked_until.is_none());
+    let delay = (delivery.next_attempt_at - Utc::now()).num_seconds();
+    assert!((8..=10).contains(&delay), "retry in {}s", delay);
+
+    // not due yet, nothing is sent
+    dispatcher.deliver_due().await.unwrap();
+    assert_eq!(receiver.received.lock().unwrap().len(), 1);
+
+    // 500 again, the delay doubles
+    setup.rewind().await;
+    dispatcher.deliver_due().await.unwrap();
+    let delivery = setup.delivery().await;
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.attempts, 2);
+    let delay = (delivery.next_attempt_at - Utc::now()).num_seconds();
+    assert!((18..=20).contains(&delay), "retry in {}s", delay);
+
+    // 200
+    setup.rewind().await;
+    dispatcher.deliver_due().await.unwrap();
+    let delivery = setup.delivery().await;
+    assert_eq!(delivery.status, "delivered");
+    assert_eq!(delivery.attempts, 3);
+    assert_eq!(delivery.last_status_code, Some(200));
+    assert!(delivery.last_error.is_none());
+    assert!(delivery.delivered_at.is_some());
+
+    // every attempt carried the same event
+    {
+        let received = receiver.received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|(_, body)| *body == received[0].1));
+    }
+
+    setup.rewind().await;
+    dispatcher.deliver_due().await.unwrap();
+    assert_eq!(receiver.received.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let (receiver, hook_url) = start_receiver(&[StatusCode::BAD_GATEWAY; 5]).await;
+    let setup = setup(&hook_url).await;
+    let dispatcher = setup.dispatcher(2, true);
+
+    dispatcher.deliver_due().await.unwrap();
+    setup.rewind().await;
+    dispatcher.deliver_due().await.unwrap();
+
+    let delivery = setup.delivery().await;
+    assert_eq!(delivery.status, "failed");
+    assert_eq!(delivery.attempts, 2);
+    assert_eq!(delivery.last_status_code, Some(502));
+
+    setup.rewind().await;
+    dispatcher.deliver_due().await.unwrap();
+    assert_eq!(receiver.received.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn private_targets_are_not_contacted() {
+    let (receiver, hook_url) = start_receiver(&[]).await;
+    let setup = setup(&hook_url).await;
+
+    setup.dispatcher(3, false).deliver_due().await.unwrap();
+
+    assert!(receiver.received.lock().unwrap().is_empty());
+    let delivery = setup.delivery().await;
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.last_status_code, None);
+    assert!(
+        delivery
+            .last_error
+            .unwrap()
+            .contains("must point to a public address")
+    );
+}